@@ -3,12 +3,21 @@
 //! This application creates a new device.json file. The file is written to the `/opt/sifis-home/`
 //! path by default, but the location can be changed with the `SIFIS_HOME_PATH` environment
 //! variable or with the -o option.
+//!
+//! The `info` subcommand prints a summary of an existing device.json file, the `rotate-uuid`
+//! subcommand gives an existing device.json file a new UUID, and the `verify` subcommand checks an
+//! existing device.json file against this version.
+//!
+//! A product name that is the same as a subcommand, like `info`, is read as the subcommand. Give
+//! such names after `--`, for example `create_device_info -- info`.
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use mobile_api::configs::{DeviceInfo, QrPayloadFormat};
+use mobile_api::security::SRNG;
 use mobile_api::SifisHome;
 use qrcodegen::{QrCode, QrCodeEcc, QrSegment};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
 /// Command line arguments for the application
@@ -19,11 +28,22 @@ use std::process::ExitCode;
 #[command(
     long_about = "This application creates a new device.json file. The file is written to
 the `/opt/sifis-home/` path by default, but the location can be changed
-with the `SIFIS_HOME_PATH` environment variable or with the -o option."
+with the `SIFIS_HOME_PATH` environment variable or with the -o option.
+
+A product name that is the same as a subcommand, like `info`, must be
+given after `--`, for example `create_device_info -- info`."
 )]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Arguments {
+    /// Commands for existing device.json files
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Product name for the SIFIS-Home Smart Device
-    product_name: String,
+    ///
+    /// Names of the subcommands must be given after `--`, like `-- info`.
+    #[arg(required = true)]
+    product_name: Option<String>,
 
     /// Sets a custom output path
     #[arg(short, long, value_name = "PATH")]
@@ -40,12 +60,73 @@ struct Arguments {
     /// Write authorization key to QR code as SVG image
     #[arg(short, long, value_name = "FILE")]
     save_qr_code_svg: Option<PathBuf>,
+
+    /// Payload of the QR code: hex, uri, or base64
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        default_value_t = QrPayloadFormat::Hex,
+        requires = "save_qr_code_svg"
+    )]
+    qr_format: QrPayloadFormat,
+}
+
+/// Subcommands for the application
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print a summary of an existing device.json file
+    ///
+    /// The authorization key is redacted unless the --show-key option is given.
+    Info {
+        /// Path to the device.json file
+        file: PathBuf,
+
+        /// Print the summary as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Show the full authorization key
+        #[arg(long)]
+        show_key: bool,
+    },
+
+    /// Replace the UUID in an existing device.json file
+    ///
+    /// A new UUIDv7 is generated, and the other device information is kept.
+    RotateUuid {
+        /// Path to the device.json file
+        file: PathBuf,
+    },
+
+    /// Check an existing device.json file against this version
+    ///
+    /// Issues that do not stop the file from loading are printed as warnings.
+    Verify {
+        /// Path to the device.json file
+        file: PathBuf,
+    },
 }
 
 fn main() -> ExitCode {
     // Parse command line arguments
     let arguments = Arguments::parse();
 
+    // Run subcommand if one was given
+    if let Some(command) = arguments.command {
+        return match command {
+            Command::Info {
+                file,
+                json,
+                show_key,
+            } => print_info(&file, json, show_key),
+            Command::RotateUuid { file } => rotate_uuid(&file),
+            Command::Verify { file } => verify(&file),
+        };
+    }
+    let product_name = arguments
+        .product_name
+        .expect("Product name is required without a subcommand");
+
     // Load .env if available
     if dotenvy::dotenv().is_ok() {
         println!("Loaded environment variables from .env file");
@@ -84,7 +165,7 @@ fn main() -> ExitCode {
 
     // Create device info and update the private key path if it was given
     let mut device_info = sifis_home
-        .new_info(arguments.product_name)
+        .new_info(product_name)
         .expect("Could not create a new device info");
     if let Some(private_key) = arguments.private_key {
         device_info.set_private_key_file(private_key);
@@ -102,8 +183,9 @@ fn main() -> ExitCode {
 
     // Create Qr Code image?
     if let Some(svg_file) = arguments.save_qr_code_svg {
-        // We store authorization key as hex string to the Qr Code
-        let segments = QrSegment::make_segments(&device_info.authorization_key().hex(true));
+        // The authorization key is stored in the chosen format, uppercase hex by default
+        let payload = device_info.qr_payload_format(arguments.qr_format);
+        let segments = QrSegment::make_segments(&payload);
         let qr_code = match QrCode::encode_segments(&segments, QrCodeEcc::Quartile) {
             Ok(code) => code,
             Err(err) => {
@@ -124,6 +206,140 @@ fn main() -> ExitCode {
     ExitCode::SUCCESS
 }
 
+/// Prints a summary of the device information *file*
+///
+/// The authorization key is only printed when *show_key* is set. Otherwise, only the key
+/// fingerprint is shown.
+fn print_info(file: &Path, json: bool, show_key: bool) -> ExitCode {
+    let device_info = match DeviceInfo::load_from(file) {
+        Ok(device_info) => device_info,
+        Err(err) => {
+            eprintln!("Could not load device information: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let key = device_info.authorization_key();
+    let created_at = device_info.created_at_unix_ms();
+
+    if json {
+        let mut summary = serde_json::json!({
+            "product_name": device_info.product_name(),
+            "uuid": device_info.uuid(),
+            "key_fingerprint": key.fingerprint(),
+            "private_key_file": device_info.private_key_file(),
+            "created_at": created_at,
+        });
+        if show_key {
+            summary["authorization_key"] = serde_json::Value::from(key.hex(false));
+        }
+        match serde_json::to_string_pretty(&summary) {
+            Ok(summary) => println!("{}", summary),
+            Err(err) => {
+                eprintln!("Could not format device information: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        println!("Product name:      {}", device_info.product_name());
+        println!("UUID:              {}", device_info.uuid());
+        if show_key {
+            println!("Authorization key: {}", key.hex(false));
+        } else {
+            println!("Authorization key: <redacted, use --show-key to reveal>");
+        }
+        println!("Key fingerprint:   {}", key.fingerprint());
+        println!("Private key file:  {:?}", device_info.private_key_file());
+        match created_at {
+            Some(created_at) => println!("Created at:        {}", format_unix_ms(created_at)),
+            None => println!("Created at:        unknown"),
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// Replaces the UUID in the device information *file*
+fn rotate_uuid(file: &Path) -> ExitCode {
+    let mut device_info = match DeviceInfo::load_from(file) {
+        Ok(device_info) => device_info,
+        Err(err) => {
+            eprintln!("Could not load device information: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let old_uuid = *device_info.uuid();
+    if let Err(err) = device_info.regenerate_uuid(&SRNG::new()) {
+        eprintln!("Could not generate a new UUID: {}", err);
+        return ExitCode::FAILURE;
+    }
+    if let Err(err) = device_info.save_to(file) {
+        eprintln!("Could not write device information: {}", err);
+        return ExitCode::FAILURE;
+    }
+    println!("Old UUID: {}", old_uuid);
+    println!("New UUID: {}", device_info.uuid());
+    ExitCode::SUCCESS
+}
+
+/// Checks the device information *file* with [DeviceInfo::validate_schema]
+///
+/// Warnings do not make the command fail, only a file that can not be loaded does.
+fn verify(file: &Path) -> ExitCode {
+    let json = match fs::read_to_string(file) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("Could not read device information: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let warnings = match DeviceInfo::validate_schema(&json) {
+        Ok(warnings) => warnings,
+        Err(err) => {
+            eprintln!("Device information is not valid: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    if warnings.is_empty() {
+        println!("The device information is up to date.");
+    } else {
+        for warning in &warnings {
+            println!("Warning: {}", warning);
+        }
+        println!(
+            "The device information loads with {} warning(s).",
+            warnings.len()
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+/// Formats milliseconds since the UNIX_EPOCH as UTC date and time
+fn format_unix_ms(unix_ms: u64) -> String {
+    let seconds = unix_ms / 1000;
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // Converting days to the civil date, see: http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
 /// Returns a string of SVG code for an image depicting
 /// the given QR Code, with the given number of border modules.
 /// The string always uses Unix newlines (\n), regardless of the platform.
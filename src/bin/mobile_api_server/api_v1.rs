@@ -1,25 +1,141 @@
 //! Smart Device Mobile API v1
 
-use rocket_okapi::openapi_get_routes;
+use crate::api_common::set_api_key_header_in_spec;
+use rocket_okapi::okapi::openapi3::OpenApi;
+use rocket_okapi::openapi_get_routes_spec;
 
+pub mod capabilities;
+#[cfg(feature = "commands")]
 pub mod commands;
 pub mod device;
+pub mod health;
+pub mod profiles;
+pub mod provision;
+pub mod signature;
 
 #[cfg(test)]
 pub mod tests_common;
 
 /// Routes for the API v1
 ///
-/// Routes are run through [openapi_get_routes!] to generate OpenAPI specifications from
-/// implementations.
-pub fn routes() -> Vec<rocket::Route> {
-    openapi_get_routes![
+/// Routes are run through [openapi_get_routes_spec!] to generate OpenAPI specifications from
+/// implementations. The specification documents the *api_key_header* as the API key header, and
+/// it is served from the `openapi.json` route. Its signature is served from the `openapi.sig`
+/// route.
+pub fn routes(api_key_header: &str) -> Vec<rocket::Route> {
+    let (mut routes, spec) = routes_and_spec(api_key_header);
+    routes.extend(signature::openapi_routes(spec));
+    routes
+}
+
+/// OpenAPI specification for the API v1
+///
+/// This is the same document that is served from the `openapi.json` route.
+pub fn openapi_spec(api_key_header: &str) -> OpenApi {
+    routes_and_spec(api_key_header).1
+}
+
+/// Routes and the OpenAPI specification generated from them
+///
+/// The command endpoints are included only when the `commands` feature is enabled.
+fn routes_and_spec(api_key_header: &str) -> (Vec<rocket::Route>, OpenApi) {
+    #[allow(unused_mut)]
+    let (mut routes, mut spec) = openapi_get_routes_spec![
         device::info,
         device::status,
+        device::reboot_required,
+        device::boot_count,
+        device::processes,
+        device::public_key,
         device::get_config,
         device::set_config,
-        commands::factory_reset,
-        commands::restart,
-        commands::shutdown,
-    ]
+        device::validate_config,
+        device::rotate_dht_key,
+        profiles::list_profiles,
+        profiles::save_profile,
+        profiles::delete_profile,
+        profiles::activate_profile,
+        provision::provision,
+        health::ready,
+        health::time,
+        capabilities::capabilities,
+    ];
+    #[cfg(feature = "commands")]
+    {
+        let (command_routes, command_spec) = openapi_get_routes_spec![
+            commands::factory_reset,
+            commands::restart,
+            commands::shutdown,
+            commands::run_script_output,
+            commands::prepare_command,
+        ];
+        routes.extend(command_routes);
+        merge_spec(&mut spec, command_spec);
+    }
+    set_api_key_header_in_spec(&mut spec, api_key_header);
+    (routes, spec)
+}
+
+/// Add the paths and the schemas of the *other* specification to the *spec*
+#[cfg(feature = "commands")]
+fn merge_spec(spec: &mut OpenApi, other: OpenApi) {
+    spec.paths.extend(other.paths);
+    if let Some(other_components) = other.components {
+        let components = spec.components.get_or_insert_with(Default::default);
+        components.schemas.extend(other_components.schemas);
+        components.responses.extend(other_components.responses);
+        components
+            .security_schemes
+            .extend(other_components.security_schemes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_v1::tests_common::*;
+    use rocket::http::Status;
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_command_routes() {
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        let spec = openapi_spec(crate::api_common::DEFAULT_API_KEY_HEADER);
+        let uris = [
+            "/command/factory_reset",
+            "/command/restart",
+            "/command/shutdown",
+        ];
+        for uri in uris {
+            assert_eq!(
+                spec.paths.contains_key(uri),
+                cfg!(feature = "commands"),
+                "{uri}"
+            );
+        }
+        assert_eq!(
+            spec.paths.contains_key("/command/{id}/prepare"),
+            cfg!(feature = "commands")
+        );
+        assert!(spec.paths.contains_key("/device/info"));
+
+        // Without the feature, the command endpoints do not exist
+        if !cfg!(feature = "commands") {
+            for uri in uris {
+                let response = client
+                    .post(format!("/v1{uri}"))
+                    .header(api_key_header.clone())
+                    .dispatch();
+                assert_eq!(response.status(), Status::NotFound, "{uri}");
+            }
+            let response = client
+                .post("/v1/command/factory_reset/prepare")
+                .header(api_key_header.clone())
+                .dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+        }
+    }
 }
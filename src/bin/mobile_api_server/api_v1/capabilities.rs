@@ -0,0 +1,170 @@
+//! Endpoint for Listing the Server Capabilities
+//!
+//! The mobile application uses the capabilities to show only the functions that the device
+//! supports, instead of trying the endpoints one by one.
+
+use crate::state::DeviceState;
+use rocket::serde::json::Json;
+use rocket::{get, State};
+use rocket_okapi::openapi;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// API versions served by this server
+pub const API_VERSIONS: [&str; 1] = ["v1"];
+
+/// Features that every server of this version has
+const BUILT_IN_FEATURES: [&str; 9] = [
+    "status_fields",
+    "status_msgpack",
+    "config_validation",
+    "dht_key_rotation",
+    "compression",
+    "boot_count",
+    "processes",
+    "config_profiles",
+    "openapi_signature",
+];
+
+/// Features of the command endpoints, included with the `commands` Cargo feature
+#[cfg(feature = "commands")]
+const COMMAND_FEATURES: [&str; 1] = ["confirmation_tokens"];
+
+/// Command features that need a script, and the name of the script
+#[cfg(feature = "commands")]
+const COMMAND_SCRIPT_FEATURES: [(&str, &str); 3] = [
+    ("factory_reset", "factory_reset.sh"),
+    ("restart", "restart.sh"),
+    ("shutdown", "shutdown.sh"),
+];
+
+/// Features that need a script, and the name of the script
+const SCRIPT_FEATURES: [(&str, &str); 1] = [("apply_timezone", "apply_timezone.sh")];
+
+/// Supported API versions and features
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// API versions, like `v1`
+    #[serde(alias = "api_versions")]
+    pub api_versions: Vec<String>,
+    /// Enabled features
+    ///
+    /// Always included are `status_fields`, `status_msgpack`, `config_validation`,
+    /// `dht_key_rotation`, `compression`, `boot_count`, `processes`, `config_profiles`, and
+    /// `openapi_signature`, and `confirmation_tokens` with the command endpoints. The
+    /// `factory_reset`, `restart`, `shutdown`, and `apply_timezone` features are included when the
+    /// device has the script for them, and `provisioning` when the device is waiting to be
+    /// provisioned. The command features are left out when the server is built without the
+    /// command endpoints.
+    pub features: Vec<String>,
+}
+
+impl Capabilities {
+    /// Capabilities of the server with the *state*
+    pub fn from_state(state: &DeviceState) -> Capabilities {
+        let mut features: Vec<String> = BUILT_IN_FEATURES.into_iter().map(String::from).collect();
+        #[cfg(feature = "commands")]
+        {
+            features.extend(COMMAND_FEATURES.into_iter().map(String::from));
+            for (feature, script) in COMMAND_SCRIPT_FEATURES {
+                if state.has_script(script) {
+                    features.push(feature.to_string());
+                }
+            }
+        }
+        for (feature, script) in SCRIPT_FEATURES {
+            if state.has_script(script) {
+                features.push(feature.to_string());
+            }
+        }
+        if !state.is_provisioned() {
+            features.push("provisioning".to_string());
+        }
+        Capabilities {
+            api_versions: API_VERSIONS.into_iter().map(String::from).collect(),
+            features,
+        }
+    }
+
+    /// Check if the *feature* is enabled
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|enabled| enabled == feature)
+    }
+}
+
+/// # Server capabilities
+///
+/// Returns the supported API versions and the enabled features. The features depend on the
+/// scripts installed on the device and on whether the device is waiting to be provisioned, so the
+/// application should read them again after provisioning.
+///
+/// This endpoint does not require the API key.
+#[openapi(tag = "Health")]
+#[get("/capabilities")]
+pub async fn capabilities(state: &State<DeviceState>) -> Json<Capabilities> {
+    Json(Capabilities::from_state(state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_v1::tests_common::*;
+    use crate::build_rocket;
+    use mobile_api::SifisHome;
+    use rocket::fs::relative;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use tempfile::TempDir;
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_capabilities() {
+        std::env::set_var("MOBILE_API_SCRIPTS_PATH", relative!("tests/scripts/"));
+        let (_test_dir, client) = create_test_setup();
+
+        let response = client.get("/v1/capabilities").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let capabilities = response.into_json::<Capabilities>().unwrap();
+        assert_eq!(capabilities.api_versions, vec!["v1"]);
+        for feature in BUILT_IN_FEATURES {
+            assert!(capabilities.has_feature(feature), "{feature}");
+        }
+
+        // The test scripts have the commands, but not the time zone script
+        let command_features = [
+            "confirmation_tokens",
+            "factory_reset",
+            "restart",
+            "shutdown",
+        ];
+        for feature in command_features {
+            assert_eq!(
+                capabilities.has_feature(feature),
+                cfg!(feature = "commands"),
+                "{feature}"
+            );
+        }
+        assert!(!capabilities.has_feature("apply_timezone"));
+        assert!(!capabilities.has_feature("provisioning"));
+        let expected_len = if cfg!(feature = "commands") {
+            BUILT_IN_FEATURES.len() + command_features.len()
+        } else {
+            BUILT_IN_FEATURES.len()
+        };
+        assert_eq!(capabilities.features.len(), expected_len);
+
+        // Provisioning is listed while the device waits for it
+        let test_dir = TempDir::new().unwrap();
+        let sifis_home = SifisHome::new_with_path(test_dir.path().to_path_buf());
+        let config_store = Box::new(sifis_home.config_store());
+        let state = DeviceState::with_provisioning(sifis_home, config_store, true).unwrap();
+        assert!(Capabilities::from_state(&state).has_feature("provisioning"));
+        let client = Client::tracked(build_rocket(state)).unwrap();
+        let response = client.get("/v1/capabilities").dispatch();
+        let capabilities = response.into_json::<Capabilities>().unwrap();
+        assert!(capabilities.has_feature("provisioning"));
+    }
+}
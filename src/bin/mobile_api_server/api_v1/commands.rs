@@ -2,53 +2,227 @@
 //!
 //! These endpoints allow Mobile Application to give commands to the Smart Device,
 
-use crate::api_common::{ApiKey, ApiKeyError, ErrorResponse, GenericResponse, OkResponse};
+use crate::api_common::{
+    make_json_responses, ApiKey, ApiKeyError, BusyResponse, ErrorResponse, GenericResponse,
+    OkResponse,
+};
+use crate::scripts::{
+    run_script_with_args, spawn_script, ScriptError, ScriptMessage, SCRIPT_TIMEOUT,
+};
 use crate::state::{BusyGuard, DeviceState};
-use rocket::{post, State};
+use rocket::futures::stream::{unfold, Stream};
+use rocket::http::Status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::response::{self, Responder};
+use rocket::serde::json::Json;
+use rocket::{post, Request, State};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{MediaType, RefOr, Responses};
 use rocket_okapi::openapi;
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::add_media_type;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
-use std::process::Command;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// The phrase required to confirm a factory reset
+pub const FACTORY_RESET_CONFIRM: &str = "I really want to perform a factory reset";
+
+/// Commands that can be confirmed with a token from the prepare endpoint
+pub const DESTRUCTIVE_COMMANDS: [&str; 3] = ["factory_reset", "restart", "shutdown"];
+
+/// Subdirectory of the scripts directory with the scripts for the run endpoint
+///
+/// The lifecycle scripts stay in the scripts directory itself, so that the run endpoint can not
+/// start them without the confirmation steps of their own endpoints.
+pub const RUN_SCRIPTS_DIR: &str = "run";
+
+/// How long a confirmation token can be used
+pub const CONFIRMATION_TOKEN_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Description of the 400 response for a token that is not accepted
+const CONFIRMATION_TOKEN_INVALID: &str =
+    "The confirmation token is not valid, has expired, or was already used.";
+
+/// One-time token for confirming a destructive command
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfirmationToken {
+    /// Token to pass in the `token` parameter of the command
+    pub token: String,
+    /// Seconds until the token expires
+    #[serde(alias = "expires_in")]
+    pub expires_in: u64,
+}
+
+/// # Prepare a destructive command
+///
+/// Returns a one-time token for confirming the `factory_reset`, `restart`, or `shutdown` command.
+/// The token is passed to the command in the `token` parameter. It can be used once, and it
+/// expires after a minute.
+///
+/// For the factory reset, the token replaces the confirmation phrase, so that localized
+/// applications do not need to send an English sentence. The restart and shutdown commands
+/// require the token. The token is not used up if the device is busy and the command is refused
+/// with 503 Service Unavailable.
+///
+/// Returns 404 for other command identifiers.
+#[openapi(tag = "Commands")]
+#[post("/command/<id>/prepare")]
+pub async fn prepare_command(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+    id: &str,
+) -> PrepareResponse {
+    match key {
+        Ok(_) if !DESTRUCTIVE_COMMANDS.contains(&id) => PrepareResponse::NotFound(
+            ErrorResponse::not_found(Some(&format!("Command `{id}` does not need preparing."))),
+        ),
+        Ok(_) => match state.issue_confirmation_token(id, CONFIRMATION_TOKEN_LIFETIME) {
+            Ok(token) => PrepareResponse::Ok(Json(ConfirmationToken {
+                token,
+                expires_in: CONFIRMATION_TOKEN_LIFETIME.as_secs(),
+            })),
+            Err(err) => {
+                PrepareResponse::Error(ErrorResponse::internal_server_error(err.to_string()))
+            }
+        },
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => PrepareResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => PrepareResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// Possible responses for the prepare endpoint
+#[derive(Responder)]
+pub enum PrepareResponse {
+    /// 200 OK
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<ConfirmationToken>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+
+    /// 404 Not Found
+    #[response(status = 404, content_type = "json")]
+    NotFound(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for PrepareResponse {
+    /// Generating responses for the prepare endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<ConfirmationToken>(), None),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+            (
+                404,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The command does not need preparing."),
+            ),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+/// Check the factory reset *confirm* parameter
+///
+/// Surrounding whitespace and letter case are ignored, but the whole phrase is still required.
+fn is_factory_reset_confirmed(confirm: &str) -> bool {
+    confirm.trim().eq_ignore_ascii_case(FACTORY_RESET_CONFIRM)
+}
+
+/// Check the confirmation *token* of the *command*
+///
+/// A missing token is not accepted. The token is used up even if the command fails later, so this
+/// is called only when the [BusyGuard] of the command is held.
+fn is_token_accepted(state: &DeviceState, command: &str, token: Option<&str>) -> bool {
+    token.is_some_and(|token| state.use_confirmation_token(command, token))
+}
 
 /// # Reset the device back to factory settings
 ///
-/// Calling this endpoint will delete any settings changes to the device. After this, we still need
-/// to call the `/command/restart` endpoint to restart the device.
+/// Calling this endpoint will delete any settings changes to the device and set the boot count
+/// back to zero. After this, we still need to call the `/command/restart` endpoint to restart the
+/// device.
 ///
 /// After the reboot, the device returns to the initialization phase, waiting for activation with
 /// the mobile application.
 ///
 /// To perform a factory reset, the `confirm` parameter must be set to the message
-/// `I really want to perform a factory reset`.
+/// `I really want to perform a factory reset`. The letter case does not matter, and whitespace
+/// around the message is ignored, so `i really want to perform a factory reset ` is also accepted.
+/// Instead of the message, the `token` parameter can be set to a token from the
+/// `/command/factory_reset/prepare` endpoint.
+///
+/// With `wipe_keys=true`, the DHT private key file is removed too, so that a decommissioned device
+/// no longer holds its DHT identity. A new key is generated when the device is provisioned again.
+///
+/// After a successful reset, the configuration endpoints answer with 410 Gone until the device is
+/// restarted.
 #[openapi(tag = "Commands")]
-#[post("/command/factory_reset?<confirm>")]
+#[post("/command/factory_reset?<confirm>&<token>&<wipe_keys>")]
 pub async fn factory_reset(
     key: Result<ApiKey, ApiKeyError>,
     state: &State<DeviceState>,
     confirm: Option<&str>,
+    token: Option<&str>,
+    wipe_keys: Option<bool>,
 ) -> GenericResponse {
     match key {
-        Ok(_) => match confirm {
-            Some("I really want to perform a factory reset") => {
-                match BusyGuard::try_busy(state, "A factory reset is performed.") {
-                    Ok(_) => {
-                        if let Err(err) = state.set_config(None) {
-                            return GenericResponse::Error(ErrorResponse::internal_server_error(
-                                err.to_string(),
-                            ));
-                        }
-                        if let Err(err) = run_script(state, "factory_reset.sh") {
+        Ok(_) => match (confirm, token) {
+            (Some(confirm), None) if !is_factory_reset_confirmed(confirm) => {
+                GenericResponse::BadRequest(ErrorResponse::bad_request(Some(
+                    "The required confirm parameter was not correct or set.",
+                )))
+            }
+            (None, None) => GenericResponse::BadRequest(ErrorResponse::bad_request(Some(
+                "The required confirm parameter was not correct or set.",
+            ))),
+            _ => match BusyGuard::try_busy(state, "A factory reset is performed.") {
+                Ok(_) if token.is_some() && !is_token_accepted(state, "factory_reset", token) => {
+                    GenericResponse::BadRequest(ErrorResponse::bad_request(Some(
+                        CONFIRMATION_TOKEN_INVALID,
+                    )))
+                }
+                Ok(_) => {
+                    if let Err(err) = state.set_config(None) {
+                        return GenericResponse::Error(ErrorResponse::internal_server_error(
+                            err.to_string(),
+                        ));
+                    }
+                    if let Err(err) = state.reset_boot_count() {
+                        return GenericResponse::Error(ErrorResponse::internal_server_error(
+                            err.to_string(),
+                        ));
+                    }
+                    if wipe_keys.unwrap_or(false) {
+                        if let Err(err) = state.remove_private_key() {
                             return GenericResponse::Error(ErrorResponse::internal_server_error(
                                 err.to_string(),
                             ));
                         }
-                        GenericResponse::Ok(OkResponse::message("Factory reset complete."))
                     }
-                    Err(busy) => GenericResponse::Busy(ErrorResponse::service_unavailable(busy)),
+                    if let Err(err) = run_script(state, "factory_reset.sh").await {
+                        return err.into();
+                    }
+                    state.set_pending_reset(true);
+                    GenericResponse::Ok(OkResponse::message("Factory reset complete."))
                 }
-            }
-            _ => GenericResponse::BadRequest(ErrorResponse::bad_request(Some(
-                "The required confirm parameter was not correct or set.",
-            ))),
+                Err(busy) => GenericResponse::Busy(ErrorResponse::service_unavailable(busy)),
+            },
         },
         Err(err) => match err {
             ApiKeyError::InvalidKey(content) => GenericResponse::BadRequest(content),
@@ -59,17 +233,30 @@ pub async fn factory_reset(
 
 /// # Restart the device
 ///
-/// Calling this endpoint will initiate a device reboot.
+/// Calling this endpoint will initiate a device reboot. Configuration changes and a factory reset
+/// are applied by the reboot, so the reboot required and pending reset flags are cleared.
+///
+/// The `token` parameter must confirm the restart with a token from the
+/// `/command/restart/prepare` endpoint. The restart is not done if the token is missing or not
+/// accepted.
 #[openapi(tag = "Commands")]
-#[post("/command/restart")]
+#[post("/command/restart?<token>")]
 pub async fn restart(
     key: Result<ApiKey, ApiKeyError>,
     state: &State<DeviceState>,
+    token: Option<&str>,
 ) -> GenericResponse {
     match key {
         Ok(_) => match BusyGuard::try_busy(state, "The device is restarting.") {
+            Ok(_) if !is_token_accepted(state, "restart", token) => GenericResponse::BadRequest(
+                ErrorResponse::bad_request(Some(CONFIRMATION_TOKEN_INVALID)),
+            ),
             Ok(_) => {
-                if let Err(err) = run_script(state, "restart.sh") {
+                if let Err(err) = run_script(state, "restart.sh").await {
+                    return err.into();
+                }
+                state.set_pending_reset(false);
+                if let Err(err) = state.set_reboot_required(false) {
                     return GenericResponse::Error(ErrorResponse::internal_server_error(
                         err.to_string(),
                     ));
@@ -88,19 +275,25 @@ pub async fn restart(
 /// # Shutdown the device
 ///
 /// Calling this endpoint will initiate a shutdown of the device.
+///
+/// The `token` parameter must confirm the shutdown with a token from the
+/// `/command/shutdown/prepare` endpoint. The shutdown is not done if the token is missing or not
+/// accepted.
 #[openapi(tag = "Commands")]
-#[post("/command/shutdown")]
+#[post("/command/shutdown?<token>")]
 pub async fn shutdown(
     key: Result<ApiKey, ApiKeyError>,
     state: &State<DeviceState>,
+    token: Option<&str>,
 ) -> GenericResponse {
     match key {
         Ok(_) => match BusyGuard::try_busy(state, "The device is shutting down.") {
+            Ok(_) if !is_token_accepted(state, "shutdown", token) => GenericResponse::BadRequest(
+                ErrorResponse::bad_request(Some(CONFIRMATION_TOKEN_INVALID)),
+            ),
             Ok(_) => {
-                if let Err(err) = run_script(state, "shutdown.sh") {
-                    return GenericResponse::Error(ErrorResponse::internal_server_error(
-                        err.to_string(),
-                    ));
+                if let Err(err) = run_script(state, "shutdown.sh").await {
+                    return err.into();
                 }
                 GenericResponse::Ok(OkResponse::message("System will now power off."))
             }
@@ -113,36 +306,350 @@ pub async fn shutdown(
     }
 }
 
-/// Run script from the server `scripts` directory
-fn run_script(
+/// Result of a script run with the run endpoint
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptOutput {
+    /// True when the script exited with code zero
+    pub success: bool,
+    /// Exit code, or null if the script was terminated by a signal
+    #[serde(alias = "exit_code")]
+    pub exit_code: Option<i32>,
+    /// Lines written to stdout and stderr in the order they were read
+    pub lines: Vec<String>,
+}
+
+/// # Run a script and show its output
+///
+/// Runs the `<id>.sh` script from the `run` subdirectory of the scripts directory, so that a
+/// script can be tried before it is wired to a command. The *id* can contain lowercase ASCII
+/// letters, digits, `_`, and `-`. The `factory_reset`, `restart`, and `shutdown` ids are refused
+/// with 400 Bad Request, because those commands need the confirmation of their own endpoints.
+///
+/// By default, the response is sent when the script has finished, with the combined stdout and
+/// stderr lines. A script that exits with a non-zero code is still a successful run, and the
+/// exit code is in the response.
+///
+/// With `stream=true`, the output is sent as Server-Sent Events while the script runs. Each line
+/// is an `output` event. The last event is `exit` with the [ScriptOutput] fields and empty
+/// `lines`, or `error` with a description if the script could not be run to the end.
+///
+/// The server is busy while the script runs, also when streaming.
+#[openapi(tag = "Commands")]
+#[post("/command/<id>/run?<stream>")]
+pub async fn run_script_output(
+    key: Result<ApiKey, ApiKeyError>,
     state: &State<DeviceState>,
-    script_name: &'static str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut script = match std::env::var("MOBILE_API_SCRIPTS_PATH") {
-        Ok(path) => PathBuf::from(path),
-        Err(_) => state.resource_path("scripts")?,
+    id: &str,
+    stream: Option<bool>,
+) -> RunScriptResponse {
+    if let Err(err) = key {
+        return match err {
+            ApiKeyError::InvalidKey(content) => RunScriptResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => RunScriptResponse::Unauthorized(content),
+        };
+    }
+    if !is_valid_script_id(id) {
+        return RunScriptResponse::BadRequest(ErrorResponse::bad_request(Some(
+            "The script id can contain only lowercase letters, digits, `_`, and `-`.",
+        )));
+    }
+    if DESTRUCTIVE_COMMANDS.contains(&id) {
+        return RunScriptResponse::BadRequest(ErrorResponse::bad_request(Some(&format!(
+            "The `{id}` command can only be run with the `/command/{id}` endpoint."
+        ))));
+    }
+    let script = match state.scripts_path() {
+        Ok(path) => path.join(RUN_SCRIPTS_DIR).join(format!("{id}.sh")),
+        Err(_) => PathBuf::new(),
     };
-    script.push(script_name);
-    println!("Running: {:?}", script);
-    let mut command = Command::new(script);
-    let output = command.output()?;
-    if output.status.success() {
-        let output_stdout = String::from_utf8_lossy(&output.stdout);
-        if !output_stdout.is_empty() {
-            println!("{}", output_stdout)
+    if !script.is_file() {
+        return RunScriptResponse::NotFound(ErrorResponse::not_found(Some(
+            "The script was not found.",
+        )));
+    }
+
+    let guard = match BusyGuard::try_busy(state, "A script is running.") {
+        Ok(guard) => guard,
+        Err(busy) => return RunScriptResponse::Busy(ErrorResponse::service_unavailable(busy)),
+    };
+    let mut receiver = match spawn_script(&script, SCRIPT_TIMEOUT) {
+        Ok(receiver) => receiver,
+        Err(error) => return RunScriptResponse::from(error),
+    };
+
+    if stream.unwrap_or(false) {
+        // The guard is moved to the stream, so that the server stays busy until the script ends
+        let events = unfold((receiver, guard), |(mut receiver, guard)| async move {
+            let event = match receiver.recv().await? {
+                ScriptMessage::Line(line) => Event::data(line).event("output"),
+                ScriptMessage::Exit(exit_code) => Event::json(&ScriptOutput {
+                    success: exit_code == Some(0),
+                    exit_code,
+                    lines: Vec::new(),
+                })
+                .event("exit"),
+                ScriptMessage::Error(error) => Event::data(error.to_string()).event("error"),
+            };
+            Some((event, (receiver, guard)))
+        });
+        return RunScriptResponse::Stream(EventStream::from(Box::pin(events) as ScriptEvents));
+    }
+
+    let mut lines = Vec::new();
+    while let Some(message) = receiver.recv().await {
+        match message {
+            ScriptMessage::Line(line) => lines.push(line),
+            ScriptMessage::Exit(exit_code) => {
+                return RunScriptResponse::Ok(Json(ScriptOutput {
+                    success: exit_code == Some(0),
+                    exit_code,
+                    lines,
+                }))
+            }
+            ScriptMessage::Error(error) => return RunScriptResponse::from(error),
+        }
+    }
+    drop(guard);
+    RunScriptResponse::Error(ErrorResponse::internal_server_error(
+        "The script ended without an exit status.".to_string(),
+    ))
+}
+
+/// Check that the script *id* is safe to use as a file name
+fn is_valid_script_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, b'_' | b'-'))
+}
+
+/// Server-Sent Events from a running script
+///
+/// The stream owns everything it uses, so that it can be sent after the request has ended.
+pub type ScriptEvents = Pin<Box<dyn Stream<Item = Event> + Send>>;
+
+/// Possible responses for the run script endpoint
+///
+/// [Responder] is implemented by hand, because [EventStream] can not be derived into a responder
+/// together with the JSON responses.
+pub enum RunScriptResponse {
+    /// 200 OK, the script was run
+    Ok(Json<ScriptOutput>),
+
+    /// 200 OK, the script output as Server-Sent Events
+    Stream(EventStream<ScriptEvents>),
+
+    /// 400 Bad Request
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    Unauthorized(Json<ErrorResponse>),
+
+    /// 404 Not Found
+    NotFound(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    Error(Json<ErrorResponse>),
+
+    /// 503 Service Unavailable
+    Busy(BusyResponse),
+
+    /// 504 Gateway Timeout
+    Timeout(Json<ErrorResponse>),
+}
+
+impl<'r> Responder<'r, 'r> for RunScriptResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'r> {
+        match self {
+            RunScriptResponse::Ok(output) => output.respond_to(request),
+            RunScriptResponse::Stream(events) => events.respond_to(request),
+            RunScriptResponse::BadRequest(error) => (Status::BadRequest, error).respond_to(request),
+            RunScriptResponse::Unauthorized(error) => {
+                (Status::Unauthorized, error).respond_to(request)
+            }
+            RunScriptResponse::NotFound(error) => (Status::NotFound, error).respond_to(request),
+            RunScriptResponse::Error(error) => {
+                (Status::InternalServerError, error).respond_to(request)
+            }
+            RunScriptResponse::Busy(busy) => busy.respond_to(request),
+            RunScriptResponse::Timeout(error) => {
+                (Status::GatewayTimeout, error).respond_to(request)
+            }
+        }
+    }
+}
+
+impl From<ScriptError> for RunScriptResponse {
+    /// Response for the script *error*
+    ///
+    /// A missing script is reported as 404 Not Found, because the client chose the script.
+    fn from(error: ScriptError) -> Self {
+        let description = error.to_string();
+        match error {
+            ScriptError::NotFound { .. } => {
+                RunScriptResponse::NotFound(ErrorResponse::not_found(Some(&description)))
+            }
+            ScriptError::Timeout { .. } => {
+                RunScriptResponse::Timeout(ErrorResponse::gateway_timeout(description))
+            }
+            _ => RunScriptResponse::Error(ErrorResponse::internal_server_error(description)),
+        }
+    }
+}
+
+impl OpenApiResponderInner for RunScriptResponse {
+    /// Generating responses for the run script endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = make_json_responses(vec![
+            (
+                200,
+                gen.json_schema::<ScriptOutput>(),
+                Some("The script was run. With `stream=true`, the output is sent as events."),
+            ),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+            (
+                404,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The script was not found."),
+            ),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+            (503, gen.json_schema::<ErrorResponse>(), None),
+            (504, gen.json_schema::<ErrorResponse>(), None),
+        ])?;
+
+        // Streamed output
+        if let Some(RefOr::Object(response)) = responses.responses.get_mut("200") {
+            let media = MediaType {
+                schema: Some(gen.json_schema::<String>()),
+                ..MediaType::default()
+            };
+            add_media_type(&mut response.content, "text/event-stream", media);
+        }
+        Ok(responses)
+    }
+}
+
+impl From<ScriptError> for GenericResponse {
+    /// Response for the script *error*
+    ///
+    /// A missing script means that the command is not available on this device, and a timeout is
+    /// reported as a gateway timeout because the script is an upstream of the server. Other
+    /// failures are internal server errors.
+    fn from(error: ScriptError) -> Self {
+        let description = error.to_string();
+        match error {
+            ScriptError::NotFound { .. } => {
+                GenericResponse::NotImplemented(ErrorResponse::not_implemented(description))
+            }
+            ScriptError::Timeout { .. } => {
+                GenericResponse::Timeout(ErrorResponse::gateway_timeout(description))
+            }
+            _ => GenericResponse::Error(ErrorResponse::internal_server_error(description)),
         }
     }
-    Ok(())
+}
+
+/// Run script from the server `scripts` directory
+async fn run_script(state: &DeviceState, script_name: &'static str) -> Result<(), ScriptError> {
+    run_script_with_args(state, script_name, &[]).await
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::api_common::{ErrorResponse, OkResponse};
+    use super::*;
     use crate::api_v1::tests_common::*;
+    use crate::build_rocket;
+    use mobile_api::SifisHome;
     use rocket::fs::relative;
-    use rocket::http::Status;
-    use std::path::PathBuf;
-    use std::time::Duration;
+    use rocket::local::blocking::Client;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_is_valid_script_id() {
+        assert!(is_valid_script_id("restart"));
+        assert!(is_valid_script_id("apply_timezone"));
+        assert!(is_valid_script_id("print-lines-2"));
+        assert!(is_valid_script_id(&"a".repeat(64)));
+        assert!(!is_valid_script_id(""));
+        assert!(!is_valid_script_id(&"a".repeat(65)));
+        assert!(!is_valid_script_id("Restart"));
+        assert!(!is_valid_script_id("restart.sh"));
+        assert!(!is_valid_script_id(".."));
+        assert!(!is_valid_script_id("a b"));
+    }
+
+    #[test]
+    fn test_script_error_status() {
+        let script = PathBuf::from("/scripts/restart.sh");
+        let not_found = ScriptError::NotFound {
+            script: script.clone(),
+        };
+        let not_executable = ScriptError::NotExecutable {
+            script: script.clone(),
+        };
+        let failed = ScriptError::Failed {
+            script: script.clone(),
+            code: Some(1),
+            stderr: String::new(),
+        };
+        let timeout = ScriptError::Timeout {
+            script: script.clone(),
+            timeout: SCRIPT_TIMEOUT,
+        };
+        let io = ScriptError::Io {
+            script,
+            error: std::io::Error::from(ErrorKind::Other),
+        };
+
+        assert!(matches!(
+            GenericResponse::from(not_found),
+            GenericResponse::NotImplemented(_)
+        ));
+        assert!(matches!(
+            GenericResponse::from(not_executable),
+            GenericResponse::Error(_)
+        ));
+        assert!(matches!(
+            GenericResponse::from(failed),
+            GenericResponse::Error(_)
+        ));
+        match GenericResponse::from(timeout) {
+            GenericResponse::Timeout(content) => {
+                assert_eq!(content.error.code, 504);
+                assert!(content.error.description.contains("30 seconds"));
+            }
+            _ => panic!("timeout should give 504 response"),
+        }
+        assert!(matches!(
+            GenericResponse::from(io),
+            GenericResponse::Error(_)
+        ));
+    }
+
+    #[test]
+    fn test_factory_reset_confirm() {
+        assert!(is_factory_reset_confirmed(FACTORY_RESET_CONFIRM));
+        assert!(is_factory_reset_confirmed(
+            "I really want to perform a factory reset "
+        ));
+        assert!(is_factory_reset_confirmed(
+            "\tI really want to perform a factory reset\n"
+        ));
+        assert!(is_factory_reset_confirmed(
+            "i REALLY want to perform a Factory Reset"
+        ));
+
+        // The whole phrase is required
+        assert!(!is_factory_reset_confirmed(""));
+        assert!(!is_factory_reset_confirmed("factory reset"));
+        assert!(!is_factory_reset_confirmed(
+            "I really want to perform a factory reset now"
+        ));
+        assert!(!is_factory_reset_confirmed("Yes, please reset the device"));
+    }
 
     // Test ignored for Miri because the server has time and io-related
     // functions that are not available in isolation mode
@@ -152,6 +659,7 @@ mod tests {
         std::env::set_var("MOBILE_API_SCRIPTS_PATH", relative!("tests/scripts/"));
         let uri = "/v1/command/factory_reset";
         let (test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
         test_invalid_auth_post(&client, uri);
 
         // Save test config
@@ -162,24 +670,93 @@ mod tests {
         test_config.save_to(&test_config_file).unwrap();
 
         // Reset needs extra query parameter
-        let response = client.post(uri).header(api_key_header()).dispatch();
+        let response = client.post(uri).header(api_key_header.clone()).dispatch();
         assert_eq!(response.status(), Status::BadRequest);
         let error_response = response.into_json::<ErrorResponse>().unwrap();
         assert_eq!(error_response.error.code, 400);
         assert!(test_config_file.exists());
 
+        // Wrong phrase is rejected too
+        let response = client
+            .post("/v1/command/factory_reset?confirm=Yes%20please")
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(test_config_file.exists());
+
         // Here we give the required extra parameter
-        let (runtime, handle) = make_script_run_checker("FactoryReset", Duration::from_secs(10));
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        let sifis_home = SifisHome::new_with_path(test_dir.path().join("sifis-home"));
+        sifis_home.increment_boot_count().unwrap();
+        assert_eq!(state.boot_count().unwrap(), 1);
+        let Some((runtime, handle)) =
+            make_script_run_checker("FactoryReset", Duration::from_secs(10))
+        else {
+            return;
+        };
         let response = client
-            .post("/v1/command/factory_reset?confirm=I%20really%20want%20to%20perform%20a%20factory%20reset")
-            .header(api_key_header())
+            .post("/v1/command/factory_reset?confirm=I%20Really%20want%20to%20perform%20a%20factory%20reset%20")
+            .header(api_key_header.clone())
             .dispatch();
         assert_eq!(response.status(), Status::Ok);
         let ok_response = response.into_json::<OkResponse>().unwrap();
         assert_eq!(ok_response.code, 200);
         assert!(!test_config_file.exists());
+        assert_eq!(state.boot_count().unwrap(), 0);
         let script = runtime.block_on(handle).unwrap().unwrap();
         assert_eq!(script, "factory_reset.sh");
+
+        // Configuration is gone until the device is restarted
+        assert!(state.pending_reset());
+        let response = client
+            .get("/v1/device/configuration")
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Gone);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(
+            error_response.error.description,
+            "Device is resetting; restart required."
+        );
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_factory_reset_wipe_keys() {
+        std::env::set_var("MOBILE_API_SCRIPTS_PATH", relative!("tests/scripts/"));
+        let uri = "/v1/command/factory_reset?confirm=I%20really%20want%20to%20perform%20a%20factory%20reset";
+        let (_test_dir, sifis_home) = create_test_sifis_home();
+        let private_key_file = sifis_home.ensure_private_key().unwrap();
+        let client = Client::tracked(build_rocket(DeviceState::new(sifis_home).unwrap())).unwrap();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+
+        // The private key is preserved by default
+        let Some((runtime, handle)) =
+            make_script_run_checker("FactoryReset", Duration::from_secs(10))
+        else {
+            return;
+        };
+        let response = client.post(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(runtime.block_on(handle).unwrap().is_ok());
+        assert!(private_key_file.exists());
+        drop(runtime);
+
+        // And removed when requested
+        let Some((runtime, handle)) =
+            make_script_run_checker("FactoryReset", Duration::from_secs(10))
+        else {
+            return;
+        };
+        let response = client
+            .post(format!("{uri}&wipe_keys=true"))
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(runtime.block_on(handle).unwrap().is_ok());
+        assert!(!private_key_file.exists());
     }
 
     // Test ignored for Miri because the server has time and io-related
@@ -190,11 +767,42 @@ mod tests {
         std::env::set_var("MOBILE_API_SCRIPTS_PATH", relative!("tests/scripts/"));
         let uri = "/v1/command/restart";
         let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
         test_invalid_auth_post(&client, uri);
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        state.set_reboot_required(true).unwrap();
+        state.set_pending_reset(true);
+
+        // The token is required
+        let response = client.post(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(error_response.error.description, CONFIRMATION_TOKEN_INVALID);
 
-        let (runtime, handle) = make_script_run_checker("Restart", Duration::from_secs(10));
-        let response = client.post(uri).header(api_key_header()).dispatch();
+        // A busy device does not use up the token
+        let token = state
+            .issue_confirmation_token("restart", CONFIRMATION_TOKEN_LIFETIME)
+            .unwrap();
+        let uri = format!("{uri}?token={token}");
+        state.set_busy("Testing").unwrap();
+        let response = client
+            .post(uri.as_str())
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        state.clear_busy();
+
+        let Some((runtime, handle)) = make_script_run_checker("Restart", Duration::from_secs(10))
+        else {
+            return;
+        };
+        let response = client
+            .post(uri.as_str())
+            .header(api_key_header.clone())
+            .dispatch();
         assert_eq!(response.status(), Status::Ok);
+        assert!(!state.reboot_required());
+        assert!(!state.pending_reset());
 
         let ok_response = response.into_json::<OkResponse>().unwrap();
         assert_eq!(ok_response.code, 200);
@@ -212,10 +820,25 @@ mod tests {
         std::env::set_var("MOBILE_API_SCRIPTS_PATH", relative!("tests/scripts/"));
         let uri = "/v1/command/shutdown";
         let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
         test_invalid_auth_post(&client, uri);
 
-        let (runtime, handle) = make_script_run_checker("Shutdown", Duration::from_secs(10));
-        let response = client.post(uri).header(api_key_header()).dispatch();
+        // The token is required
+        let response = client.post(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        let token = state
+            .issue_confirmation_token("shutdown", CONFIRMATION_TOKEN_LIFETIME)
+            .unwrap();
+        let Some((runtime, handle)) = make_script_run_checker("Shutdown", Duration::from_secs(10))
+        else {
+            return;
+        };
+        let response = client
+            .post(format!("{uri}?token={token}"))
+            .header(api_key_header.clone())
+            .dispatch();
         assert_eq!(response.status(), Status::Ok);
 
         let ok_response = response.into_json::<OkResponse>().unwrap();
@@ -225,4 +848,169 @@ mod tests {
         let script = runtime.block_on(handle).unwrap().unwrap();
         assert_eq!(script, "shutdown.sh");
     }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_prepare_command() {
+        std::env::set_var("MOBILE_API_SCRIPTS_PATH", relative!("tests/scripts/"));
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        test_invalid_auth_post(&client, "/v1/command/shutdown/prepare");
+        let prepare = |id: &str| {
+            let response = client
+                .post(format!("/v1/command/{id}/prepare"))
+                .header(api_key_header.clone())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let token = response.into_json::<ConfirmationToken>().unwrap();
+            assert_eq!(token.expires_in, 60);
+            token.token
+        };
+
+        // Only destructive commands can be prepared
+        let response = client
+            .post("/v1/command/print_lines/prepare")
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Happy path, the token replaces the confirmation phrase. Without D-Bus, the script can
+        // not report back, but the token is used all the same.
+        let token = prepare("factory_reset");
+        assert_ne!(token, prepare("factory_reset"));
+        let checker = make_script_run_checker("FactoryReset", Duration::from_secs(10));
+        let response = client
+            .post(format!("/v1/command/factory_reset?token={token}"))
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_ne!(response.status(), Status::BadRequest);
+        if let Some((runtime, handle)) = checker {
+            assert_eq!(response.status(), Status::Ok);
+            let script = runtime.block_on(handle).unwrap().unwrap();
+            assert_eq!(script, "factory_reset.sh");
+        }
+
+        // Reused token
+        let response = client
+            .post(format!("/v1/command/factory_reset?token={token}"))
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(error_response.error.description, CONFIRMATION_TOKEN_INVALID);
+
+        // Expired token
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        let expired = state
+            .issue_confirmation_token("shutdown", Duration::ZERO)
+            .unwrap();
+        let response = client
+            .post(format!("/v1/command/shutdown?token={expired}"))
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Token of another command, and a made up token
+        let restart_token = prepare("restart");
+        for token in [restart_token.as_str(), "abcdefghijklmnopqrst"] {
+            let response = client
+                .post(format!("/v1/command/shutdown?token={token}"))
+                .header(api_key_header.clone())
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest, "{token}");
+        }
+
+        // Wrong token is not replaced by a correct phrase
+        let response = client
+            .post(format!(
+                "/v1/command/factory_reset?token={expired}&confirm=I%20really%20want%20to%20perform%20a%20factory%20reset"
+            ))
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Restart with its own token
+        let checker = make_script_run_checker("Restart", Duration::from_secs(10));
+        let response = client
+            .post(format!("/v1/command/restart?token={restart_token}"))
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_ne!(response.status(), Status::BadRequest);
+        if let Some((runtime, handle)) = checker {
+            assert_eq!(response.status(), Status::Ok);
+            let script = runtime.block_on(handle).unwrap().unwrap();
+            assert_eq!(script, "restart.sh");
+        }
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_run_script_output() {
+        std::env::set_var("MOBILE_API_SCRIPTS_PATH", relative!("tests/scripts/"));
+        let uri = "/v1/command/print_lines/run";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        test_invalid_auth_post(&client, uri);
+        let expected_lines = ["first line", "second line", "third line"];
+
+        // Buffered output
+        let response = client.post(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let output = response.into_json::<ScriptOutput>().unwrap();
+        assert!(output.success);
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.lines, expected_lines);
+
+        // Streamed output
+        let response = client
+            .post(format!("{uri}?stream=true"))
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.content_type(),
+            Some(rocket::http::ContentType::EventStream)
+        );
+        let body = response.into_string().unwrap();
+        let mut position = 0;
+        for line in expected_lines {
+            let event = format!("data:{line}\n");
+            let found = body[position..].find(&event).unwrap();
+            position += found + event.len();
+        }
+        assert_eq!(body.matches("event:output").count(), expected_lines.len());
+        assert!(body[position..].contains("event:exit"));
+        assert!(body[position..].contains(r#""exitCode":0"#));
+
+        // Server is not left busy
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        assert!(state.busy().is_empty());
+
+        // Unknown and invalid script ids
+        let response = client
+            .post("/v1/command/no_such_script/run")
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client
+            .post("/v1/command/Print_Lines/run")
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Lifecycle scripts are in the scripts directory, but they are never run from here
+        for id in DESTRUCTIVE_COMMANDS {
+            assert!(state.has_script(&format!("{id}.sh")));
+            let response = client
+                .post(format!("/v1/command/{id}/run"))
+                .header(api_key_header.clone())
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest, "{id}");
+        }
+        assert!(!state.pending_reset());
+    }
 }
@@ -3,35 +3,58 @@
 //! These endpoints allow Mobile Application to check device status, read and set configuration.
 
 use crate::api_common::*;
-use crate::device_status::DeviceStatus;
-use crate::state::{BusyGuard, DeviceState};
+use crate::device_status::{
+    DeviceStatus, PartialDeviceStatus, ProcessOrder, ProcessStatus, StatusSection,
+};
+use crate::scripts::{run_script_with_args, ScriptError};
+use crate::state::{BusyGuard, DeviceState, SetConfigError};
 use mobile_api::configs::DeviceConfig;
-use rocket::serde::json::Json;
-use rocket::{get, put, Responder, State};
+use mobile_api::error::ErrorKind;
+use rocket::serde::json::{self, Json};
+use rocket::{get, post, put, Responder, State};
 use rocket_okapi::gen::OpenApiGenerator;
-use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::okapi::openapi3::{MediaType, RefOr, Responses};
 use rocket_okapi::openapi;
 use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::add_media_type;
+use schemars::schema::{SchemaObject, SubschemaValidation};
 use schemars::JsonSchema;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
 use uuid::Uuid;
 
+/// Number of processes returned when the `top` parameter is not given
+pub const DEFAULT_TOP_PROCESSES: usize = 5;
+
+/// Largest accepted `top` parameter for the processes endpoint
+pub const MAX_TOP_PROCESSES: usize = 100;
+
+/// Description of the 410 response while a factory reset waits for a restart
+pub const PENDING_RESET_DESCRIPTION: &str = "Device is resetting; restart required.";
+
+/// Free space in bytes needed on the disk for saving the configuration
+pub const MIN_FREE_SPACE: u64 = 1024 * 1024;
+
+/// How long saving the configuration waits for another write to finish
+pub const CONFIG_LOCK_WAIT: Duration = Duration::from_millis(500);
+
 /// Smart Device Information
 ///
 /// Contains the product name and unique identifier
 #[derive(Debug, JsonSchema, Serialize)]
-pub struct DeviceInfo<'a> {
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
     /// Product name
-    product_name: &'a str,
+    product_name: String,
     /// 128-bit UUID in standard hex format
-    uuid: &'a Uuid,
+    uuid: Uuid,
 }
 
-impl<'a> From<&'a mobile_api::configs::DeviceInfo> for DeviceInfo<'a> {
-    fn from(value: &'a mobile_api::configs::DeviceInfo) -> DeviceInfo<'a> {
+impl From<&mobile_api::configs::DeviceInfo> for DeviceInfo {
+    fn from(value: &mobile_api::configs::DeviceInfo) -> DeviceInfo {
         Self {
-            product_name: value.product_name(),
-            uuid: value.uuid(),
+            product_name: value.product_name().to_string(),
+            uuid: *value.uuid(),
         }
     }
 }
@@ -44,10 +67,43 @@ impl<'a> From<&'a mobile_api::configs::DeviceInfo> for DeviceInfo<'a> {
 /// Unlike other endpoints, this one works without an API key.
 /// Thus, applications using the interface can identify which device
 /// this is and then use the appropriate key for other endpoints.
+///
+/// Returns 404 if the device is waiting for provisioning.
 #[openapi(tag = "Device")]
 #[get("/device/info")]
-pub async fn info(state: &State<DeviceState>) -> Json<DeviceInfo> {
-    Json(state.device_info().into())
+pub async fn info(state: &State<DeviceState>) -> InfoResponse {
+    match state.device_info() {
+        Some(device_info) => InfoResponse::Ok(Json(device_info.as_ref().into())),
+        None => InfoResponse::NotFound(ErrorResponse::not_found(Some(
+            "The device has not been provisioned yet.",
+        ))),
+    }
+}
+
+/// Info Endpoint Response
+#[derive(Responder)]
+pub enum InfoResponse {
+    /// 200 OK
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<DeviceInfo>),
+
+    /// 404 Not Found
+    #[response(status = 404, content_type = "json")]
+    NotFound(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for InfoResponse {
+    /// Generating responses for the info endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<DeviceInfo>(), None),
+            (
+                404,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The device has not been provisioned yet."),
+            ),
+        ])
+    }
 }
 
 /// # Device status
@@ -56,22 +112,49 @@ pub async fn info(state: &State<DeviceState>) -> Json<DeviceInfo> {
 ///
 /// * CPU usage
 ///
+/// * CPU model and frequency
+///
 /// * Memory usage
 ///
 /// * Disk space usage
 ///
-/// * Uptime
+/// * Uptime and boot time
+///
+/// * Load average and process count
+///
+/// * Time when the status was collected
 ///
-/// * Load average
+/// The optional `fields` parameter selects the sections to return as a comma-separated list of
+/// `cpu`, `mem`, `disks`, `uptime`, and `load`. For example, `?fields=cpu,mem` returns only the
+/// CPU and memory information. Unknown names are answered with 400 Bad Request.
 ///
+/// The status is returned as MessagePack instead of JSON when `application/msgpack` is the
+/// preferred type in the `Accept` header. The fields are the same in both formats.
 #[openapi(tag = "Device")]
-#[get("/device/status")]
+#[get("/device/status?<fields>")]
 pub async fn status(
     key: Result<ApiKey, ApiKeyError>,
     state: &State<DeviceState>,
+    format: ResponseFormat,
+    fields: Option<&str>,
 ) -> StatusResponse {
     match key {
-        Ok(_) => StatusResponse::Ok(Json(state.device_status())),
+        Ok(_) => match fields.map(StatusSection::parse_list) {
+            None => match format {
+                ResponseFormat::Json => StatusResponse::Ok(Json(state.device_status())),
+                ResponseFormat::MessagePack => msgpack_status(&state.device_status()),
+            },
+            Some(Ok(sections)) => {
+                let selected = state.partial_device_status(&sections);
+                match format {
+                    ResponseFormat::Json => StatusResponse::Partial(Json(selected)),
+                    ResponseFormat::MessagePack => msgpack_status(&selected),
+                }
+            }
+            Some(Err(error)) => {
+                StatusResponse::BadRequest(ErrorResponse::bad_request(Some(&error)))
+            }
+        },
         Err(err) => match err {
             ApiKeyError::InvalidKey(content) => StatusResponse::BadRequest(content),
             ApiKeyError::WrongKey(content) => StatusResponse::Unauthorized(content),
@@ -79,6 +162,16 @@ pub async fn status(
     }
 }
 
+/// Status response in MessagePack with named fields
+fn msgpack_status<T: Serialize>(value: &T) -> StatusResponse {
+    match rmp_serde::to_vec_named(value) {
+        Ok(bytes) => StatusResponse::MessagePack(bytes),
+        Err(error) => {
+            StatusResponse::Error(ErrorResponse::internal_server_error(error.to_string()))
+        }
+    }
+}
+
 /// Status Endpoint Response
 #[derive(Responder)]
 pub enum StatusResponse {
@@ -86,6 +179,14 @@ pub enum StatusResponse {
     #[response(status = 200, content_type = "json")]
     Ok(Json<DeviceStatus>),
 
+    /// 200 OK with only the selected sections
+    #[response(status = 200, content_type = "json")]
+    Partial(Json<PartialDeviceStatus>),
+
+    /// 200 OK as MessagePack, with all or only the selected sections
+    #[response(status = 200, content_type = "msgpack")]
+    MessagePack(Vec<u8>),
+
     /// 400 Bad Request
     #[response(status = 400, content_type = "json")]
     BadRequest(Json<ErrorResponse>),
@@ -93,23 +194,330 @@ pub enum StatusResponse {
     /// 401 Unauthorized
     #[response(status = 401, content_type = "json")]
     Unauthorized(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
 }
 
 impl OpenApiResponderInner for StatusResponse {
     /// Generating responses for the status endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        // Either the full status, or only the selected sections
+        let status_schema = SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                one_of: Some(vec![
+                    gen.json_schema::<DeviceStatus>().into(),
+                    gen.json_schema::<PartialDeviceStatus>().into(),
+                ]),
+                ..SubschemaValidation::default()
+            })),
+            ..SchemaObject::default()
+        };
+        let mut responses = make_json_responses(vec![
+            (
+                200,
+                status_schema.clone(),
+                Some("Device status. With `fields`, only the selected sections are included."),
+            ),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+        ])?;
+
+        // The same status is available as MessagePack
+        if let Some(RefOr::Object(response)) = responses.responses.get_mut("200") {
+            let media = MediaType {
+                schema: Some(status_schema),
+                ..MediaType::default()
+            };
+            add_media_type(&mut response.content, "application/msgpack", media);
+        }
+        Ok(responses)
+    }
+}
+
+/// Reboot status of the device
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RebootRequired {
+    /// True if the configuration has changed and the device should be restarted
+    #[serde(alias = "reboot_required")]
+    pub reboot_required: bool,
+}
+
+/// # Is a reboot required
+///
+/// Configuration changes take effect only after the device is restarted. This endpoint tells if
+/// the configuration has changed since the last restart with the `/command/restart` endpoint, so
+/// that the application knows when to prompt the user.
+#[openapi(tag = "Device")]
+#[get("/device/reboot_required")]
+pub async fn reboot_required(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+) -> RebootRequiredResponse {
+    match key {
+        Ok(_) => RebootRequiredResponse::Ok(Json(RebootRequired {
+            reboot_required: state.reboot_required(),
+        })),
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => RebootRequiredResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => RebootRequiredResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// Reboot Required Endpoint Response
+#[derive(Responder)]
+pub enum RebootRequiredResponse {
+    /// 200 OK
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<RebootRequired>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for RebootRequiredResponse {
+    /// Generating responses for the reboot required endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<RebootRequired>(), None),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+/// Boot count of the device
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootCount {
+    /// How many times the device has booted since it was installed or factory reset
+    #[serde(alias = "boot_count")]
+    pub boot_count: u64,
+}
+
+/// # Boot count
+///
+/// Returns how many times the device has booted. The count is increased when the server starts,
+/// and the factory reset sets it back to zero. A count that grows quickly tells about a reboot
+/// loop.
+#[openapi(tag = "Device")]
+#[get("/device/boot_count")]
+pub async fn boot_count(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+) -> BootCountResponse {
+    match key {
+        Ok(_) => match state.boot_count() {
+            Ok(boot_count) => BootCountResponse::Ok(Json(BootCount { boot_count })),
+            Err(error) => {
+                BootCountResponse::Error(ErrorResponse::internal_server_error(error.to_string()))
+            }
+        },
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => BootCountResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => BootCountResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// Possible responses for the boot count endpoint
+#[derive(Responder)]
+pub enum BootCountResponse {
+    /// 200 OK
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<BootCount>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for BootCountResponse {
+    /// Generating responses for the boot count endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<BootCount>(), None),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+/// Parse the `top` parameter of the processes endpoint
+fn parse_top(top: Option<&str>) -> Result<usize, String> {
+    match top {
+        None => Ok(DEFAULT_TOP_PROCESSES),
+        Some(top) => match top.parse::<usize>() {
+            Ok(top) if (1..=MAX_TOP_PROCESSES).contains(&top) => Ok(top),
+            _ => Err(format!(
+                "The top must be a number from 1 to {MAX_TOP_PROCESSES}."
+            )),
+        },
+    }
+}
+
+/// # Top processes
+///
+/// Returns the processes using the most resources, so that a runaway process can be found
+/// without logging in to the device.
+///
+/// The optional `top` parameter sets how many processes are returned, from 1 to 100. The default
+/// is 5. The optional `by` parameter selects the order, `cpu` for the CPU usage (default) or `mem`
+/// for the RAM usage. Invalid values are answered with 400 Bad Request.
+#[openapi(tag = "Device")]
+#[get("/device/processes?<top>&<by>")]
+pub async fn processes(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+    top: Option<&str>,
+    by: Option<&str>,
+) -> ProcessesResponse {
+    match key {
+        Ok(_) => {
+            let order = by
+                .map(str::parse::<ProcessOrder>)
+                .unwrap_or(Ok(ProcessOrder::default()));
+            match (parse_top(top), order) {
+                (Ok(top), Ok(order)) => {
+                    ProcessesResponse::Ok(Json(state.top_processes(top, order)))
+                }
+                (Err(error), _) | (_, Err(error)) => {
+                    ProcessesResponse::BadRequest(ErrorResponse::bad_request(Some(&error)))
+                }
+            }
+        }
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => ProcessesResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => ProcessesResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// Possible responses for the processes endpoint
+#[derive(Responder)]
+pub enum ProcessesResponse {
+    /// 200 OK
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<Vec<ProcessStatus>>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for ProcessesResponse {
+    /// Generating responses for the processes endpoint
     fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
         make_json_responses(vec![
-            (200, gen.json_schema::<DeviceStatus>(), None),
+            (
+                200,
+                gen.json_schema::<Vec<ProcessStatus>>(),
+                Some("Processes using the most resources, in the requested order"),
+            ),
             (400, gen.json_schema::<ErrorResponse>(), None),
             (401, gen.json_schema::<ErrorResponse>(), None),
         ])
     }
 }
 
+/// DHT public key of the device
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevicePublicKey {
+    /// Ed25519 public key in PEM format
+    pub pem: String,
+    /// Ed25519 public key as 32 bytes in hex format
+    pub hex: String,
+}
+
+/// # Device public key
+///
+/// Returns the Ed25519 public key of the device in PEM and hex formats. The key is derived from
+/// the DHT private key file, and it allows peers and applications to verify messages signed by
+/// the device. The private key is never returned.
+///
+/// Like the device info, this endpoint works without an API key, as the public key is not a
+/// secret.
+///
+/// Returns 404 if the private key file does not exist yet.
+#[openapi(tag = "Device")]
+#[get("/device/public_key")]
+pub async fn public_key(state: &State<DeviceState>) -> PublicKeyResponse {
+    match state.public_key() {
+        Ok(public_key) => PublicKeyResponse::Ok(Json(DevicePublicKey {
+            pem: public_key.to_pem(),
+            hex: public_key.hex(),
+        })),
+        Err(error) => match error.kind() {
+            ErrorKind::IoError(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                PublicKeyResponse::NotFound(ErrorResponse::not_found(Some(
+                    "The private key file does not exist yet.",
+                )))
+            }
+            _ => PublicKeyResponse::Error(ErrorResponse::internal_server_error(error.to_string())),
+        },
+    }
+}
+
+/// Possible responses for the public key endpoint
+#[derive(Responder)]
+pub enum PublicKeyResponse {
+    /// 200 OK
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<DevicePublicKey>),
+
+    /// 404 Not Found
+    #[response(status = 404, content_type = "json")]
+    NotFound(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for PublicKeyResponse {
+    /// Generating responses for the public key endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<DevicePublicKey>(), None),
+            (
+                404,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The private key file does not exist yet."),
+            ),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
 /// # Device configuration
 ///
-/// Returns the device settings or 404 if the configuration is not done yet.
+/// Returns the device settings or 409 Conflict if the configuration is not done yet.
 /// Use PUT /device/configuration to set the configuration.
+///
+/// After a factory reset, 410 Gone is returned until the device is restarted.
 #[openapi(tag = "Device")]
 #[get("/device/configuration")]
 pub async fn get_config(
@@ -117,10 +525,11 @@ pub async fn get_config(
     state: &State<DeviceState>,
 ) -> GetConfigResponse {
     match key {
+        Ok(_) if state.pending_reset() => {
+            GetConfigResponse::Gone(ErrorResponse::gone(PENDING_RESET_DESCRIPTION))
+        }
         Ok(_) => match state.get_config() {
-            None => GetConfigResponse::NotFound(ErrorResponse::not_found(Some(
-                "This device has not been configured yet.",
-            ))),
+            None => GetConfigResponse::Conflict(ErrorResponse::not_configured()),
             Some(config) => GetConfigResponse::Ok(Json(config)),
         },
         Err(err) => match err {
@@ -145,9 +554,13 @@ pub enum GetConfigResponse {
     #[response(status = 401, content_type = "json")]
     Unauthorized(Json<ErrorResponse>),
 
-    /// 404 Not Found, configuration is not done
-    #[response(status = 404, content_type = "json")]
-    NotFound(Json<ErrorResponse>),
+    /// 409 Conflict, configuration is not done
+    #[response(status = 409, content_type = "json")]
+    Conflict(Json<ErrorResponse>),
+
+    /// 410 Gone, factory reset is waiting for a restart
+    #[response(status = 410, content_type = "json")]
+    Gone(Json<ErrorResponse>),
 }
 
 impl OpenApiResponderInner for GetConfigResponse {
@@ -158,9 +571,14 @@ impl OpenApiResponderInner for GetConfigResponse {
             (400, gen.json_schema::<ErrorResponse>(), None),
             (401, gen.json_schema::<ErrorResponse>(), None),
             (
-                404,
+                409,
+                gen.json_schema::<ErrorResponse>(),
+                Some(NOT_CONFIGURED_DESCRIPTION),
+            ),
+            (
+                410,
                 gen.json_schema::<ErrorResponse>(),
-                Some("This device has not been configured yet."),
+                Some(PENDING_RESET_DESCRIPTION),
             ),
         ])
     }
@@ -168,96 +586,560 @@ impl OpenApiResponderInner for GetConfigResponse {
 
 /// # Set device configuration
 ///
-/// The device settings are sent in JSON format in the body of the message. After this, the device
-/// must be restarted using the `/commands/restart` endpoint.
+/// The device settings are sent in JSON format in the body of the message. If the DHT shared key,
+/// locale, or vendor settings changed, the device must be restarted using the
+/// `/commands/restart` endpoint. Until then, the `/device/reboot_required` endpoint reports that a
+/// reboot is required. Changing only the name or time zone does not require a reboot.
+///
+/// The optional `timezone` must be an IANA time zone name like `Europe/Rome`, and the optional
+/// `locale` a locale name like `it_IT.UTF-8`. When the time zone changes, the `apply_timezone.sh`
+/// script is run with the new time zone if the device has one. The optional `description` is
+/// free text of at most 256 characters, like the location or purpose of the device.
+///
+/// Whitespace around the device name is removed before saving. The name must not be empty or
+/// contain control characters, and it can be at most 64 bytes long when encoded as UTF-8. Names
+/// with multibyte characters therefore fit fewer characters. Invalid configuration is answered
+/// with 400 Bad Request.
+///
+/// On success, the stored configuration is returned so that the application can see the values
+/// without making another request. The response is the same as from the GET endpoint, so it
+/// includes the DHT shared key that the application has just sent.
+///
+/// After a factory reset, 410 Gone is returned until the device is restarted. If the disk of the
+/// SIFIS-Home directory has less than 1 MiB of free space, the configuration is not saved, and 507
+/// Insufficient Storage is returned. If another request keeps the configuration locked for more
+/// than half a second, 503 Service Unavailable is returned.
 #[openapi(tag = "Device")]
 #[put("/device/configuration", data = "<config>")]
 pub async fn set_config(
     key: Result<ApiKey, ApiKeyError>,
     state: &State<DeviceState>,
-    config: Json<DeviceConfig>,
-) -> GenericResponse {
+    config: Result<Json<DeviceConfig>, json::Error<'_>>,
+) -> SetConfigResponse {
     match key {
-        Ok(_) => match BusyGuard::try_busy(state, "Saving device configuration.") {
-            Ok(_) => match state.set_config(Some(config.0)) {
-                Ok(_) => GenericResponse::Ok(OkResponse::message("Configuration saved.")),
+        Ok(_) if state.pending_reset() => {
+            SetConfigResponse::Gone(ErrorResponse::gone(PENDING_RESET_DESCRIPTION))
+        }
+        Ok(_) => {
+            let config = match check_config(config) {
+                Ok(config) => config,
                 Err(error) => {
-                    GenericResponse::Error(ErrorResponse::internal_server_error(error.to_string()))
+                    return SetConfigResponse::BadRequest(ErrorResponse::bad_request(Some(&error)))
                 }
-            },
-            Err(busy) => GenericResponse::Busy(ErrorResponse::service_unavailable(busy)),
-        },
+            };
+            if let Some(free_space) = state.free_space_for(state.home_path()) {
+                if free_space < MIN_FREE_SPACE {
+                    return SetConfigResponse::InsufficientStorage(
+                        ErrorResponse::insufficient_storage(format!(
+                            "Only {free_space} bytes are free, at least {MIN_FREE_SPACE} are needed."
+                        )),
+                    );
+                }
+            }
+            match BusyGuard::try_busy(state, "Saving device configuration.") {
+                Ok(_) => {
+                    let old_timezone = state
+                        .try_get_config()
+                        .and_then(|old| old.timezone().map(String::from));
+                    match state
+                        .try_set_config(Some(config.clone()), CONFIG_LOCK_WAIT)
+                        .await
+                    {
+                        Ok(()) => {}
+                        Err(SetConfigError::Locked) => {
+                            return SetConfigResponse::Busy(ErrorResponse::service_unavailable(
+                                "The device configuration is being saved by another request.",
+                            ))
+                        }
+                        Err(SetConfigError::Failed(error)) => {
+                            return SetConfigResponse::Error(ErrorResponse::internal_server_error(
+                                error,
+                            ))
+                        }
+                    }
+                    match apply_timezone(state, old_timezone.as_deref(), config.timezone()).await {
+                        Ok(_) => SetConfigResponse::Ok(Json(config)),
+                        Err(error) => {
+                            SetConfigResponse::Error(ErrorResponse::internal_server_error(format!(
+                                "The configuration was saved, but applying the time zone failed: {}",
+                                error
+                            )))
+                        }
+                    }
+                }
+                Err(busy) => SetConfigResponse::Busy(ErrorResponse::service_unavailable(busy)),
+            }
+        }
         Err(err) => match err {
-            ApiKeyError::InvalidKey(content) => GenericResponse::BadRequest(content),
-            ApiKeyError::WrongKey(content) => GenericResponse::Unauthorized(content),
+            ApiKeyError::InvalidKey(content) => SetConfigResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => SetConfigResponse::Unauthorized(content),
         },
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::api_common::ErrorResponse;
-    use crate::api_v1::tests_common::{
-        api_key_header, create_test_config, create_test_setup, test_invalid_auth_get,
+/// Run the `apply_timezone.sh` script if the time zone changed
+///
+/// The new time zone is given to the script as the first argument. The script is optional, so a
+/// device without it only stores the time zone in the configuration.
+async fn apply_timezone(
+    state: &DeviceState,
+    old_timezone: Option<&str>,
+    new_timezone: Option<&str>,
+) -> Result<(), ScriptError> {
+    let timezone = match new_timezone {
+        Some(timezone) if Some(timezone) != old_timezone => timezone,
+        _ => return Ok(()),
     };
-    use crate::device_status::DeviceStatus;
-    use crate::state::DeviceState;
-    use mobile_api::configs::DeviceConfig;
-    use rocket::http::{ContentType, Header, Status};
-    use rocket::local::blocking::Client;
-    use serde::Deserialize;
-    use uuid::Uuid;
-
-    #[derive(Deserialize)]
-    pub struct DeviceInfoTest {
-        product_name: String,
-        uuid: Uuid,
+    match run_script_with_args(state, "apply_timezone.sh", &[timezone]).await {
+        Err(ScriptError::NotFound { .. }) => Ok(()),
+        result => result,
     }
+}
 
-    // Test ignored for Miri because the server has time and io-related
-    // functions that are not available in isolation mode
-    #[cfg_attr(miri, ignore)]
-    #[test]
-    fn test_info() {
-        let uri = "/v1/device/info";
-        let (_test_dir, client) = create_test_setup();
+/// Read the configuration from the request body and check it
+///
+/// Returns the message for the 400 response if the body is not an acceptable configuration.
+pub(crate) fn check_config(
+    config: Result<Json<DeviceConfig>, json::Error<'_>>,
+) -> Result<DeviceConfig, String> {
+    let config = config.map_err(|error| error.to_string())?.0;
+    config.validate().map_err(|error| error.to_string())?;
+    Ok(config)
+}
 
-        let response = client.get(uri).dispatch();
-        assert_eq!(response.status(), Status::Ok);
+/// Possible responses for the configuration PUT endpoint
+#[derive(Responder)]
+pub enum SetConfigResponse {
+    /// 200 OK, configuration was saved
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<DeviceConfig>),
 
-        let device_info_reply = response.into_json::<DeviceInfoTest>().unwrap();
-        let device_info = client
-            .rocket()
-            .state::<DeviceState>()
-            .unwrap()
-            .device_info();
-        assert_eq!(device_info.product_name(), device_info_reply.product_name);
-        assert_eq!(device_info.uuid(), &device_info_reply.uuid);
-    }
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
 
-    // Test ignored for Miri because the server has time and io-related
-    // functions that are not available in isolation mode
-    #[cfg_attr(miri, ignore)]
-    #[test]
-    fn test_status() {
-        let uri = "/v1/device/status";
-        let (_test_dir, client) = create_test_setup();
-        test_invalid_auth_get(&client, uri);
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
 
-        let response = client.get(uri).header(api_key_header()).dispatch();
-        assert_eq!(response.status(), Status::Ok);
+    /// 410 Gone, factory reset is waiting for a restart
+    #[response(status = 410, content_type = "json")]
+    Gone(Json<ErrorResponse>),
 
-        let device_status = response.into_json::<DeviceStatus>();
-        assert!(device_status.is_some());
-    }
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
 
-    // Test ignored for Miri because the server has time and io-related
-    // functions that are not available in isolation mode
-    #[cfg_attr(miri, ignore)]
-    #[test]
-    fn test_configuration() {
+    /// 503 Service Unavailable
+    #[response(status = 503, content_type = "json")]
+    Busy(BusyResponse),
+
+    /// 507 Insufficient Storage, the disk is too full for saving
+    #[response(status = 507, content_type = "json")]
+    InsufficientStorage(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for SetConfigResponse {
+    /// Generating responses for the configuration PUT endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (
+                200,
+                gen.json_schema::<DeviceConfig>(),
+                Some("The configuration was saved."),
+            ),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+            (
+                410,
+                gen.json_schema::<ErrorResponse>(),
+                Some(PENDING_RESET_DESCRIPTION),
+            ),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+            (503, gen.json_schema::<ErrorResponse>(), None),
+            (507, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+/// Result of the configuration validation
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigValidation {
+    /// Always true, problems are reported with the 400 response
+    pub valid: bool,
+}
+
+/// # Validate device configuration
+///
+/// Checks the configuration in the body the same way as the PUT `/device/configuration`
+/// endpoint, but does not save it. The application can use this to give feedback before the
+/// user commits the configuration.
+///
+/// Returns `{"valid": true}` for an acceptable configuration, and 400 Bad Request with the
+/// problem in the description otherwise.
+#[openapi(tag = "Device")]
+#[post("/device/configuration/validate", data = "<config>")]
+pub async fn validate_config(
+    key: Result<ApiKey, ApiKeyError>,
+    config: Result<Json<DeviceConfig>, json::Error<'_>>,
+) -> ValidateConfigResponse {
+    match key {
+        Ok(_) => match check_config(config) {
+            Ok(_) => ValidateConfigResponse::Ok(Json(ConfigValidation { valid: true })),
+            Err(error) => {
+                ValidateConfigResponse::BadRequest(ErrorResponse::bad_request(Some(&error)))
+            }
+        },
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => ValidateConfigResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => ValidateConfigResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// Possible responses for the configuration validation endpoint
+#[derive(Responder)]
+pub enum ValidateConfigResponse {
+    /// 200 OK, configuration is valid
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<ConfigValidation>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for ValidateConfigResponse {
+    /// Generating responses for the configuration validation endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<ConfigValidation>(), None),
+            (
+                400,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The configuration is not valid, or the API key is invalid."),
+            ),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+/// Result of the DHT key rotation
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DhtKeyRotated {
+    /// Fingerprint of the new shared DHT key, the key itself is not returned
+    pub fingerprint: String,
+}
+
+/// # Rotate DHT shared key
+///
+/// Generates a new shared DHT key and saves it to the configuration. The new key is used after
+/// the device is restarted, so the `/device/reboot_required` endpoint reports that a reboot is
+/// required. The other devices in the network need the same key, so the rotation must be
+/// coordinated with them.
+///
+/// Returns the fingerprint of the new key, or 409 Conflict if the device has not been configured
+/// yet.
+/// After a factory reset, 410 Gone is returned until the device is restarted.
+#[openapi(tag = "Device")]
+#[post("/device/dht_key/rotate")]
+pub async fn rotate_dht_key(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+) -> RotateDhtKeyResponse {
+    match key {
+        Ok(_) if state.pending_reset() => {
+            RotateDhtKeyResponse::Gone(ErrorResponse::gone(PENDING_RESET_DESCRIPTION))
+        }
+        Ok(_) => match BusyGuard::try_busy(state, "Rotating DHT key.") {
+            Ok(_) => {
+                let mut config = match state.get_config() {
+                    Some(config) => config,
+                    None => return RotateDhtKeyResponse::Conflict(ErrorResponse::not_configured()),
+                };
+                let new_key = match state.srng().generate_key() {
+                    Ok(new_key) => new_key,
+                    Err(error) => {
+                        return RotateDhtKeyResponse::Error(ErrorResponse::internal_server_error(
+                            error.to_string(),
+                        ))
+                    }
+                };
+                config.set_dht_shared_key(new_key);
+
+                // Changing the key marks the device as requiring a reboot
+                match state.set_config(Some(config)) {
+                    Ok(_) => RotateDhtKeyResponse::Ok(Json(DhtKeyRotated {
+                        fingerprint: new_key.fingerprint(),
+                    })),
+                    Err(error) => RotateDhtKeyResponse::Error(
+                        ErrorResponse::internal_server_error(error.to_string()),
+                    ),
+                }
+            }
+            Err(busy) => RotateDhtKeyResponse::Busy(ErrorResponse::service_unavailable(busy)),
+        },
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => RotateDhtKeyResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => RotateDhtKeyResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// Possible responses for the DHT key rotation endpoint
+#[derive(Responder)]
+pub enum RotateDhtKeyResponse {
+    /// 200 OK, the key was rotated
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<DhtKeyRotated>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+
+    /// 409 Conflict, configuration is not done
+    #[response(status = 409, content_type = "json")]
+    Conflict(Json<ErrorResponse>),
+
+    /// 410 Gone, factory reset is waiting for a restart
+    #[response(status = 410, content_type = "json")]
+    Gone(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
+
+    /// 503 Service Unavailable
+    #[response(status = 503, content_type = "json")]
+    Busy(BusyResponse),
+}
+
+impl OpenApiResponderInner for RotateDhtKeyResponse {
+    /// Generating responses for the DHT key rotation endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<DhtKeyRotated>(), None),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+            (
+                409,
+                gen.json_schema::<ErrorResponse>(),
+                Some(NOT_CONFIGURED_DESCRIPTION),
+            ),
+            (
+                410,
+                gen.json_schema::<ErrorResponse>(),
+                Some(PENDING_RESET_DESCRIPTION),
+            ),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+            (503, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BootCount, ConfigValidation, DevicePublicKey, DhtKeyRotated, RebootRequired,
+        DEFAULT_TOP_PROCESSES, PENDING_RESET_DESCRIPTION,
+    };
+    use crate::api_common::{
+        ErrorResponse, DEFAULT_RETRY_AFTER_SECONDS, NOT_CONFIGURED_DESCRIPTION,
+    };
+    use crate::api_v1::tests_common::{
+        api_key_header_for, create_test_config, create_test_setup, test_invalid_auth_get,
+        test_invalid_auth_post,
+    };
+    use crate::device_status::{DeviceStatus, ProcessStatus};
+    use crate::state::{BusyGuard, DeviceState};
+    use mobile_api::configs::{DeviceConfig, DEVICE_NAME_MAX_BYTES};
+    use mobile_api::SifisHome;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::{Client, LocalResponse};
+    use serde::Deserialize;
+    use serde_json::{Map, Value};
+    use uuid::Uuid;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct DeviceInfoTest {
+        product_name: String,
+        uuid: Uuid,
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_info() {
+        let uri = "/v1/device/info";
+        let (_test_dir, client) = create_test_setup();
+
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let device_info_reply = response.into_json::<DeviceInfoTest>().unwrap();
+        let device_info = client
+            .rocket()
+            .state::<DeviceState>()
+            .unwrap()
+            .device_info()
+            .unwrap();
+        assert_eq!(device_info.product_name(), device_info_reply.product_name);
+        assert_eq!(device_info.uuid(), &device_info_reply.uuid);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_status() {
+        let uri = "/v1/device/status";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        test_invalid_auth_get(&client, uri);
+
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let device_status = response.into_json::<DeviceStatus>();
+        assert!(device_status.is_some());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_status_msgpack() {
+        let uri = "/v1/device/status";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+
+        // JSON by default, and when preferred
+        for accept in [None, Some("application/json"), Some("*/*")] {
+            let mut request = client.get(uri).header(api_key_header.clone());
+            if let Some(accept) = accept {
+                request = request.header(Header::new("Accept", accept));
+            }
+            let response = request.dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.content_type(), Some(ContentType::JSON));
+            assert!(response.into_json::<DeviceStatus>().is_some());
+        }
+
+        // MessagePack when preferred
+        for accept in [
+            "application/msgpack",
+            "application/x-msgpack",
+            "application/json; q=0.5, application/msgpack",
+        ] {
+            let response = client
+                .get(uri)
+                .header(api_key_header.clone())
+                .header(Header::new("Accept", accept))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok, "{accept}");
+            assert_eq!(response.content_type(), Some(ContentType::MsgPack));
+            let bytes = response.into_bytes().unwrap();
+            let status = rmp_serde::from_slice::<DeviceStatus>(&bytes).unwrap();
+            assert!(status.logical_cpus > 0);
+        }
+
+        // Selected sections work too
+        let response = client
+            .get("/v1/device/status?fields=load")
+            .header(api_key_header.clone())
+            .header(Header::new("Accept", "application/msgpack"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::MsgPack));
+        let bytes = response.into_bytes().unwrap();
+        let status = rmp_serde::from_slice::<Map<String, Value>>(&bytes).unwrap();
+        assert_eq!(
+            status.keys().collect::<Vec<_>>(),
+            vec!["loadAverage", "processCount"]
+        );
+
+        // Errors are still JSON
+        let response = client
+            .get("/v1/device/status?fields=gpu")
+            .header(api_key_header.clone())
+            .header(Header::new("Accept", "application/msgpack"))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_status_fields() {
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+
+        let response = client
+            .get("/v1/device/status?fields=cpu,mem")
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let status = response.into_json::<Map<String, Value>>().unwrap();
+        assert!(status.contains_key("cpuUsage"));
+        assert!(status.contains_key("logicalCpus"));
+        assert!(status.contains_key("cpuInfo"));
+        assert!(status.contains_key("memUsage"));
+        for omitted in [
+            "disks",
+            "uptime",
+            "bootTimeUnix",
+            "loadAverage",
+            "processCount",
+        ] {
+            assert!(!status.contains_key(omitted), "{omitted}");
+        }
+
+        let response = client
+            .get("/v1/device/status?fields=load")
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let status = response.into_json::<Map<String, Value>>().unwrap();
+        assert_eq!(
+            status.keys().collect::<Vec<_>>(),
+            vec!["loadAverage", "processCount"]
+        );
+
+        // Unknown names are rejected
+        let response = client
+            .get("/v1/device/status?fields=cpu,gpu")
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(
+            error_response.error.description,
+            "Unknown status field `gpu`."
+        );
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_configuration() {
         let uri = "/v1/device/configuration";
         let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
         test_invalid_auth_get(&client, uri);
 
         // We need to test PUT method for invalid authentication too
@@ -266,23 +1148,410 @@ mod tests {
         test_invalid_auth_put(&client, uri, &test_config_json);
 
         // Should not have config yet
-        let response = client.get(uri).header(api_key_header()).dispatch();
-        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(error_response.error.description, NOT_CONFIGURED_DESCRIPTION);
 
         // Sending test configuration
         let response = client
             .put(uri)
-            .header(api_key_header())
+            .header(api_key_header.clone())
             .header(ContentType::JSON)
             .body(test_config_json)
             .dispatch();
         assert_eq!(response.status(), Status::Ok);
+        let put_config = response.into_json::<DeviceConfig>().unwrap();
+        assert_eq!(put_config, test_config);
 
         // Should have the same config now
-        let response = client.get(uri).header(api_key_header()).dispatch();
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
         assert_eq!(response.status(), Status::Ok);
         let config = response.into_json::<DeviceConfig>().unwrap();
         assert_eq!(config, test_config);
+
+        // Name is trimmed, and the PUT response should match what GET returns
+        let padded_config_json = format!(
+            r#"{{"name":"  Padded name \t","dht_shared_key":"{}"}}"#,
+            test_config.dht_shared_key()
+        );
+        let response = client
+            .put(uri)
+            .header(api_key_header.clone())
+            .header(ContentType::JSON)
+            .body(padded_config_json)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let put_config = response.into_json::<DeviceConfig>().unwrap();
+        assert_eq!(put_config.name(), "Padded name");
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        let config = response.into_json::<DeviceConfig>().unwrap();
+        assert_eq!(config, put_config);
+
+        // Too long name should be rejected and the saved config should stay the same
+        let long_config_json = format!(
+            r#"{{"name":"{}","dht_shared_key":"{}"}}"#,
+            "ä".repeat(DEVICE_NAME_MAX_BYTES / 2 + 1),
+            test_config.dht_shared_key()
+        );
+        let response = client
+            .put(uri)
+            .header(api_key_header.clone())
+            .header(ContentType::JSON)
+            .body(long_config_json)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert!(error_response
+            .error
+            .description
+            .contains("device name is too long"));
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        let config = response.into_json::<DeviceConfig>().unwrap();
+        assert_eq!(config, put_config);
+
+        // Time zone, locale, and description are stored, the apply script is optional
+        let mut zoned_config = test_config.clone();
+        zoned_config.set_timezone(Some("Europe/Rome".to_string()));
+        zoned_config.set_locale(Some("it_IT.UTF-8".to_string()));
+        zoned_config.set_description(Some("Living room".to_string()));
+        let response = client
+            .put(uri)
+            .header(api_key_header.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&zoned_config).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        let config = response.into_json::<DeviceConfig>().unwrap();
+        assert_eq!(config, zoned_config);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_reboot_required() {
+        let uri = "/v1/device/reboot_required";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        test_invalid_auth_get(&client, uri);
+
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let reply = response.into_json::<RebootRequired>().unwrap();
+        assert!(!reply.reboot_required);
+
+        // Setting configuration flips the flag
+        let response = client
+            .put("/v1/device/configuration")
+            .header(api_key_header.clone())
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&create_test_config()).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        let reply = response.into_json::<RebootRequired>().unwrap();
+        assert!(reply.reboot_required);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_validate_configuration() {
+        let uri = "/v1/device/configuration/validate";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        let test_config = create_test_config();
+        let test_config_json = serde_json::to_string(&test_config).unwrap();
+        test_invalid_auth_post(&client, uri);
+
+        // Validation works while busy, and nothing is saved
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        let _guard = BusyGuard::try_busy(state, "Testing validation").unwrap();
+        let response = client
+            .post(uri)
+            .header(api_key_header.clone())
+            .header(ContentType::JSON)
+            .body(&test_config_json)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_json::<ConfigValidation>().unwrap().valid);
+        assert!(state.get_config().is_none());
+        assert!(!state.reboot_required());
+
+        // Each problem should be reported
+        let key = test_config.dht_shared_key();
+        let null_key = "0".repeat(64);
+        let invalid_configs = [
+            (
+                format!(r#"{{"name":"","dht_shared_key":"{key}"}}"#),
+                "device name is empty",
+            ),
+            (
+                format!(
+                    r#"{{"name":"{}","dht_shared_key":"{key}"}}"#,
+                    "a".repeat(65)
+                ),
+                "device name is too long",
+            ),
+            (
+                format!(r#"{{"name":"Line\nbreak","dht_shared_key":"{key}"}}"#),
+                "device name contains control characters",
+            ),
+            (
+                format!(r#"{{"name":"Test","dht_shared_key":"{null_key}"}}"#),
+                "DHT shared key is null",
+            ),
+            (
+                format!(
+                    r#"{{"name":"Test","dht_shared_key":"{key}","timezone":"Europe/Nowhere"}}"#
+                ),
+                "unknown time zone",
+            ),
+            (
+                format!(r#"{{"name":"Test","dht_shared_key":"{key}","locale":"en US"}}"#),
+                "locale contains invalid characters",
+            ),
+            (
+                format!(
+                    r#"{{"name":"Test","dht_shared_key":"{key}","description":"{}"}}"#,
+                    "a".repeat(257)
+                ),
+                "description is too long",
+            ),
+            (r#"{"name":"Test","dht_shared_key":"1234"}"#.to_string(), ""),
+            (r#"{"name":"Test"}"#.to_string(), "dhtSharedKey"),
+            ("not json".to_string(), ""),
+        ];
+        for (body, problem) in invalid_configs {
+            let response = client
+                .post(uri)
+                .header(api_key_header.clone())
+                .header(ContentType::JSON)
+                .body(&body)
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest, "{body}");
+            let error_response = response.into_json::<ErrorResponse>().unwrap();
+            assert!(
+                error_response.error.description.contains(problem),
+                "{} does not contain {problem}",
+                error_response.error.description
+            );
+        }
+        assert!(state.get_config().is_none());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_configuration_busy() {
+        let uri = "/v1/device/configuration";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        let test_config_json = serde_json::to_string(&create_test_config()).unwrap();
+
+        // Saving is refused while the server is busy with something else
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        let busy_reason = "Testing busy response";
+        let guard = BusyGuard::try_busy(state, busy_reason).unwrap();
+        let response = client
+            .put(uri)
+            .header(api_key_header.clone())
+            .header(ContentType::JSON)
+            .body(&test_config_json)
+            .dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(
+            response.headers().get_one("Retry-After"),
+            Some(DEFAULT_RETRY_AFTER_SECONDS.to_string().as_str())
+        );
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(error_response.error.code, 503);
+        assert_eq!(error_response.error.description, busy_reason);
+
+        // Works again when the server is free
+        drop(guard);
+        let response = client
+            .put(uri)
+            .header(api_key_header.clone())
+            .header(ContentType::JSON)
+            .body(&test_config_json)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("Retry-After").is_none());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_boot_count() {
+        let uri = "/v1/device/boot_count";
+        let (test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        test_invalid_auth_get(&client, uri);
+        let get_boot_count = || {
+            let response = client.get(uri).header(api_key_header.clone()).dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            response.into_json::<BootCount>().unwrap().boot_count
+        };
+        assert_eq!(get_boot_count(), 0);
+
+        // Simulated startups
+        let sifis_home = SifisHome::new_with_path(test_dir.path().join("sifis-home"));
+        sifis_home.increment_boot_count().unwrap();
+        sifis_home.increment_boot_count().unwrap();
+        assert_eq!(get_boot_count(), 2);
+
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        state.reset_boot_count().unwrap();
+        assert_eq!(get_boot_count(), 0);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_processes() {
+        let uri = "/v1/device/processes";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        test_invalid_auth_get(&client, uri);
+        let get_processes = |uri: &str| {
+            let response = client.get(uri).header(api_key_header.clone()).dispatch();
+            assert_eq!(response.status(), Status::Ok, "{uri}");
+            response.into_json::<Vec<ProcessStatus>>().unwrap()
+        };
+
+        // At least the test itself is running
+        let processes = get_processes(uri);
+        assert!(!processes.is_empty());
+        assert!(processes.len() <= DEFAULT_TOP_PROCESSES);
+        for process in &processes {
+            assert!(!process.name.is_empty());
+            assert!(process.cpu >= 0.0);
+        }
+        assert!(processes.windows(2).all(|pair| pair[0].cpu >= pair[1].cpu));
+
+        // Ordered by memory
+        let processes = get_processes("/v1/device/processes?top=3&by=mem");
+        assert!(!processes.is_empty());
+        assert!(processes.len() <= 3);
+        assert!(processes[0].mem > 0);
+        assert!(processes.windows(2).all(|pair| pair[0].mem >= pair[1].mem));
+        assert_eq!(get_processes("/v1/device/processes?top=1").len(), 1);
+
+        // The test process is counted in the status
+        let response = client
+            .get("/v1/device/status?fields=load")
+            .header(api_key_header.clone())
+            .dispatch();
+        let status = response.into_json::<Map<String, Value>>().unwrap();
+        assert!(status["processCount"].as_u64().unwrap() > 0);
+
+        // Invalid parameters
+        for query in ["top=0", "top=101", "top=many", "by=disk", "top=5&by=gpu"] {
+            let response = client
+                .get(format!("{uri}?{query}"))
+                .header(api_key_header.clone())
+                .dispatch();
+            assert_eq!(response.status(), Status::BadRequest, "{query}");
+        }
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_public_key() {
+        let uri = "/v1/device/public_key";
+        let (test_dir, client) = create_test_setup();
+
+        // The key file does not exist yet
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(
+            error_response.error.description,
+            "The private key file does not exist yet."
+        );
+
+        // The key is derived from the private key file, and works without an API key
+        let sifis_home = SifisHome::new_with_path(test_dir.path().join("sifis-home"));
+        let key_file = sifis_home.ensure_private_key().unwrap();
+        let private_pem = std::fs::read_to_string(key_file).unwrap();
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.into_string().unwrap();
+        assert!(!body.contains("PRIVATE"));
+        let public_key = serde_json::from_str::<DevicePublicKey>(&body).unwrap();
+        let expected = sifis_home.load_public_key().unwrap();
+        assert_eq!(public_key.hex, expected.hex());
+        assert_eq!(public_key.pem, expected.to_pem());
+        assert!(public_key.pem.starts_with("-----BEGIN PUBLIC KEY-----\n"));
+        for line in private_pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+        {
+            assert!(!body.contains(line));
+        }
+
+        // Damaged key file
+        std::fs::write(sifis_home.private_key_file_path(), "not a key").unwrap();
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_configuration_pending_reset() {
+        let uri = "/v1/device/configuration";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        let test_config = create_test_config();
+        let test_config_json = serde_json::to_string(&test_config).unwrap();
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        state.set_config(Some(test_config.clone())).unwrap();
+        state.set_pending_reset(true);
+
+        fn check_gone(response: LocalResponse<'_>) {
+            assert_eq!(response.status(), Status::Gone);
+            let error_response = response.into_json::<ErrorResponse>().unwrap();
+            assert_eq!(error_response.error.code, 410);
+            assert_eq!(error_response.error.reason, "Gone");
+            assert_eq!(error_response.error.description, PENDING_RESET_DESCRIPTION);
+        }
+        check_gone(client.get(uri).header(api_key_header.clone()).dispatch());
+        check_gone(
+            client
+                .put(uri)
+                .header(api_key_header.clone())
+                .header(ContentType::JSON)
+                .body(&test_config_json)
+                .dispatch(),
+        );
+        check_gone(
+            client
+                .post("/v1/device/dht_key/rotate")
+                .header(api_key_header.clone())
+                .dispatch(),
+        );
+        assert_eq!(state.get_config(), Some(test_config.clone()));
+
+        // API key is still checked first
+        let response = client.get(uri).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Configuration is available again after the flag is cleared
+        state.set_pending_reset(false);
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_json::<DeviceConfig>().unwrap(), test_config);
     }
 
     fn test_invalid_auth_put(client: &Client, uri: &str, body: &str) {
@@ -327,4 +1596,50 @@ mod tests {
             "The request requires user authentication."
         );
     }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_rotate_dht_key() {
+        let uri = "/v1/device/dht_key/rotate";
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        test_invalid_auth_post(&client, uri);
+        let state = client.rocket().state::<DeviceState>().unwrap();
+
+        // Configuration is required
+        let response = client.post(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(error_response.error.description, NOT_CONFIGURED_DESCRIPTION);
+        assert!(!state.reboot_required());
+
+        // The key changes, and nothing else
+        let old_config = create_test_config();
+        state.set_config(Some(old_config.clone())).unwrap();
+        state.set_reboot_required(false).unwrap();
+        let response = client.post(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.into_string().unwrap();
+        let rotated = serde_json::from_str::<DhtKeyRotated>(&body).unwrap();
+        let new_config = state.get_config().unwrap();
+        assert_ne!(new_config.dht_shared_key(), old_config.dht_shared_key());
+        assert!(!new_config.dht_shared_key().is_null());
+        assert_eq!(new_config.name(), old_config.name());
+        assert_eq!(
+            rotated.fingerprint,
+            new_config.dht_shared_key().fingerprint()
+        );
+        assert!(state.reboot_required());
+
+        // The key itself is not revealed
+        assert!(!body.contains(&new_config.dht_shared_key().to_string()));
+
+        // Busy server refuses to rotate
+        let _guard = BusyGuard::try_busy(state, "Testing rotation").unwrap();
+        let response = client.post(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(state.get_config().unwrap(), new_config);
+    }
 }
@@ -0,0 +1,171 @@
+//! Endpoints for Checking the Server Health
+//!
+//! These endpoints do not need the API key, so they must not reveal anything secret.
+
+use crate::api_common::make_json_responses;
+use crate::state::DeviceState;
+use mobile_api::security::{get_unix_time_ms, is_clock_likely_unset};
+use rocket::serde::json::Json;
+use rocket::{get, Responder, State};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::openapi;
+use rocket_okapi::response::OpenApiResponderInner;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Readiness of the device subsystems
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    /// True when all subsystems are ready
+    pub ready: bool,
+    /// Subsystems that are not ready: `device_info`, `private_key`, `configuration`, or `scripts`
+    pub missing: Vec<String>,
+}
+
+/// # Device readiness
+///
+/// Returns 200 OK when the device is provisioned and configured, the private key is present, and
+/// the command scripts are found. Otherwise, 503 Service Unavailable is returned with the list of
+/// missing subsystems.
+///
+/// This endpoint does not require the API key.
+#[openapi(tag = "Health")]
+#[get("/health/ready")]
+pub async fn ready(state: &State<DeviceState>) -> ReadyResponse {
+    let missing: Vec<String> = state
+        .missing_subsystems()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let readiness = Readiness {
+        ready: missing.is_empty(),
+        missing,
+    };
+    if readiness.ready {
+        ReadyResponse::Ready(Json(readiness))
+    } else {
+        ReadyResponse::NotReady(Json(readiness))
+    }
+}
+
+/// System clock of the device
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemTime {
+    /// Current time in milliseconds since the UNIX epoch
+    #[serde(alias = "unix_time_ms")]
+    pub unix_time_ms: u64,
+    /// True when the time is before 2023, which means that the clock has likely not been set
+    #[serde(alias = "clock_likely_unset")]
+    pub clock_likely_unset: bool,
+}
+
+/// # Device time
+///
+/// Returns the current time of the device. Devices without a real-time clock may start at the
+/// UNIX epoch, so `clock_likely_unset` tells when the time should not be trusted yet.
+///
+/// This endpoint does not require the API key.
+#[openapi(tag = "Health")]
+#[get("/time")]
+pub async fn time() -> Json<SystemTime> {
+    // Time before the epoch is reported as the epoch
+    let unix_time_ms = get_unix_time_ms().unwrap_or_default();
+    Json(SystemTime {
+        unix_time_ms: unix_time_ms as u64,
+        clock_likely_unset: is_clock_likely_unset(unix_time_ms),
+    })
+}
+
+/// Possible responses for the readiness endpoint
+#[derive(Responder)]
+pub enum ReadyResponse {
+    /// 200 OK, all subsystems are ready
+    #[response(status = 200, content_type = "json")]
+    Ready(Json<Readiness>),
+
+    /// 503 Service Unavailable, some subsystems are not ready
+    #[response(status = 503, content_type = "json")]
+    NotReady(Json<Readiness>),
+}
+
+impl OpenApiResponderInner for ReadyResponse {
+    /// Generating responses for the readiness endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (
+                200,
+                gen.json_schema::<Readiness>(),
+                Some("All subsystems are ready."),
+            ),
+            (
+                503,
+                gen.json_schema::<Readiness>(),
+                Some("Some subsystems are not ready."),
+            ),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_v1::tests_common::*;
+    use crate::build_rocket;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_ready() {
+        let (_test_dir, sifis_home) = create_test_sifis_home();
+        sifis_home.ensure_private_key().unwrap();
+        let state = DeviceState::new(sifis_home).unwrap();
+        state.set_config(Some(create_test_config())).unwrap();
+        let client = Client::tracked(build_rocket(state)).unwrap();
+
+        let response = client.get("/v1/health/ready").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let readiness = response.into_json::<Readiness>().unwrap();
+        assert!(readiness.ready);
+        assert!(readiness.missing.is_empty());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_ready_without_private_key() {
+        let (_test_dir, state) = create_test_state();
+        state.set_config(Some(create_test_config())).unwrap();
+        let client = Client::tracked(build_rocket(state)).unwrap();
+
+        let response = client.get("/v1/health/ready").dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        let body = response.into_string().unwrap();
+        assert!(!body.contains(&TEST_AUTH_KEY.to_base64()));
+        let readiness = serde_json::from_str::<Readiness>(&body).unwrap();
+        assert!(!readiness.ready);
+        assert_eq!(readiness.missing, vec!["private_key"]);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_time() {
+        let (_test_dir, client) = create_test_setup();
+        let response = client.get("/v1/time").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let time = response.into_json::<SystemTime>().unwrap();
+        assert!(time.unix_time_ms > 0);
+        assert_eq!(
+            time.clock_likely_unset,
+            is_clock_likely_unset(time.unix_time_ms as u128)
+        );
+    }
+}
@@ -0,0 +1,393 @@
+//! Endpoints for Named Configuration Profiles
+//!
+//! Lab devices switch between configurations, like test and production DHT networks, often.
+//! Instead of sending the full configuration each time, the configurations can be stored as named
+//! profiles, and one of them is activated by copying it to the active configuration.
+
+use crate::api_common::*;
+use crate::api_v1::device::{check_config, MIN_FREE_SPACE, PENDING_RESET_DESCRIPTION};
+use crate::state::{ActivateProfileError, BusyGuard, DeviceState};
+use mobile_api::configs::DeviceConfig;
+use mobile_api::error::ErrorKind;
+use rocket::serde::json::{self, Json};
+use rocket::{delete, get, post, put, Responder, State};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::openapi;
+use rocket_okapi::response::OpenApiResponderInner;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Names of the stored configuration profiles
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigProfiles {
+    /// Profile names in sorted order
+    pub profiles: Vec<String>,
+}
+
+/// Map a profile error to a response
+///
+/// Invalid names are the client's fault, and missing profiles are not found. Anything else is an
+/// internal error.
+fn profile_error_response(error: mobile_api::error::Error) -> ProfilesResponse {
+    match error.kind() {
+        ErrorKind::ProfileNameInvalid(_) => {
+            ProfilesResponse::BadRequest(ErrorResponse::bad_request(Some(&error.to_string())))
+        }
+        ErrorKind::ProfileNotFound(_) => {
+            ProfilesResponse::NotFound(ErrorResponse::not_found(Some(&error.to_string())))
+        }
+        _ => ProfilesResponse::Error(ErrorResponse::internal_server_error(error.to_string())),
+    }
+}
+
+/// Current profile names as the 200 response
+fn profiles_response(state: &DeviceState) -> ProfilesResponse {
+    match state.profiles() {
+        Ok(profiles) => ProfilesResponse::Ok(Json(ConfigProfiles { profiles })),
+        Err(error) => profile_error_response(error),
+    }
+}
+
+/// # List configuration profiles
+///
+/// Returns the names of the stored configuration profiles.
+#[openapi(tag = "Device")]
+#[get("/device/configuration/profiles")]
+pub async fn list_profiles(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+) -> ProfilesResponse {
+    match key {
+        Ok(_) => profiles_response(state),
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => ProfilesResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => ProfilesResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// # Save configuration profile
+///
+/// Stores the configuration in the body as the profile with the *name*, replacing an existing
+/// profile with the same name. The configuration is checked like with the PUT
+/// `/device/configuration` endpoint, but the active configuration is not changed.
+///
+/// The name may have ASCII letters, digits, `-`, and `_`, and it can be at most 32 characters
+/// long. Returns the names of the stored profiles. If the disk of the SIFIS-Home directory has
+/// less than 1 MiB of free space, the profile is not saved, and 507 Insufficient Storage is
+/// returned.
+#[openapi(tag = "Device")]
+#[put("/device/configuration/profiles/<name>", data = "<config>")]
+pub async fn save_profile(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+    name: &str,
+    config: Result<Json<DeviceConfig>, json::Error<'_>>,
+) -> ProfilesResponse {
+    match key {
+        Ok(_) if state.pending_reset() => {
+            ProfilesResponse::Gone(ErrorResponse::gone(PENDING_RESET_DESCRIPTION))
+        }
+        Ok(_) => {
+            let config = match check_config(config) {
+                Ok(config) => config,
+                Err(error) => {
+                    return ProfilesResponse::BadRequest(ErrorResponse::bad_request(Some(&error)))
+                }
+            };
+            if let Some(free_space) = state.free_space_for(state.home_path()) {
+                if free_space < MIN_FREE_SPACE {
+                    return ProfilesResponse::InsufficientStorage(
+                        ErrorResponse::insufficient_storage(format!(
+                            "Only {free_space} bytes are free, at least {MIN_FREE_SPACE} are needed."
+                        )),
+                    );
+                }
+            }
+            match state.save_profile(name, &config) {
+                Ok(_) => profiles_response(state),
+                Err(error) => profile_error_response(error),
+            }
+        }
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => ProfilesResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => ProfilesResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// # Delete configuration profile
+///
+/// Removes the profile with the *name*. The active configuration is not changed, even if it was
+/// activated from this profile. Returns the names of the remaining profiles.
+#[openapi(tag = "Device")]
+#[delete("/device/configuration/profiles/<name>")]
+pub async fn delete_profile(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+    name: &str,
+) -> ProfilesResponse {
+    match key {
+        Ok(_) => match state.remove_profile(name) {
+            Ok(_) => profiles_response(state),
+            Err(error) => profile_error_response(error),
+        },
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => ProfilesResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => ProfilesResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// Possible responses for the profile list, save, and delete endpoints
+#[derive(Responder)]
+pub enum ProfilesResponse {
+    /// 200 OK, the stored profile names
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<ConfigProfiles>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+
+    /// 404 Not Found, the profile does not exist
+    #[response(status = 404, content_type = "json")]
+    NotFound(Json<ErrorResponse>),
+
+    /// 410 Gone, factory reset is waiting for a restart
+    #[response(status = 410, content_type = "json")]
+    Gone(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
+
+    /// 507 Insufficient Storage, the disk is too full for saving
+    #[response(status = 507, content_type = "json")]
+    InsufficientStorage(Json<ErrorResponse>),
+}
+
+impl OpenApiResponderInner for ProfilesResponse {
+    /// Generating responses for the profile list, save, and delete endpoints
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<ConfigProfiles>(), None),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+            (
+                404,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The profile does not exist."),
+            ),
+            (
+                410,
+                gen.json_schema::<ErrorResponse>(),
+                Some(PENDING_RESET_DESCRIPTION),
+            ),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+            (507, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+/// # Activate configuration profile
+///
+/// Copies the profile with the *name* to the active configuration, like sending it to the PUT
+/// `/device/configuration` endpoint. Changes that take effect after a restart are reported by the
+/// `/device/reboot_required` endpoint. Returns the activated configuration.
+#[openapi(tag = "Device")]
+#[post("/device/configuration/profiles/<name>/activate")]
+pub async fn activate_profile(
+    key: Result<ApiKey, ApiKeyError>,
+    state: &State<DeviceState>,
+    name: &str,
+) -> ActivateProfileResponse {
+    match key {
+        Ok(_) if state.pending_reset() => {
+            ActivateProfileResponse::Gone(ErrorResponse::gone(PENDING_RESET_DESCRIPTION))
+        }
+        Ok(_) => match BusyGuard::try_busy(state, "Activating configuration profile.") {
+            Ok(_) => match state.activate_profile(name) {
+                Ok(config) => ActivateProfileResponse::Ok(Json(config)),
+                Err(ActivateProfileError::Load(error)) => match error.kind() {
+                    ErrorKind::ProfileNameInvalid(_) => ActivateProfileResponse::BadRequest(
+                        ErrorResponse::bad_request(Some(&error.to_string())),
+                    ),
+                    ErrorKind::ProfileNotFound(_) => ActivateProfileResponse::NotFound(
+                        ErrorResponse::not_found(Some(&error.to_string())),
+                    ),
+                    _ => ActivateProfileResponse::Error(ErrorResponse::internal_server_error(
+                        error.to_string(),
+                    )),
+                },
+                Err(ActivateProfileError::Save(error)) => {
+                    ActivateProfileResponse::Error(ErrorResponse::internal_server_error(error))
+                }
+            },
+            Err(busy) => ActivateProfileResponse::Busy(ErrorResponse::service_unavailable(busy)),
+        },
+        Err(err) => match err {
+            ApiKeyError::InvalidKey(content) => ActivateProfileResponse::BadRequest(content),
+            ApiKeyError::WrongKey(content) => ActivateProfileResponse::Unauthorized(content),
+        },
+    }
+}
+
+/// Possible responses for the profile activation endpoint
+#[derive(Responder)]
+pub enum ActivateProfileResponse {
+    /// 200 OK, the profile is the active configuration
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<DeviceConfig>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+
+    /// 404 Not Found, the profile does not exist
+    #[response(status = 404, content_type = "json")]
+    NotFound(Json<ErrorResponse>),
+
+    /// 410 Gone, factory reset is waiting for a restart
+    #[response(status = 410, content_type = "json")]
+    Gone(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
+
+    /// 503 Service Unavailable
+    #[response(status = 503, content_type = "json")]
+    Busy(BusyResponse),
+}
+
+impl OpenApiResponderInner for ActivateProfileResponse {
+    /// Generating responses for the profile activation endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (
+                200,
+                gen.json_schema::<DeviceConfig>(),
+                Some("The profile was activated."),
+            ),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (401, gen.json_schema::<ErrorResponse>(), None),
+            (
+                404,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The profile does not exist."),
+            ),
+            (
+                410,
+                gen.json_schema::<ErrorResponse>(),
+                Some(PENDING_RESET_DESCRIPTION),
+            ),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+            (503, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_v1::tests_common::*;
+    use mobile_api::security::SecurityKey;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::blocking::Client;
+
+    fn put_profile(client: &Client, name: &str, config: &DeviceConfig) -> Status {
+        client
+            .put(format!("/v1/device/configuration/profiles/{name}"))
+            .header(api_key_header_for(client.rocket().state().unwrap()))
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(config).unwrap())
+            .dispatch()
+            .status()
+    }
+
+    fn list_profiles(client: &Client) -> Vec<String> {
+        let response = client
+            .get("/v1/device/configuration/profiles")
+            .header(api_key_header_for(client.rocket().state().unwrap()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<ConfigProfiles>().unwrap().profiles
+    }
+
+    fn activate(client: &Client, name: &str) -> Status {
+        client
+            .post(format!("/v1/device/configuration/profiles/{name}/activate"))
+            .header(api_key_header_for(client.rocket().state().unwrap()))
+            .dispatch()
+            .status()
+    }
+
+    fn active_config(client: &Client) -> DeviceConfig {
+        let response = client
+            .get("/v1/device/configuration")
+            .header(api_key_header_for(client.rocket().state().unwrap()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<DeviceConfig>().unwrap()
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_profiles() {
+        let (_test_dir, client) = create_test_setup();
+        test_invalid_auth_get(&client, "/v1/device/configuration/profiles");
+        test_invalid_auth_post(&client, "/v1/device/configuration/profiles/lab/activate");
+        assert!(list_profiles(&client).is_empty());
+
+        // Saving two profiles does not change the active configuration
+        let lab = DeviceConfig::new(SecurityKey::new().unwrap(), "Lab".parse().unwrap());
+        let production =
+            DeviceConfig::new(SecurityKey::new().unwrap(), "Production".parse().unwrap());
+        assert_eq!(put_profile(&client, "lab", &lab), Status::Ok);
+        assert_eq!(put_profile(&client, "production", &production), Status::Ok);
+        assert_eq!(list_profiles(&client), ["lab", "production"]);
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        assert!(state.get_config().is_none());
+
+        // Switching between the profiles
+        assert_eq!(activate(&client, "lab"), Status::Ok);
+        assert_eq!(active_config(&client), lab);
+        assert!(state.reboot_required());
+        assert_eq!(activate(&client, "production"), Status::Ok);
+        assert_eq!(active_config(&client), production);
+
+        // Invalid and missing profiles
+        assert_eq!(activate(&client, "missing"), Status::NotFound);
+        assert_eq!(activate(&client, "a.b"), Status::BadRequest);
+        assert_eq!(put_profile(&client, "a.b", &lab), Status::BadRequest);
+        assert_eq!(active_config(&client), production);
+
+        // Deleting keeps the active configuration
+        let response = client
+            .delete("/v1/device/configuration/profiles/production")
+            .header(api_key_header_for(state))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let profiles = response.into_json::<ConfigProfiles>().unwrap().profiles;
+        assert_eq!(profiles, ["lab"]);
+        assert_eq!(active_config(&client), production);
+        let response = client
+            .delete("/v1/device/configuration/profiles/production")
+            .header(api_key_header_for(state))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
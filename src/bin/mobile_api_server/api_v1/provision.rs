@@ -0,0 +1,304 @@
+//! Endpoint for Provisioning the Device
+//!
+//! When the server is started with the `MOBILE_API_ALLOW_PROVISIONING` environment variable, it can
+//! run without the `device.json` file. The device information is then created once with the
+//! provision endpoint, after which the other endpoints can be used with the new API key.
+//!
+//! The server writes a provisioning token to its log when it starts waiting for provisioning.
+//! The provision request must contain the token, so only someone with local access to the device
+//! can provision it. The token also holds the UUID and the API key that the device gets.
+
+use crate::api_common::*;
+use crate::state::{BusyGuard, DeviceState, ProvisionError};
+use mobile_api::security::SecurityKey;
+use rocket::serde::json::{self, Json};
+use rocket::{post, Responder, State};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::openapi;
+use rocket_okapi::response::OpenApiResponderInner;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Provisioning request
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisionRequest {
+    /// Product name for the device
+    #[serde(alias = "product_name")]
+    pub product_name: String,
+    /// Provisioning token from the server log
+    pub token: String,
+}
+
+/// Created device identity
+///
+/// This is the only time the authorization key is returned by the server.
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisionedDevice {
+    /// Product name
+    #[serde(alias = "product_name")]
+    pub product_name: String,
+    /// 128-bit UUID in standard hex format
+    pub uuid: Uuid,
+    /// API key for the other endpoints
+    #[serde(alias = "authorization_key")]
+    pub authorization_key: SecurityKey,
+}
+
+/// # Provision the device
+///
+/// Creates the device information with the authorization key and UUID of the provisioning token.
+/// This works only once, when the server was started without the device information file and is
+/// waiting for provisioning. The token is written to the server log at startup. The response
+/// contains the authorization key for the other endpoints.
+///
+/// Returns 401 Unauthorized if the token does not match, and 409 Conflict if the device has
+/// already been provisioned.
+#[openapi(tag = "Provisioning")]
+#[post("/provision", data = "<request>")]
+pub async fn provision(
+    state: &State<DeviceState>,
+    request: Result<Json<ProvisionRequest>, json::Error<'_>>,
+) -> ProvisionResponse {
+    if state.is_provisioned() {
+        return ProvisionResponse::Conflict(ErrorResponse::conflict(
+            "The device has already been provisioned.",
+        ));
+    }
+    let (product_name, token) = match request {
+        Ok(request) => (request.0.product_name.trim().to_string(), request.0.token),
+        Err(error) => {
+            return ProvisionResponse::BadRequest(ErrorResponse::bad_request(Some(
+                &error.to_string(),
+            )))
+        }
+    };
+    if product_name.is_empty() {
+        return ProvisionResponse::BadRequest(ErrorResponse::bad_request(Some(
+            "product name is empty",
+        )));
+    }
+    match BusyGuard::try_busy(state, "The device is being provisioned.") {
+        Ok(_) => match state.provision(product_name, &token) {
+            Ok(device_info) => ProvisionResponse::Ok(Json(ProvisionedDevice {
+                product_name: device_info.product_name().to_string(),
+                uuid: *device_info.uuid(),
+                authorization_key: *device_info.authorization_key(),
+            })),
+            Err(ProvisionError::AlreadyProvisioned) => ProvisionResponse::Conflict(
+                ErrorResponse::conflict("The device has already been provisioned."),
+            ),
+            Err(ProvisionError::TokenInvalid) => ProvisionResponse::Unauthorized(
+                ErrorResponse::unauthorized(Some("The provisioning token does not match.")),
+            ),
+            Err(ProvisionError::Failed(error)) => {
+                ProvisionResponse::Error(ErrorResponse::internal_server_error(error.to_string()))
+            }
+        },
+        Err(busy) => ProvisionResponse::Busy(ErrorResponse::service_unavailable(busy)),
+    }
+}
+
+/// Possible responses for the provision endpoint
+#[derive(Responder)]
+pub enum ProvisionResponse {
+    /// 200 OK, the device was provisioned
+    #[response(status = 200, content_type = "json")]
+    Ok(Json<ProvisionedDevice>),
+
+    /// 400 Bad Request
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<ErrorResponse>),
+
+    /// 401 Unauthorized
+    #[response(status = 401, content_type = "json")]
+    Unauthorized(Json<ErrorResponse>),
+
+    /// 409 Conflict
+    #[response(status = 409, content_type = "json")]
+    Conflict(Json<ErrorResponse>),
+
+    /// 500 Internal Server Error
+    #[response(status = 500, content_type = "json")]
+    Error(Json<ErrorResponse>),
+
+    /// 503 Service Unavailable
+    #[response(status = 503, content_type = "json")]
+    Busy(BusyResponse),
+}
+
+impl OpenApiResponderInner for ProvisionResponse {
+    /// Generating responses for the provision endpoint
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        make_json_responses(vec![
+            (200, gen.json_schema::<ProvisionedDevice>(), None),
+            (400, gen.json_schema::<ErrorResponse>(), None),
+            (
+                401,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The provisioning token does not match."),
+            ),
+            (
+                409,
+                gen.json_schema::<ErrorResponse>(),
+                Some("The device has already been provisioned."),
+            ),
+            (500, gen.json_schema::<ErrorResponse>(), None),
+            (503, gen.json_schema::<ErrorResponse>(), None),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_v1::tests_common::create_test_setup;
+    use crate::build_rocket;
+    use mobile_api::configs::{DeviceInfo, ProvisioningToken};
+    use mobile_api::SifisHome;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+    use tempfile::TempDir;
+
+    /// Server without the device information file
+    fn create_unprovisioned_setup() -> (TempDir, SifisHome, Client) {
+        let test_dir = TempDir::new().unwrap();
+        let sifis_home = SifisHome::new_with_path(test_dir.path().to_path_buf());
+        let config_store = Box::new(sifis_home.config_store());
+        let sifis_home_copy = SifisHome::new_with_path(test_dir.path().to_path_buf());
+        let state = DeviceState::with_provisioning(sifis_home_copy, config_store, true).unwrap();
+        assert!(!state.is_provisioned());
+        let client = Client::tracked(build_rocket(state)).unwrap();
+        (test_dir, sifis_home, client)
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_provisioning_requires_permission() {
+        let test_dir = TempDir::new().unwrap();
+        let sifis_home = SifisHome::new_with_path(test_dir.path().to_path_buf());
+        let config_store = Box::new(sifis_home.config_store());
+        assert!(DeviceState::with_provisioning(sifis_home, config_store, false).is_err());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_provision() {
+        let uri = "/v1/provision";
+        let (_test_dir, sifis_home, client) = create_unprovisioned_setup();
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        let token = state.provisioning_token().unwrap();
+        let expected = DeviceInfo::from_provisioning_token(&token).unwrap();
+        let request_json = format!(r#"{{"productName":" Test Product ","token":"{token}"}}"#);
+
+        // Device information is not available yet
+        let response = client.get("/v1/device/info").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        // Any key is refused before provisioning
+        let any_key = SecurityKey::new().unwrap().to_base64();
+        let response = client
+            .get("/v1/device/status")
+            .header(Header::new(state.api_key_header().to_string(), any_key))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        // Product name is required
+        let response = client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"productName":"  ","token":"{token}"}}"#))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client.post(uri).header(ContentType::JSON).dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // The token is required and must match
+        let response = client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(r#"{"productName":"Test Product"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let other_token = ProvisioningToken {
+            uuid: expected.uuid,
+            authorization_key: SecurityKey::new().unwrap(),
+        }
+        .to_string();
+        for wrong in ["", "ABCD-EFGH", other_token.as_str()] {
+            let response = client
+                .post(uri)
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{"productName":"Test Product","token":"{wrong}"}}"#
+                ))
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized, "{wrong}");
+        }
+        assert!(!sifis_home.info_exists());
+
+        // Older clients' field names are accepted
+        let request: ProvisionRequest =
+            serde_json::from_str(r#"{"product_name":"Old Client","token":""}"#).unwrap();
+        assert_eq!(request.product_name, "Old Client");
+
+        // Provisioning creates the device information
+        let response = client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(request_json.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let device = response.into_json::<ProvisionedDevice>().unwrap();
+        assert_eq!(device.product_name, "Test Product");
+        assert_eq!(device.uuid, expected.uuid);
+        assert_eq!(device.authorization_key, expected.authorization_key);
+        let saved_info = sifis_home.load_info().unwrap();
+        assert_eq!(saved_info.authorization_key(), &device.authorization_key);
+        assert_eq!(saved_info.uuid(), &device.uuid);
+
+        // Other endpoints work with the new key
+        let response = client.get("/v1/device/info").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get("/v1/device/status")
+            .header(Header::new(
+                "x-api-key",
+                device.authorization_key.hex(false),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // Provisioning works only once
+        assert!(state.provisioning_token().is_none());
+        let response = client
+            .post(uri)
+            .header(ContentType::JSON)
+            .body(request_json)
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        assert_eq!(sifis_home.load_info().unwrap(), saved_info);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_provision_refused_when_provisioned() {
+        let (_test_dir, client) = create_test_setup();
+        let response = client
+            .post("/v1/provision")
+            .header(ContentType::JSON)
+            .body(r#"{"productName":"Test Product","token":""}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(error_response.error.code, 409);
+    }
+}
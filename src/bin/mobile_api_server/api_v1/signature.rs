@@ -0,0 +1,192 @@
+//! OpenAPI document and its signature
+//!
+//! Clients that know the authorization key of the device can check that the `openapi.json` they
+//! fetched came from the device. The `openapi.sig` route returns the HMAC-SHA256 tag of the
+//! document computed with the authorization key, as a lowercase hex string.
+
+use crate::api_common::ErrorResponse;
+use crate::state::DeviceState;
+use mobile_api::security::SecurityKey;
+use ring::hmac;
+use rocket::http::{Method, Status};
+use rocket::response::content::RawJson;
+use rocket::route::{Handler, Outcome};
+use rocket::{Data, Request, Route};
+use rocket_okapi::okapi::openapi3::{OpenApi, Server};
+use std::sync::Arc;
+
+/// Sign the OpenAPI *document* with the *authorization_key*
+///
+/// Returns the HMAC-SHA256 tag as a lowercase hex string.
+pub fn sign_document(document: &[u8], authorization_key: &SecurityKey) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, authorization_key.as_bytes());
+    hmac::sign(&key, document)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Routes serving the *spec* from `openapi.json` and its signature from `openapi.sig`
+///
+/// Both routes serialize the specification with [document_for], so the signature matches the
+/// bytes that clients receive. The routes are not documented in the specification.
+pub fn openapi_routes(spec: OpenApi) -> Vec<Route> {
+    let spec = Arc::new(spec);
+    vec![
+        Route::new(
+            Method::Get,
+            "/openapi.json",
+            DocumentHandler { spec: spec.clone() },
+        ),
+        Route::new(Method::Get, "/openapi.sig", SignatureHandler { spec }),
+    ]
+}
+
+/// The *spec* serialized for the *request*
+///
+/// Like in rocket_okapi, the base path of the route is added as the server of the API when the
+/// specification does not list any servers.
+fn document_for(spec: &OpenApi, request: &Request<'_>) -> String {
+    let base_path = request
+        .route()
+        .expect("Routing should already have occurred")
+        .uri
+        .base();
+    let mut spec = spec.clone();
+    if spec.servers.is_empty() && base_path != "/" {
+        spec.servers.push(Server {
+            url: base_path.to_string(),
+            ..Server::default()
+        });
+    }
+    serde_json::to_string_pretty(&spec).expect("OpenAPI specification could not be serialized")
+}
+
+/// Handler serving the document
+#[derive(Clone)]
+struct DocumentHandler {
+    spec: Arc<OpenApi>,
+}
+
+#[rocket::async_trait]
+impl Handler for DocumentHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> Outcome<'r> {
+        Outcome::from(request, RawJson(document_for(&self.spec, request)))
+    }
+}
+
+/// Handler signing the document with the current authorization key
+#[derive(Clone)]
+struct SignatureHandler {
+    spec: Arc<OpenApi>,
+}
+
+#[rocket::async_trait]
+impl Handler for SignatureHandler {
+    async fn handle<'r>(&self, request: &'r Request<'_>, _data: Data<'r>) -> Outcome<'r> {
+        // The key is looked up on every request, because provisioning can change it
+        let device_info = request
+            .rocket()
+            .state::<DeviceState>()
+            .and_then(DeviceState::device_info);
+        match device_info {
+            Some(info) => {
+                let document = document_for(&self.spec, request);
+                Outcome::from(
+                    request,
+                    sign_document(document.as_bytes(), info.authorization_key()),
+                )
+            }
+            None => Outcome::from(
+                request,
+                (
+                    Status::NotFound,
+                    ErrorResponse::not_found(Some("The device has not been provisioned yet.")),
+                ),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_v1::tests_common::create_test_setup;
+    use crate::build_rocket;
+    use mobile_api::SifisHome;
+    use rocket::local::blocking::Client;
+    use tempfile::TempDir;
+
+    /// Decode the lowercase hex *string* from [sign_document]
+    fn decode_hex(string: &str) -> Vec<u8> {
+        (0..string.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&string[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_sign_document() {
+        let key = SecurityKey::from_hex(&"ab".repeat(32)).unwrap();
+        let signature = sign_document(b"{}", &key);
+        assert_eq!(signature.len(), 64);
+        assert!(signature
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)));
+        assert_eq!(signature, sign_document(b"{}", &key));
+        assert_ne!(signature, sign_document(b"{ }", &key));
+        let other_key = SecurityKey::from_hex(&"cd".repeat(32)).unwrap();
+        assert_ne!(signature, sign_document(b"{}", &other_key));
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_openapi_signature() {
+        let (_test_dir, client) = create_test_setup();
+
+        let response = client.get("/v1/openapi.json").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let document = response.into_bytes().unwrap();
+
+        let response = client.get("/v1/openapi.sig").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let signature = response.into_string().unwrap();
+
+        // Clients with the authorization key can verify the document
+        let state = client.rocket().state::<DeviceState>().unwrap();
+        let device_info = state.device_info().unwrap();
+        let key = hmac::Key::new(
+            hmac::HMAC_SHA256,
+            device_info.authorization_key().as_bytes(),
+        );
+        assert!(hmac::verify(&key, &document, &decode_hex(&signature)).is_ok());
+
+        // Modified documents do not match
+        let mut modified = document.clone();
+        modified.push(b' ');
+        assert!(hmac::verify(&key, &modified, &decode_hex(&signature)).is_err());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_openapi_signature_not_provisioned() {
+        let test_dir = TempDir::new().unwrap();
+        let sifis_home = SifisHome::new_with_path(test_dir.path().to_path_buf());
+        let config_store = Box::new(sifis_home.config_store());
+        let state = DeviceState::with_provisioning(sifis_home, config_store, true).unwrap();
+        let client = Client::tracked(build_rocket(state)).unwrap();
+
+        let response = client.get("/v1/openapi.sig").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(
+            error_response.error.description,
+            "The device has not been provisioned yet."
+        );
+    }
+}
@@ -8,11 +8,16 @@ use rocket::http::{Header, Status};
 use rocket::local::blocking::Client;
 use rocket::serde::uuid::Uuid;
 use std::path::PathBuf;
+#[cfg(feature = "commands")]
 use std::time::Duration;
 use tempfile::TempDir;
+#[cfg(feature = "commands")]
 use tokio::runtime::{Builder, Runtime};
+#[cfg(feature = "commands")]
 use tokio::sync::oneshot;
+#[cfg(feature = "commands")]
 use tokio::task::JoinHandle;
+#[cfg(feature = "commands")]
 use zbus::{dbus_interface, Connection};
 
 pub const TEST_AUTH_KEY: SecurityKey = SecurityKey::from_bytes([
@@ -20,8 +25,6 @@ pub const TEST_AUTH_KEY: SecurityKey = SecurityKey::from_bytes([
     0x24, 0xe8, 0x4d, 0xeb, 0x2d, 0x49, 0xea, 0xef, 0x7a, 0xb1, 0x27, 0x76, 0x9a, 0x22, 0x9e, 0xdb,
 ]);
 
-pub const TEST_API_KEY: &str = "UnsecureTestKeyUseOnlyToTestServerEndpoints=";
-
 pub const TEST_DEVICE_NAME: &str = "Test Device";
 
 pub const TEST_PRODUCT_NAME: &str = "Test Product";
@@ -35,16 +38,26 @@ pub const TEST_UUID: Uuid = Uuid::from_bytes([
     0x12, 0x3e, 0x45, 0x67, 0xe8, 0x9b, 0x12, 0xd3, 0xa4, 0x56, 0x42, 0x66, 0x14, 0x17, 0x40, 0x00,
 ]);
 
-pub fn api_key_header() -> Header<'static> {
-    Header::new("x-api-key", TEST_API_KEY)
+/// API key header that authenticates to a server using the *state*
+///
+/// The header name and the authorization key are taken from the state, so the header stays
+/// valid even when the state was created with other values than the test constants.
+///
+/// Panics if the device is not provisioned.
+pub fn api_key_header_for(state: &DeviceState) -> Header<'static> {
+    let device_info = state.device_info().expect("device is not provisioned");
+    Header::new(
+        state.api_key_header().to_string(),
+        device_info.authorization_key().to_base64(),
+    )
 }
 
 pub fn create_test_config() -> DeviceConfig {
-    DeviceConfig::new(TEST_SHARED_DHT_KEY, TEST_DEVICE_NAME.to_string())
+    DeviceConfig::new(TEST_SHARED_DHT_KEY, TEST_DEVICE_NAME.parse().unwrap())
 }
 
 #[must_use]
-pub fn create_test_state() -> (TempDir, DeviceState) {
+pub fn create_test_sifis_home() -> (TempDir, SifisHome) {
     // Making SifisHome object pointing to temporary directory
     let test_dir = TempDir::new().unwrap();
     let mut sifis_home_path = PathBuf::from(test_dir.path());
@@ -62,8 +75,12 @@ pub fn create_test_state() -> (TempDir, DeviceState) {
         TEST_UUID,
     );
     sifis_home.save_info(&device_info).unwrap();
+    (test_dir, sifis_home)
+}
 
-    // Making DeviceState using the above
+#[must_use]
+pub fn create_test_state() -> (TempDir, DeviceState) {
+    let (test_dir, sifis_home) = create_test_sifis_home();
     let device_state = DeviceState::new(sifis_home).unwrap();
     (test_dir, device_state)
 }
@@ -75,10 +92,12 @@ pub fn create_test_setup() -> (TempDir, Client) {
     (test_dir, client)
 }
 
+#[cfg(feature = "commands")]
 struct DbusTestingListener {
     done_tx: Option<oneshot::Sender<String>>,
 }
 
+#[cfg(feature = "commands")]
 #[dbus_interface(name = "eu.sifis_home.Testing")]
 impl DbusTestingListener {
     async fn script_was_run(&mut self, script: &str) {
@@ -88,6 +107,7 @@ impl DbusTestingListener {
     }
 }
 
+#[cfg(feature = "commands")]
 fn map_error_to_string<E>(e: E) -> String
 where
     E: std::fmt::Display,
@@ -95,6 +115,36 @@ where
     e.to_string()
 }
 
+/// How many times connecting to the session bus is tried
+#[cfg(feature = "commands")]
+const DBUS_CONNECT_ATTEMPTS: u32 = 4;
+
+/// Delay before the first reconnection attempt, doubled after each attempt
+#[cfg(feature = "commands")]
+const DBUS_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Connect to the session bus, retrying with backoff
+///
+/// The bus may still be starting in minimal CI containers, so the connection is tried a few times
+/// before giving up.
+#[cfg(feature = "commands")]
+async fn connect_session_bus() -> Result<Connection, String> {
+    let mut delay = DBUS_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match Connection::session().await {
+            Ok(connection) => return Ok(connection),
+            Err(err) if attempt >= DBUS_CONNECT_ATTEMPTS => return Err(map_error_to_string(err)),
+            Err(_) => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "commands")]
 async fn wait_dbus_confirm(
     name: String,
     timeout: Duration,
@@ -107,7 +157,7 @@ async fn wait_dbus_confirm(
 
     let well_known_name = format!("eu.sifis_home.Testing.{name}");
 
-    let connection = Connection::session().await.map_err(map_error_to_string)?;
+    let connection = connect_session_bus().await?;
     connection
         .object_server()
         .at("/Testing", testing)
@@ -126,10 +176,20 @@ async fn wait_dbus_confirm(
     }
 }
 
+/// Start listening for the test script of the *name* to report that it was run
+///
+/// Returns `None` when the `DBUS_SESSION_BUS_ADDRESS` environment variable is not set, because the
+/// scripts can not report without a session bus. The caller should then skip the test.
+#[cfg(feature = "commands")]
 pub fn make_script_run_checker(
     name: &str,
     timeout: Duration,
-) -> (Runtime, JoinHandle<Result<String, String>>) {
+) -> Option<(Runtime, JoinHandle<Result<String, String>>)> {
+    if std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
+        println!("The {name} script check was skipped because the D-Bus session bus is not");
+        println!("available, the DBUS_SESSION_BUS_ADDRESS environment variable is not set.");
+        return None;
+    }
     let runtime = Builder::new_multi_thread()
         .worker_threads(1)
         .enable_all()
@@ -138,7 +198,7 @@ pub fn make_script_run_checker(
     let (ready_tx, ready_rx) = oneshot::channel();
     let handle = runtime.spawn(wait_dbus_confirm(name.to_string(), timeout, ready_tx));
     let _ = ready_rx.blocking_recv();
-    (runtime, handle)
+    Some((runtime, handle))
 }
 
 pub fn test_invalid_auth_get(client: &Client, uri: &str) {
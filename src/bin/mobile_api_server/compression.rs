@@ -0,0 +1,305 @@
+//! Compression for JSON responses
+//!
+//! The mobile link to the device may be slow, so larger JSON responses are compressed when the
+//! client announces support for it with the `Accept-Encoding` header. Small responses are left
+//! uncompressed because compressing them would cost more than it saves.
+
+use flate2::write::{DeflateEncoder, GzEncoder};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+use std::io::{Cursor, Write};
+
+/// Responses smaller than this many bytes are not compressed by default
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// Supported content encodings
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    /// The `gzip` encoding
+    Gzip,
+    /// The `deflate` encoding
+    Deflate,
+}
+
+impl Encoding {
+    /// Name used in the `Content-Encoding` header
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Compress the given data with this encoding
+    pub fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Choose an encoding from the `Accept-Encoding` header value
+///
+/// The gzip is preferred over the deflate when both are accepted. Encodings with `q=0` are
+/// considered refused. The `*` wildcard accepts the encodings that are not listed explicitly, so
+/// `gzip;q=0, *` refuses gzip but accepts deflate.
+pub fn preferred_encoding(accept_encoding: &str) -> Option<Encoding> {
+    // None when the encoding is not listed, otherwise whether it is accepted
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let refused = parts.any(|param| {
+            let param = param.trim();
+            match param.strip_prefix("q=") {
+                Some(quality) => quality.trim().parse::<f32>().is_ok_and(|q| q <= 0.0),
+                None => false,
+            }
+        });
+        let listed = match coding.as_str() {
+            "gzip" | "x-gzip" => &mut gzip,
+            "deflate" => &mut deflate,
+            "*" => &mut wildcard,
+            _ => continue,
+        };
+        *listed = Some(listed.unwrap_or(false) || !refused);
+    }
+    if gzip.or(wildcard).unwrap_or(false) {
+        Some(Encoding::Gzip)
+    } else if deflate.or(wildcard).unwrap_or(false) {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+/// Fairing compressing JSON responses
+pub struct Compression {
+    /// Responses smaller than this are sent uncompressed
+    min_size: usize,
+}
+
+impl Compression {
+    /// Create a fairing compressing JSON responses of at least *min_size* bytes
+    pub fn new(min_size: usize) -> Compression {
+        Compression { min_size }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_SIZE)
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "JSON Response Compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        // Only JSON responses that are not already encoded
+        let is_json = response
+            .content_type()
+            .is_some_and(|content_type| content_type.is_json());
+        if !is_json || response.headers().contains("Content-Encoding") {
+            return;
+        }
+
+        // Does the client accept compressed content?
+        let accept_encoding = request
+            .headers()
+            .get("Accept-Encoding")
+            .collect::<Vec<_>>()
+            .join(",");
+        let encoding = match preferred_encoding(&accept_encoding) {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        // The body needs to be read before we know its size
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(_) => return,
+        };
+        if body.len() < self.min_size {
+            response.set_sized_body(body.len(), Cursor::new(body));
+            return;
+        }
+
+        match encoding.compress(&body) {
+            Ok(compressed) => {
+                response.set_sized_body(compressed.len(), Cursor::new(compressed));
+                response.set_header(Header::new("Content-Encoding", encoding.name()));
+                response.set_header(Header::new("Vary", "Accept-Encoding"));
+            }
+            Err(_) => response.set_sized_body(body.len(), Cursor::new(body)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_v1;
+    use crate::api_v1::tests_common::{api_key_header_for, create_test_state};
+    use crate::device_status::DeviceStatus;
+    use flate2::read::{DeflateDecoder, GzDecoder};
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use std::io::Read;
+
+    /// Routes for the tests
+    ///
+    /// Rocket exports a URI macro for each route, and the tests do not use them.
+    #[allow(unused_imports)]
+    mod test_routes {
+        use rocket::serde::json::Json;
+
+        #[rocket::get("/large")]
+        pub fn large() -> Json<Vec<u32>> {
+            Json((0..1000).collect())
+        }
+
+        #[rocket::get("/small")]
+        pub fn small() -> Json<&'static str> {
+            Json("small")
+        }
+    }
+
+    fn test_client() -> Client {
+        let rocket = rocket::build()
+            .mount("/", rocket::routes![test_routes::large, test_routes::small])
+            .attach(Compression::default());
+        Client::tracked(rocket).unwrap()
+    }
+
+    #[test]
+    fn test_preferred_encoding() {
+        assert_eq!(preferred_encoding(""), None);
+        assert_eq!(preferred_encoding("identity"), None);
+        assert_eq!(preferred_encoding("gzip"), Some(Encoding::Gzip));
+        assert_eq!(preferred_encoding("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(preferred_encoding("br, deflate"), Some(Encoding::Deflate));
+        assert_eq!(
+            preferred_encoding("gzip;q=0, deflate"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(preferred_encoding("GZIP;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(preferred_encoding("*"), Some(Encoding::Gzip));
+
+        // Explicit refusals win over the wildcard
+        assert_eq!(preferred_encoding("gzip;q=0, *"), Some(Encoding::Deflate));
+        assert_eq!(preferred_encoding("*, gzip;q=0"), Some(Encoding::Deflate));
+        assert_eq!(preferred_encoding("gzip;q=0, deflate;q=0, *"), None);
+        assert_eq!(
+            preferred_encoding("*;q=0, deflate"),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(preferred_encoding("*;q=0"), None);
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_large_response_compression() {
+        let client = test_client();
+
+        // Without Accept-Encoding the response should not be touched
+        let response = client.get("/large").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("Content-Encoding").is_none());
+        let plain = response.into_bytes().unwrap();
+        assert!(plain.len() >= DEFAULT_MIN_SIZE);
+
+        // Gzip compressed response should decompress to the same content
+        let response = client
+            .get("/large")
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch();
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+        let compressed = response.into_bytes().unwrap();
+        assert!(compressed.len() < plain.len());
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+
+        // The same with deflate
+        let response = client
+            .get("/large")
+            .header(Header::new("Accept-Encoding", "deflate"))
+            .dispatch();
+        assert_eq!(
+            response.headers().get_one("Content-Encoding"),
+            Some("deflate")
+        );
+        let compressed = response.into_bytes().unwrap();
+        let mut decompressed = Vec::new();
+        DeflateDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, plain);
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_small_response_not_compressed() {
+        let client = test_client();
+        let response = client
+            .get("/small")
+            .header(Header::new("Accept-Encoding", "gzip, deflate"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("Content-Encoding").is_none());
+        assert_eq!(response.into_string().unwrap(), "\"small\"");
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_status_compression() {
+        // Compressing everything so that the test does not depend on the size of the status
+        let (_test_dir, state) = create_test_state();
+        let api_key_header = api_key_header_for(&state);
+        let routes = api_v1::routes(state.api_key_header());
+        let rocket = rocket::build()
+            .manage(state)
+            .mount("/v1/", routes)
+            .attach(Compression::new(0));
+        let client = Client::tracked(rocket).unwrap();
+        let uri = "/v1/device/status";
+
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        assert!(response.headers().get_one("Content-Encoding").is_none());
+        assert!(response.into_json::<DeviceStatus>().is_some());
+
+        let response = client
+            .get(uri)
+            .header(api_key_header)
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch();
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+        let compressed = response.into_bytes().unwrap();
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert!(serde_json::from_slice::<DeviceStatus>(&decompressed).is_ok());
+    }
+}
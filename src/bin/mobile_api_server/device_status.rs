@@ -1,13 +1,16 @@
 //! Device status structures
 //!
 //! System status information is collected into these structures
-//! and sent to the client application in JSON format.
+//! and sent to the client application in JSON format. Field names are
+//! in camelCase, the older snake_case names are accepted when parsing.
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 /// Memory information
 #[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct MemStatus {
     /// Total available memory in bytes
     pub total: u64,
@@ -39,22 +42,50 @@ impl MemStatus {
     }
 }
 
+/// CPU information
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CpuInfo {
+    /// CPU model name as reported by the system, empty if unknown
+    pub brand: String,
+
+    /// Current frequency per core in MHz
+    ///
+    /// The array contains a value for each CPU core in the same order as the `cpuUsage`.
+    pub frequency: Vec<u64>,
+}
+
 #[derive(Debug, Deserialize, Serialize, JsonSchema)]
 /// Disk information
+#[serde(rename_all = "camelCase")]
 pub struct DiskStatus {
     /// Device file
     pub device: String,
 
     /// Filesystem name
+    #[serde(alias = "file_system")]
     pub file_system: String,
 
     /// Total diskspace in bytes
+    #[serde(alias = "total_space")]
     pub total_space: u64,
 
     /// Mount point of the disk
+    ///
+    /// When duplicate disks are merged, this is the first of the
+    /// [mount_points](DiskStatus::mount_points).
+    #[serde(alias = "mount_point")]
     pub mount_point: String,
 
+    /// All mount points of the disk
+    ///
+    /// Only the [mount_point](DiskStatus::mount_point) is listed unless the same device is mounted
+    /// in several places and the duplicates are merged, see [merge_duplicate_disks].
+    #[serde(alias = "mount_points", default)]
+    pub mount_points: Vec<String>,
+
     /// Available disk space in bytes
+    #[serde(alias = "available_space")]
     pub available_space: u64,
 
     /// Disk space usage
@@ -65,18 +96,35 @@ pub struct DiskStatus {
 
 #[derive(Debug, Deserialize, Serialize, JsonSchema)]
 /// A collection of system information
+#[serde(rename_all = "camelCase")]
 pub struct DeviceStatus {
     /// CPU usage per core
     ///
     /// CPU usage is between zero and one, where zero is 0% and one is 100%.
     /// The array contains a value for each CPU core.
+    #[serde(alias = "cpu_usage")]
     pub cpu_usage: Vec<f32>,
 
+    /// Number of logical CPUs, the same as the length of `cpuUsage`
+    #[serde(alias = "logical_cpus")]
+    pub logical_cpus: usize,
+
+    /// Number of physical CPU cores when the system reports it
+    ///
+    /// This is smaller than `logicalCpus` when hyperthreading is used.
+    #[serde(alias = "physical_cpus", skip_serializing_if = "Option::is_none")]
+    pub physical_cpus: Option<usize>,
+
+    /// CPU model and frequencies
+    #[serde(alias = "cpu_info")]
+    pub cpu_info: CpuInfo,
+
     /// RAM information
+    #[serde(alias = "mem_usage")]
     pub mem_usage: MemStatus,
 
     /// Swap information when available
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "swap_usage", skip_serializing_if = "Option::is_none")]
     pub swap_usage: Option<MemStatus>,
 
     /// A collection of disk information
@@ -85,6 +133,292 @@ pub struct DeviceStatus {
     /// System uptime in seconds
     pub uptime: u64,
 
+    /// System boot time as seconds since the UNIX epoch
+    #[serde(alias = "boot_time_unix")]
+    pub boot_time_unix: u64,
+
     /// Load average values for 1 min, 5 min, and 15 min
+    #[serde(alias = "load_average")]
     pub load_average: [f32; 3],
+
+    /// Number of running processes
+    pub process_count: usize,
+
+    /// Time when the status was collected in milliseconds since the UNIX epoch
+    #[serde(alias = "collected_at_unix_ms")]
+    pub collected_at_unix_ms: u128,
+}
+
+/// Merge disks that are the same device mounted in several places
+///
+/// Bind mounts and containers make the same device appear under many mount points. Disks with the
+/// same device and file system are merged into the first of them, and the mount points of the
+/// others are added to its [mount_points](DiskStatus::mount_points). Mount points that are
+/// already listed are not added again. The order of the remaining disks is kept.
+///
+/// Separate file systems without a real device, like two `tmpfs` mounts, are merged too, which is
+/// why this is optional.
+pub fn merge_duplicate_disks(disks: Vec<DiskStatus>) -> Vec<DiskStatus> {
+    let mut merged: Vec<DiskStatus> = Vec::with_capacity(disks.len());
+    for disk in disks {
+        let duplicate_of = merged
+            .iter_mut()
+            .find(|kept| kept.device == disk.device && kept.file_system == disk.file_system);
+        match duplicate_of {
+            Some(kept) => {
+                for mount_point in disk.mount_points {
+                    if !kept.mount_points.contains(&mount_point) {
+                        kept.mount_points.push(mount_point);
+                    }
+                }
+            }
+            None => merged.push(disk),
+        }
+    }
+    merged
+}
+
+impl DeviceStatus {
+    /// Disks sorted from the fullest to the emptiest
+    ///
+    /// Disks with the same usage keep their order from [disks](DeviceStatus::disks), which is
+    /// sorted by the device name. The serialized order is not changed.
+    pub fn disks_by_fullness(&self) -> Vec<&DiskStatus> {
+        let mut disks: Vec<&DiskStatus> = self.disks.iter().collect();
+        disks.sort_by(|a, b| b.usage.total_cmp(&a.usage));
+        disks
+    }
+}
+
+/// Selected sections of the DeviceStatus
+///
+/// Only the information of the selected [StatusSection]s is collected, and the fields of the other
+/// sections are left out. The fields are the same as in the [DeviceStatus].
+#[derive(Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PartialDeviceStatus {
+    /// CPU usage per core, in the `cpu` section
+    #[serde(alias = "cpu_usage", skip_serializing_if = "Option::is_none")]
+    pub cpu_usage: Option<Vec<f32>>,
+
+    /// Number of logical CPUs, in the `cpu` section
+    #[serde(alias = "logical_cpus", skip_serializing_if = "Option::is_none")]
+    pub logical_cpus: Option<usize>,
+
+    /// Number of physical CPU cores when the system reports it, in the `cpu` section
+    #[serde(alias = "physical_cpus", skip_serializing_if = "Option::is_none")]
+    pub physical_cpus: Option<usize>,
+
+    /// CPU model and frequencies, in the `cpu` section
+    #[serde(alias = "cpu_info", skip_serializing_if = "Option::is_none")]
+    pub cpu_info: Option<CpuInfo>,
+
+    /// RAM information, in the `mem` section
+    #[serde(alias = "mem_usage", skip_serializing_if = "Option::is_none")]
+    pub mem_usage: Option<MemStatus>,
+
+    /// Swap information when available, in the `mem` section
+    #[serde(alias = "swap_usage", skip_serializing_if = "Option::is_none")]
+    pub swap_usage: Option<MemStatus>,
+
+    /// A collection of disk information, in the `disks` section
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disks: Option<Vec<DiskStatus>>,
+
+    /// System uptime in seconds, in the `uptime` section
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<u64>,
+
+    /// System boot time as seconds since the UNIX epoch, in the `uptime` section
+    #[serde(alias = "boot_time_unix", skip_serializing_if = "Option::is_none")]
+    pub boot_time_unix: Option<u64>,
+
+    /// Load average values for 1 min, 5 min, and 15 min, in the `load` section
+    #[serde(alias = "load_average", skip_serializing_if = "Option::is_none")]
+    pub load_average: Option<[f32; 3]>,
+
+    /// Number of running processes, in the `load` section
+    #[serde(alias = "process_count", skip_serializing_if = "Option::is_none")]
+    pub process_count: Option<usize>,
+}
+
+/// Sections of the DeviceStatus that can be selected
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatusSection {
+    /// CPU usage, counts, and information
+    Cpu,
+    /// RAM and swap usage
+    Mem,
+    /// Disk information
+    Disks,
+    /// Uptime and boot time
+    Uptime,
+    /// Load average and process count
+    Load,
+}
+
+impl StatusSection {
+    /// Parse a comma-separated *list* of section names
+    ///
+    /// The names are `cpu`, `mem`, `disks`, `uptime`, and `load`. Unknown names are errors.
+    pub fn parse_list(list: &str) -> Result<Vec<StatusSection>, String> {
+        let mut sections = Vec::new();
+        for name in list.split(',') {
+            let section = name.trim().parse::<StatusSection>()?;
+            if !sections.contains(&section) {
+                sections.push(section);
+            }
+        }
+        Ok(sections)
+    }
+}
+
+impl FromStr for StatusSection {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "cpu" => Ok(StatusSection::Cpu),
+            "mem" => Ok(StatusSection::Mem),
+            "disks" => Ok(StatusSection::Disks),
+            "uptime" => Ok(StatusSection::Uptime),
+            "load" => Ok(StatusSection::Load),
+            _ => Err(format!("Unknown status field `{}`.", name)),
+        }
+    }
+}
+
+/// Information about a single process
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessStatus {
+    /// Process identifier
+    pub pid: u32,
+
+    /// Process name
+    pub name: String,
+
+    /// CPU usage
+    ///
+    /// One is a single CPU core used at 100%, so a process using several cores can go above one.
+    pub cpu: f32,
+
+    /// Amount of used RAM in bytes
+    pub mem: u64,
+}
+
+/// Order of the process list
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ProcessOrder {
+    /// Highest CPU usage first
+    #[default]
+    Cpu,
+    /// Highest RAM usage first
+    Mem,
+}
+
+impl FromStr for ProcessOrder {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "cpu" => Ok(ProcessOrder::Cpu),
+            "mem" => Ok(ProcessOrder::Mem),
+            _ => Err(format!("Unknown process order `{}`.", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(device: &str, usage: f32) -> DiskStatus {
+        DiskStatus {
+            device: device.to_string(),
+            file_system: "ext4".to_string(),
+            total_space: 1000,
+            mount_point: format!("/mnt/{device}"),
+            mount_points: vec![format!("/mnt/{device}")],
+            available_space: (1000.0 * (1.0 - usage)) as u64,
+            usage,
+        }
+    }
+
+    #[test]
+    fn test_merge_duplicate_disks() {
+        let mut bind_mount = disk("sda1", 0.5);
+        bind_mount.mount_point = "/var/lib/container".to_string();
+        bind_mount.mount_points = vec![bind_mount.mount_point.clone()];
+        let mut other_file_system = disk("sda1", 0.5);
+        other_file_system.file_system = "vfat".to_string();
+        let disks = vec![
+            disk("sda1", 0.5),
+            disk("sdb1", 0.25),
+            bind_mount,
+            other_file_system,
+            disk("sdb1", 0.25),
+        ];
+
+        let merged = merge_duplicate_disks(disks);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].device, "sda1");
+        assert_eq!(merged[0].mount_point, "/mnt/sda1");
+        assert_eq!(merged[0].mount_points, ["/mnt/sda1", "/var/lib/container"]);
+        assert_eq!(merged[1].device, "sdb1");
+        assert_eq!(merged[1].mount_points, ["/mnt/sdb1"]);
+        assert_eq!(merged[2].file_system, "vfat");
+        assert_eq!(merged[2].mount_points, ["/mnt/sda1"]);
+
+        // Older clients' field names and the missing list are accepted
+        let old: DiskStatus = serde_json::from_str(
+            r#"{"device":"sda1","file_system":"ext4","total_space":1,"mount_point":"/",
+            "available_space":1,"usage":0.0}"#,
+        )
+        .unwrap();
+        assert!(old.mount_points.is_empty());
+    }
+
+    #[test]
+    fn test_disks_by_fullness() {
+        let status = DeviceStatus {
+            cpu_usage: vec![0.5],
+            logical_cpus: 1,
+            physical_cpus: Some(1),
+            cpu_info: CpuInfo {
+                brand: String::new(),
+                frequency: vec![1000],
+            },
+            mem_usage: MemStatus::new(1000, 500, 500),
+            swap_usage: None,
+            disks: vec![
+                disk("sda1", 0.25),
+                disk("sda2", 0.9),
+                disk("sdb1", 0.5),
+                disk("sdc1", 0.9),
+                disk("sdd1", 0.0),
+            ],
+            uptime: 0,
+            boot_time_unix: 0,
+            load_average: [0.0; 3],
+            process_count: 1,
+            collected_at_unix_ms: 0,
+        };
+        let devices: Vec<&str> = status
+            .disks_by_fullness()
+            .iter()
+            .map(|disk| disk.device.as_str())
+            .collect();
+        assert_eq!(devices, ["sda2", "sdc1", "sdb1", "sda1", "sdd1"]);
+
+        // The serialized order is still by the device name
+        let json = serde_json::to_value(&status).unwrap();
+        let devices: Vec<&str> = json["disks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|disk| disk["device"].as_str().unwrap())
+            .collect();
+        assert_eq!(devices, ["sda1", "sda2", "sdb1", "sdc1", "sdd1"]);
+    }
 }
@@ -7,39 +7,143 @@
 //!
 //! * `SIFIS_HOME_PATH` - The path where the device settings are stored
 //! * `MOBILE_API_SCRIPTS_PATH` - The path where command scripts are stored
+//! * `MOBILE_API_KEY_HEADER` - Header name for the API key, `x-api-key` by default
+//! * `MOBILE_API_ALLOW_PROVISIONING` - Set to `1` to start without device information and wait for
+//!   the provision endpoint to create it. The provisioning token that the endpoint requires is
+//!   written to the log.
+//! * `MOBILE_API_STATIC_NOT_FOUND` - Set to `index` to answer missing static files with the
+//!   `index.html` page instead of a JSON error
+//! * `MOBILE_API_DEVICE_NAME` - Device name overriding the one in `config.json`
+//! * `MOBILE_API_DHT_KEY` - DHT shared key as a hex or base64 string, overriding the one in
+//!   `config.json`. When both overrides are set, the device is configured even without the file.
+//!   The overrides are applied when the server starts, so a configuration saved through the API
+//!   is used until the next start.
+//! * `MOBILE_API_MERGE_DISKS` - Set to `1` to list a device mounted in several places only once in
+//!   the device status, with all of its mount points
+//! * `MOBILE_API_LOG` - Log filter in the [env_logger] format, like `debug` or
+//!   `info,rocket=warn`. The default is `info`. Rocket's messages are filtered with it too.
+//! * `MOBILE_API_BIND` - Comma-separated list of IPv4 and IPv6 addresses to listen on, overrides
+//!   `ROCKET_ADDRESS` when set
 //! * `ROCKET_ADDRESS` - Ip address or host to listen on
 //! * `ROCKET_PORT` - Port number to listen on
 //!
+//! The `--dump-openapi <FILE>` option writes the OpenAPI specification to the file and exits
+//! without starting the server. Similarly, the `--dump-schemas <DIR>` option writes the JSON
+//! Schemas of the device configuration, information, and status to `DeviceConfig.schema.json`,
+//! `DeviceInfo.schema.json`, and `DeviceStatus.schema.json` files in the directory.
+//!
+//! JSON responses are compact by default. GET requests can add the `pretty=true` query parameter to
+//! receive indented JSON, which is easier to read when debugging with tools like `curl`.
+//!
 //! These environment variables can be set in the `.env` file. This file is used during the
 //! development to store configurations in the program's local directory.
 //!
 //! See more Rocket related configuration options from: [rocket#configuration]
 
+use crate::api_common::{api_key_header_from_env, catchers, static_catchers};
+use crate::compression::Compression;
+use crate::device_status::DeviceStatus;
+use crate::pretty_json::PrettyJson;
 use crate::state::DeviceState;
-use mobile_api::SifisHome;
+use clap::Parser;
+use log::{error, info, warn};
+use mobile_api::configs::{DeviceConfig, DeviceInfo};
+use mobile_api::security::{get_unix_time_ms, is_clock_likely_unset};
+use mobile_api::{SifisHome, SifisHomeConfig};
 use rocket::fs::FileServer;
+use rocket::futures::future::try_join_all;
 use rocket::{Build, Rocket};
 use rocket_okapi::rapidoc::{make_rapidoc, GeneralConfig, HideShowConfig, RapiDocConfig};
 use rocket_okapi::settings::UrlObject;
 use rocket_okapi::swagger_ui::{make_swagger_ui, SwaggerUIConfig};
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
 pub mod api_common;
 pub mod api_v1;
+pub mod compression;
 pub mod device_status;
+pub mod pretty_json;
+pub mod scripts;
 pub mod state;
 
+/// Environment variable for listening on multiple addresses
+const BIND_ENV: &str = "MOBILE_API_BIND";
+
+/// Environment variable for the log filter
+const LOG_ENV: &str = "MOBILE_API_LOG";
+
+/// Command line arguments for the server
+#[derive(Debug, Parser)]
+#[command(about = "Smart Device Mobile API server")]
+struct Arguments {
+    /// Write the OpenAPI specification to the FILE and exit
+    #[arg(long, value_name = "FILE")]
+    dump_openapi: Option<PathBuf>,
+
+    /// Write the JSON Schemas of the device configuration, information, and status to the DIR
+    /// and exit
+    #[arg(long, value_name = "DIR")]
+    dump_schemas: Option<PathBuf>,
+}
+
 /// Entry Point for the Server Program
 #[rocket::main]
 async fn main() -> ExitCode {
-    // Read .env file when available
-    if dotenvy::dotenv().is_ok() {
-        println!("Loaded environment variables from .env file");
+    let args = Arguments::parse();
+
+    // Read .env file when available, it may set the log filter too
+    let dotenv_loaded = dotenvy::dotenv().is_ok();
+    // Messages go to stderr, journald adds the timestamps when running under systemd
+    env_logger::Builder::from_env(env_logger::Env::new().filter_or(LOG_ENV, "info"))
+        .format_timestamp(None)
+        .init();
+    if dotenv_loaded {
+        info!("Loaded environment variables from .env file");
+    }
+
+    // Only writing the API specification
+    if let Some(file) = args.dump_openapi {
+        return match dump_openapi(&file) {
+            Ok(_) => {
+                info!("OpenAPI specification was written to: {}", file.display());
+                ExitCode::SUCCESS
+            }
+            Err(message) => {
+                error!("{}", message);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // Only writing the JSON Schemas
+    if let Some(dir) = args.dump_schemas {
+        return match dump_schemas(&dir) {
+            Ok(_) => {
+                info!("JSON Schemas were written to: {}", dir.display());
+                ExitCode::SUCCESS
+            }
+            Err(message) => {
+                error!("{}", message);
+                ExitCode::FAILURE
+            }
+        };
     }
 
-    // Using default SifisHome
-    let sifis_home = SifisHome::new();
-    println!(
+    // Using default SifisHome, a broken symbolic link as the path would fail every save
+    let sifis_home = match SifisHome::try_from_config(SifisHomeConfig::default()) {
+        Ok(sifis_home) => sifis_home,
+        Err(err) => {
+            error!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    info!(
         "SIFIS-Home path: {}",
         sifis_home
             .home_path()
@@ -47,26 +151,130 @@ async fn main() -> ExitCode {
             .expect("Could not get SIFIS-Home path")
     );
 
+    // Counting the boots helps diagnosing reboot loops
+    match sifis_home.increment_boot_count() {
+        Ok(boot_count) => info!("Boot count: {}", boot_count),
+        Err(err) => warn!("Could not update the boot count: {}", err),
+    }
+
+    // Timestamps are meaningless until the clock is set
+    if get_unix_time_ms().map_or(true, is_clock_likely_unset) {
+        warn!("The system clock is likely not set, UUID timestamps will be wrong");
+    }
+
+    // Addresses from MOBILE_API_BIND, empty when ROCKET_ADDRESS should be used
+    let bind_addresses = match env::var(BIND_ENV) {
+        Ok(list) if !list.trim().is_empty() => match parse_bind_addresses(&list) {
+            Ok(addresses) => addresses,
+            Err(message) => {
+                error!("{}", message);
+                return ExitCode::FAILURE;
+            }
+        },
+        _ => Vec::new(),
+    };
+
     let device_state = match DeviceState::new(sifis_home) {
         Ok(device_state) => device_state,
         Err(message) => {
-            eprintln!("{}", message);
+            error!("{}", message);
             return ExitCode::FAILURE;
         }
     };
+    if let Some(token) = device_state.provisioning_token() {
+        info!("Device information not found, waiting for provisioning with the token {token}");
+    }
 
-    let launch_result = build_rocket(device_state).launch().await;
+    let launch_result = if bind_addresses.is_empty() {
+        build_rocket(device_state).launch().await.map(|_| ())
+    } else {
+        launch_on_addresses(device_state, bind_addresses).await
+    };
 
     // Check launch result
     match launch_result {
         Ok(_) => ExitCode::SUCCESS,
         Err(err) => {
-            eprintln!("Rocket had an error: {}", err);
+            error!("Rocket had an error: {}", err);
             ExitCode::FAILURE
         }
     }
 }
 
+/// Parse a comma-separated *list* of IPv4 and IPv6 addresses
+///
+/// IPv6 addresses may also be written in brackets, like `[::1]`. Every entry must be a valid
+/// address, and duplicates are removed.
+fn parse_bind_addresses(list: &str) -> Result<Vec<IpAddr>, String> {
+    let mut addresses = Vec::new();
+    for entry in list.split(',') {
+        let entry = entry.trim();
+        let address = entry
+            .strip_prefix('[')
+            .and_then(|address| address.strip_suffix(']'))
+            .unwrap_or(entry);
+        match address.parse::<IpAddr>() {
+            Ok(address) => {
+                if !addresses.contains(&address) {
+                    addresses.push(address);
+                }
+            }
+            Err(_) => return Err(format!("Invalid address {:?} in {}", entry, BIND_ENV)),
+        }
+    }
+    Ok(addresses)
+}
+
+/// Launch a Rocket instance for each of the *addresses*
+///
+/// The instances share the same DeviceState and the other settings, like the port. If one of the
+/// instances fails, the others are stopped too.
+async fn launch_on_addresses(
+    state: DeviceState,
+    addresses: Vec<IpAddr>,
+) -> Result<(), rocket::Error> {
+    let launches = addresses.into_iter().map(|address| {
+        info!("Listening on address: {}", address);
+        let rocket = build_rocket(state.clone());
+        let figment = rocket.figment().clone().merge(("address", address));
+        rocket.configure(figment).launch()
+    });
+    try_join_all(launches).await.map(|_| ())
+}
+
+/// Write the OpenAPI specification of the API v1 to the *file*
+///
+/// The API key header is read from the `MOBILE_API_KEY_HEADER` environment variable, like when
+/// the server is running.
+fn dump_openapi(file: &Path) -> Result<(), String> {
+    let spec = api_v1::openapi_spec(&api_key_header_from_env());
+    let json = serde_json::to_string_pretty(&spec)
+        .map_err(|err| format!("Could not serialize the OpenAPI specification: {}", err))?;
+    fs::write(file, json).map_err(|err| format!("Could not write {}: {}", file.display(), err))
+}
+
+/// Write the JSON Schemas of the shared types to the *dir*
+///
+/// Each schema is written to a `<Type>.schema.json` file, where the type name is also the `title`
+/// of the schema. The directory is created if it does not exist.
+fn dump_schemas(dir: &Path) -> Result<(), String> {
+    let schemas: [(&str, RootSchema); 3] = [
+        ("DeviceConfig", schema_for!(DeviceConfig)),
+        ("DeviceInfo", schema_for!(DeviceInfo)),
+        ("DeviceStatus", schema_for!(DeviceStatus)),
+    ];
+    fs::create_dir_all(dir)
+        .map_err(|err| format!("Could not create {}: {}", dir.display(), err))?;
+    for (name, schema) in schemas {
+        let file = dir.join(format!("{name}.schema.json"));
+        let json = serde_json::to_string_pretty(&schema)
+            .map_err(|err| format!("Could not serialize the {} schema: {}", name, err))?;
+        fs::write(&file, json)
+            .map_err(|err| format!("Could not write {}: {}", file.display(), err))?;
+    }
+    Ok(())
+}
+
 /// Builds Mobile API Rocket
 ///
 /// This function creates a Rocket object that is ready to launch. Rocket is created from the main
@@ -96,15 +304,60 @@ fn build_rocket(state: DeviceState) -> Rocket<Build> {
         .resource_path("static")
         .expect("Could not find static files path");
 
+    // The API documentation should show the configured key header
+    let api_routes = api_v1::routes(state.api_key_header());
+
     // Launch server
     rocket::build()
         // Manage state through DeviceState object
         .manage(state)
+        // JSON error responses for the API, and the chosen policy for missing static files
+        .register("/", static_catchers())
+        .register("/v1/", catchers())
         // Mount static files to root
         .mount("/", FileServer::from(static_files))
         // Mount APIv1
-        .mount("/v1/", api_v1::routes())
+        .mount("/v1/", api_routes)
         // API documentation from the implementation
         .mount("/v1/rapidoc/", make_rapidoc(&rapidoc_config))
         .mount("/v1/swagger-ui/", make_swagger_ui(&swagger_ui_config))
+        // Indent JSON responses for GET requests with `?pretty=true`, before compressing them
+        .attach(PrettyJson)
+        // Compress larger JSON responses for clients that accept it
+        .attach(Compression::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn test_parse_bind_addresses() {
+        let addresses = parse_bind_addresses("127.0.0.1, ::1,[fe80::1], 127.0.0.1").unwrap();
+        assert_eq!(
+            addresses,
+            vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+            ]
+        );
+        assert_eq!(
+            parse_bind_addresses("0.0.0.0").unwrap(),
+            vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)]
+        );
+
+        // Every entry must be valid
+        for list in [
+            "127.0.0.1,",
+            "127.0.0.1,localhost",
+            "::1,256.0.0.1",
+            "[::1",
+            "[127.0.0.1]:8000",
+        ] {
+            let error = parse_bind_addresses(list).unwrap_err();
+            assert!(error.contains(BIND_ENV), "{}", error);
+        }
+    }
 }
@@ -0,0 +1,122 @@
+//! Pretty-printing for JSON responses
+//!
+//! The API sends compact JSON to save bandwidth. When debugging with tools like `curl`, indented
+//! JSON is easier to read, so GET requests can ask for it with the `pretty=true` query parameter.
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Method;
+use rocket::{Request, Response};
+use serde_json::Value;
+use std::io::Cursor;
+
+/// Query parameter for requesting pretty JSON
+pub const PRETTY_QUERY: &str = "pretty";
+
+/// Check if the *request* asks for pretty JSON
+///
+/// Only GET requests with `pretty=true` qualify. Other values, like `pretty=1`, are ignored.
+pub fn wants_pretty(request: &Request<'_>) -> bool {
+    request.method() == Method::Get
+        && matches!(request.query_value::<&str>(PRETTY_QUERY), Some(Ok("true")))
+}
+
+/// Indent the compact JSON *body*
+///
+/// Returns `None` if the body is not valid JSON. Object keys are written in sorted order.
+pub fn prettify(body: &[u8]) -> Option<Vec<u8>> {
+    let value: Value = serde_json::from_slice(body).ok()?;
+    serde_json::to_vec_pretty(&value).ok()
+}
+
+/// Fairing indenting JSON responses on request
+///
+/// The fairing must be attached before the [Compression](crate::compression::Compression), so
+/// that the body is indented before it is compressed.
+pub struct PrettyJson;
+
+#[rocket::async_trait]
+impl Fairing for PrettyJson {
+    fn info(&self) -> Info {
+        Info {
+            name: "Pretty JSON Responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        // Only JSON responses that are not already encoded
+        let is_json = response
+            .content_type()
+            .is_some_and(|content_type| content_type.is_json());
+        if !is_json || response.headers().contains("Content-Encoding") || !wants_pretty(request) {
+            return;
+        }
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(_) => return,
+        };
+        let body = prettify(&body).unwrap_or(body);
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_v1::tests_common::*;
+    use rocket::http::Status;
+
+    #[test]
+    fn test_prettify() {
+        assert_eq!(prettify(b"not json"), None);
+        assert_eq!(prettify(b"1").unwrap(), b"1");
+        let pretty = prettify(br#"{"b":[1,2],"a":"x"}"#).unwrap();
+        assert_eq!(
+            String::from_utf8(pretty).unwrap(),
+            "{\n  \"a\": \"x\",\n  \"b\": [\n    1,\n    2\n  ]\n}"
+        );
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_pretty_response() {
+        let (_test_dir, client) = create_test_setup();
+        let api_key_header = api_key_header_for(client.rocket().state().unwrap());
+        let uri = "/v1/device/info";
+
+        let response = client.get(uri).header(api_key_header.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let compact = response.into_string().unwrap();
+        assert!(!compact.contains('\n'));
+
+        let response = client
+            .get(format!("{uri}?pretty=true"))
+            .header(api_key_header.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let pretty = response.into_string().unwrap();
+        assert!(pretty.contains('\n'));
+        assert_eq!(
+            serde_json::from_str::<Value>(&pretty).unwrap(),
+            serde_json::from_str::<Value>(&compact).unwrap()
+        );
+
+        // Error responses are indented too
+        let response = client.get("/v1/device/status?pretty=true").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.into_string().unwrap().contains('\n'));
+
+        // Anything but true keeps the compact form
+        for query in ["pretty=false", "pretty=1", "pretty"] {
+            let response = client
+                .get(format!("{uri}?{query}"))
+                .header(api_key_header.clone())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.into_string().unwrap(), compact, "{query}");
+        }
+    }
+}
@@ -0,0 +1,414 @@
+//! Running the device scripts
+//!
+//! The command endpoints and the device configuration hooks run shell scripts from the scripts
+//! directory. This module starts the scripts, waits for them within a time limit, and reports how
+//! they ended.
+
+use crate::state::DeviceState;
+use log::debug;
+#[cfg(feature = "commands")]
+use rocket::tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use std::io::ErrorKind;
+#[cfg(feature = "commands")]
+use std::io::{BufRead, BufReader, Read};
+#[cfg(feature = "commands")]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+#[cfg(feature = "commands")]
+use std::process::{Child, Command, ExitStatus};
+#[cfg(feature = "commands")]
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+#[cfg(feature = "commands")]
+use std::time::Instant;
+
+/// How long a command script may run before it is stopped
+pub const SCRIPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Reasons why running a command script failed
+#[derive(Debug)]
+pub enum ScriptError {
+    /// The script file does not exist
+    NotFound {
+        /// Path to the script
+        script: PathBuf,
+    },
+
+    /// The script exists but can not be executed
+    NotExecutable {
+        /// Path to the script
+        script: PathBuf,
+    },
+
+    /// The script ran but exited with a failure
+    Failed {
+        /// Path to the script
+        script: PathBuf,
+        /// Exit code, or None if the script was terminated by a signal
+        code: Option<i32>,
+        /// Error output from the script
+        stderr: String,
+    },
+
+    /// The script did not finish in time and was stopped
+    Timeout {
+        /// Path to the script
+        script: PathBuf,
+        /// The time limit that was exceeded
+        timeout: Duration,
+    },
+
+    /// Other IO error while running the script
+    Io {
+        /// Path to the script
+        script: PathBuf,
+        /// The underlying error
+        error: std::io::Error,
+    },
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScriptError::NotFound { script } => write!(f, "script {script:?} was not found"),
+            ScriptError::NotExecutable { script } => {
+                write!(f, "script {script:?} is not executable")
+            }
+            ScriptError::Failed {
+                script,
+                code,
+                stderr,
+            } => {
+                match code {
+                    Some(code) => write!(f, "script {script:?} failed with exit code {code}")?,
+                    None => write!(f, "script {script:?} was terminated by a signal")?,
+                }
+                let stderr = stderr.trim();
+                if !stderr.is_empty() {
+                    write!(f, ": {stderr}")?;
+                }
+                Ok(())
+            }
+            ScriptError::Timeout { script, timeout } => write!(
+                f,
+                "script {script:?} did not finish in {} seconds",
+                timeout.as_secs()
+            ),
+            ScriptError::Io { script, error } => write!(f, "script {script:?}: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Run script from the server `scripts` directory with the command line *args*
+pub(crate) async fn run_script_with_args(
+    state: &DeviceState,
+    script_name: &'static str,
+    args: &[&str],
+) -> Result<(), ScriptError> {
+    let mut script = match state.scripts_path() {
+        Ok(path) => path,
+        Err(_) => {
+            return Err(ScriptError::NotFound {
+                script: PathBuf::from("scripts").join(script_name),
+            })
+        }
+    };
+    script.push(script_name);
+    run_script_file(&script, args, SCRIPT_TIMEOUT).await
+}
+
+/// Run the *script* with the *args* and wait for it to finish within the *timeout*
+///
+/// The output is read while the script runs, so scripts writing a lot of output do not block.
+/// The script and the processes it started are killed if it does not finish in time.
+async fn run_script_file(
+    script: &Path,
+    args: &[&str],
+    timeout: Duration,
+) -> Result<(), ScriptError> {
+    debug!("Running: {:?}", script);
+    let child = tokio::process::Command::new(script)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|error| spawn_error(script, error))?;
+    let pid = child.id();
+
+    // The unfinished script is killed with the processes it started
+    let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => output.map_err(|error| ScriptError::Io {
+            script: script.to_path_buf(),
+            error,
+        })?,
+        Err(_) => {
+            if let Some(pid) = pid {
+                kill_process_group(pid);
+            }
+            return Err(ScriptError::Timeout {
+                script: script.to_path_buf(),
+                timeout,
+            });
+        }
+    };
+    if !output.status.success() {
+        return Err(ScriptError::Failed {
+            script: script.to_path_buf(),
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).into(),
+        });
+    }
+    let output_stdout = String::from_utf8_lossy(&output.stdout);
+    if !output_stdout.is_empty() {
+        debug!("{}", output_stdout)
+    }
+    Ok(())
+}
+
+/// Error for the *script* that could not be started
+fn spawn_error(script: &Path, error: std::io::Error) -> ScriptError {
+    let script = script.to_path_buf();
+    match error.kind() {
+        ErrorKind::NotFound => ScriptError::NotFound { script },
+        ErrorKind::PermissionDenied => ScriptError::NotExecutable { script },
+        _ => ScriptError::Io { script, error },
+    }
+}
+
+/// Kill the process group led by the script with the *pid*
+///
+/// The scripts are started in their own process groups, so this also stops the processes the
+/// script started. Otherwise they could keep the output pipes open after the script is gone.
+fn kill_process_group(pid: u32) {
+    // SAFETY: kill only sends a signal and does not touch the memory of this process
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Start the *script* with piped stdout and stderr in a new process group
+#[cfg(feature = "commands")]
+fn spawn_piped(script: &Path) -> Result<Child, ScriptError> {
+    debug!("Running: {:?}", script);
+    Command::new(script)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|error| spawn_error(script, error))
+}
+
+/// Wait for the *child* running the *script* to exit, or stop it after the *timeout*
+///
+/// This blocks the thread, so it is only used in the threads started by [spawn_script].
+#[cfg(feature = "commands")]
+fn wait_with_timeout(
+    child: &mut Child,
+    script: &Path,
+    timeout: Duration,
+) -> Result<ExitStatus, ScriptError> {
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) if started.elapsed() >= timeout => {
+                kill_process_group(child.id());
+                let _ = child.wait();
+                return Err(ScriptError::Timeout {
+                    script: script.to_path_buf(),
+                    timeout,
+                });
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+            Err(error) => {
+                return Err(ScriptError::Io {
+                    script: script.to_path_buf(),
+                    error,
+                })
+            }
+        }
+    }
+}
+
+/// Messages from a script started with [spawn_script]
+#[cfg(feature = "commands")]
+#[derive(Debug)]
+pub(crate) enum ScriptMessage {
+    /// A line written to stdout or stderr
+    Line(String),
+    /// The script exited with the code, or None if it was terminated by a signal
+    Exit(Option<i32>),
+    /// The script could not be run to the end
+    Error(ScriptError),
+}
+
+/// Start the *script* and send its output lines as they are written
+///
+/// The lines from stdout and stderr are sent in the order they are read, and the last message
+/// tells how the script ended. The script is stopped after the *timeout*.
+#[cfg(feature = "commands")]
+pub(crate) fn spawn_script(
+    script: &Path,
+    timeout: Duration,
+) -> Result<UnboundedReceiver<ScriptMessage>, ScriptError> {
+    let mut child = spawn_piped(script)?;
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let readers = [
+        child
+            .stdout
+            .take()
+            .map(|stdout| forward_lines(stdout, sender.clone())),
+        child
+            .stderr
+            .take()
+            .map(|stderr| forward_lines(stderr, sender.clone())),
+    ];
+    let script = script.to_path_buf();
+    thread::spawn(move || {
+        let message = match wait_with_timeout(&mut child, &script, timeout) {
+            Ok(status) => {
+                // All output is sent before the exit
+                for reader in readers.into_iter().flatten() {
+                    let _ = reader.join();
+                }
+                ScriptMessage::Exit(status.code())
+            }
+            Err(error) => ScriptMessage::Error(error),
+        };
+        let _ = sender.send(message);
+    });
+    Ok(receiver)
+}
+
+/// Send the lines from the *reader* until it is closed
+#[cfg(feature = "commands")]
+fn forward_lines<R>(reader: R, sender: UnboundedSender<ScriptMessage>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        for line in BufReader::new(reader).split(b'\n').map_while(Result::ok) {
+            let line = String::from_utf8_lossy(&line);
+            let line = line.strip_suffix('\r').unwrap_or(&line).to_string();
+            if sender.send(ScriptMessage::Line(line)).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+    use tokio::runtime::Builder;
+
+    /// Write a shell script with the given *body* and *mode* to the directory
+    fn write_script(dir: &TempDir, name: &str, body: &str, mode: u32) -> PathBuf {
+        let script = dir.path().join(name);
+        fs::write(&script, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(mode)).unwrap();
+        script
+    }
+
+    /// Run [run_script_file] to the end in a new runtime
+    fn run_script_blocking(
+        script: &Path,
+        args: &[&str],
+        timeout: Duration,
+    ) -> Result<(), ScriptError> {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(run_script_file(script, args, timeout))
+    }
+
+    // Test ignored for Miri because running processes is not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_run_script_blocking() {
+        let test_dir = TempDir::new().unwrap();
+        let timeout = Duration::from_secs(10);
+
+        let script = write_script(&test_dir, "ok.sh", "echo ok", 0o755);
+        assert!(run_script_blocking(&script, &[], timeout).is_ok());
+
+        let script = test_dir.path().join("missing.sh");
+        let error = run_script_blocking(&script, &[], timeout).unwrap_err();
+        assert!(matches!(error, ScriptError::NotFound { .. }));
+
+        let script = write_script(&test_dir, "no_exec.sh", "echo ok", 0o644);
+        let error = run_script_blocking(&script, &[], timeout).unwrap_err();
+        assert!(matches!(error, ScriptError::NotExecutable { .. }));
+
+        let script = write_script(&test_dir, "fail.sh", "echo oops >&2\nexit 3", 0o755);
+        let error = run_script_blocking(&script, &[], timeout).unwrap_err();
+        assert!(matches!(error, ScriptError::Failed { code: Some(3), .. }));
+        assert!(error.to_string().ends_with("failed with exit code 3: oops"));
+
+        let script = write_script(&test_dir, "slow.sh", "sleep 10", 0o755);
+        let error = run_script_blocking(&script, &[], Duration::from_millis(100)).unwrap_err();
+        assert!(matches!(error, ScriptError::Timeout { .. }));
+
+        let output = test_dir.path().join("args.txt");
+        let body = format!("echo \"$1 $2\" > {:?}", output);
+        let script = write_script(&test_dir, "args.sh", &body, 0o755);
+        assert!(run_script_blocking(&script, &["Europe/Rome", "it"], timeout).is_ok());
+        assert_eq!(fs::read_to_string(&output).unwrap(), "Europe/Rome it\n");
+
+        // Output larger than the pipe buffer does not block the script until the timeout
+        let body = "head -c 1000000 /dev/zero\nhead -c 1000000 /dev/zero >&2";
+        let script = write_script(&test_dir, "large_output.sh", body, 0o755);
+        assert!(run_script_blocking(&script, &[], timeout).is_ok());
+    }
+
+    /// Collect the messages from [spawn_script] until the channel is closed
+    #[cfg(feature = "commands")]
+    fn collect_messages(mut receiver: UnboundedReceiver<ScriptMessage>) -> Vec<ScriptMessage> {
+        let mut messages = Vec::new();
+        while let Some(message) = receiver.blocking_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    // Test ignored for Miri because running processes is not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[cfg(feature = "commands")]
+    #[test]
+    fn test_spawn_script() {
+        let test_dir = TempDir::new().unwrap();
+        let timeout = Duration::from_secs(10);
+
+        let body = "echo one\necho two\necho three\nexit 2";
+        let script = write_script(&test_dir, "lines.sh", body, 0o755);
+        let messages = collect_messages(spawn_script(&script, timeout).unwrap());
+        assert_eq!(messages.len(), 4);
+        for (message, expected) in messages.iter().zip(["one", "two", "three"]) {
+            assert!(matches!(message, ScriptMessage::Line(line) if line == expected));
+        }
+        assert!(matches!(messages[3], ScriptMessage::Exit(Some(2))));
+
+        let script = write_script(&test_dir, "stderr.sh", "echo oops >&2", 0o755);
+        let messages = collect_messages(spawn_script(&script, timeout).unwrap());
+        assert!(matches!(&messages[0], ScriptMessage::Line(line) if line == "oops"));
+        assert!(matches!(messages[1], ScriptMessage::Exit(Some(0))));
+
+        let script = test_dir.path().join("missing.sh");
+        let error = spawn_script(&script, timeout).unwrap_err();
+        assert!(matches!(error, ScriptError::NotFound { .. }));
+
+        let script = write_script(&test_dir, "slow.sh", "echo start\nsleep 10", 0o755);
+        let receiver = spawn_script(&script, Duration::from_millis(200)).unwrap();
+        let messages = collect_messages(receiver);
+        assert!(matches!(
+            messages.last(),
+            Some(ScriptMessage::Error(ScriptError::Timeout { .. }))
+        ));
+    }
+}
@@ -3,18 +3,50 @@
 //! The DeviceState is used to ensure that multiple commands are not run at the same time.
 //! The module also contains some other components needed for the backend.
 
-use crate::device_status::{DeviceStatus, DiskStatus, MemStatus};
-use mobile_api::configs::{DeviceConfig, DeviceInfo};
+use crate::api_common::{api_key_header_from_env, static_not_found_from_env, StaticNotFound};
+use crate::device_status::{
+    merge_duplicate_disks, CpuInfo, DeviceStatus, DiskStatus, MemStatus, PartialDeviceStatus,
+    ProcessOrder, ProcessStatus, StatusSection,
+};
+use mobile_api::configs::{DeviceConfig, DeviceInfo, ProvisioningToken};
+use mobile_api::security::{get_unix_time_ms, PublicKey, SRNG};
+use mobile_api::store::ConfigStore;
 use mobile_api::SifisHome;
-use std::cmp::Ordering;
+use ring::constant_time::verify_slices_are_equal;
+use rocket::tokio::sync::watch;
+use rocket::tokio::time::sleep;
+use std::cmp::{Ordering, Reverse};
 use std::env;
+use std::fs;
 use std::ops::Deref;
-use std::path::PathBuf;
-use std::sync::{Mutex, RwLock};
-use sysinfo::{CpuExt, CpuRefreshKind, Disk, DiskExt, RefreshKind, System, SystemExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard, TryLockError};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use sysinfo::{
+    CpuExt, CpuRefreshKind, Disk, DiskExt, PidExt, ProcessExt, ProcessRefreshKind, RefreshKind,
+    System, SystemExt,
+};
 
 /// Managed state structure
+///
+/// Cloning the state is cheap, and the clones share everything except the API key header name.
+/// This allows running multiple Rocket instances, for example one for each bind address, that
+/// still see the same busy status and configuration.
+#[derive(Clone)]
 pub struct DeviceState {
+    /// State shared between the clones
+    shared: Arc<SharedState>,
+
+    /// Name of the header carrying the API key
+    api_key_header: String,
+
+    /// How requests for missing static files are answered
+    static_not_found: StaticNotFound,
+}
+
+/// Part of the DeviceState shared between the clones
+struct SharedState {
     /// SIFIS Home configurations instance
     sifis_home: SifisHome,
 
@@ -24,14 +56,218 @@ pub struct DeviceState {
     /// Device configuration
     device_config: RwLock<Option<DeviceConfig>>,
 
-    /// Device information
-    device_info: DeviceInfo,
+    /// Last known device configuration for reads that must not wait for writers
+    last_config: Mutex<Option<DeviceConfig>>,
+
+    /// Where the device configuration is persisted
+    config_store: Box<dyn ConfigStore>,
+
+    /// Publishes the device configuration to the subscribers when it changes
+    config_sender: watch::Sender<Option<Arc<DeviceConfig>>>,
+
+    /// Device information, or None while waiting for provisioning
+    device_info: RwLock<Option<Arc<DeviceInfo>>>,
+
+    /// Identity that the provision request must know, if the server started without device
+    /// information
+    provisioning_token: Option<ProvisioningToken>,
+
+    /// Has the configuration changed since the last restart
+    reboot_required: AtomicBool,
+
+    /// Has a factory reset been done without restarting the device
+    pending_reset: AtomicBool,
+
+    /// Confirmation tokens that have not been used yet
+    #[cfg(feature = "commands")]
+    confirmation_tokens: Mutex<Vec<ConfirmationToken>>,
 
     /// An object for querying the system status
     sys_info: Mutex<System>,
 
     /// What system information is updated when the system status is queried
     sys_info_refreshes: RefreshKind,
+
+    /// Are disks with the same device and file system merged in the status
+    merge_duplicate_disks: bool,
+}
+
+/// Characters used in the confirmation tokens
+#[cfg(feature = "commands")]
+const CONFIRMATION_TOKEN_ALPHABET: [char; 36] = [
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's',
+    't', 'u', 'v', 'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
+];
+
+/// Length of the confirmation tokens, about 103 bits of randomness
+#[cfg(feature = "commands")]
+const CONFIRMATION_TOKEN_LENGTH: usize = 20;
+
+/// Most confirmation tokens kept at the same time, older tokens are dropped first
+#[cfg(feature = "commands")]
+pub const MAX_CONFIRMATION_TOKENS: usize = 16;
+
+/// One-time token confirming a destructive command
+#[cfg(feature = "commands")]
+struct ConfirmationToken {
+    /// Command the token was issued for
+    command: String,
+    /// The token value given to the client
+    token: String,
+    /// The token can not be used after this
+    expires_at: Instant,
+}
+
+/// Environment variable for allowing the server to start without the device information
+pub const ALLOW_PROVISIONING_ENV: &str = "MOBILE_API_ALLOW_PROVISIONING";
+
+/// Environment variable for merging the same device mounted in several places
+pub const MERGE_DISKS_ENV: &str = "MOBILE_API_MERGE_DISKS";
+
+/// Check if the environment variable with the *name* is set to `1`, `true`, or `yes`
+fn env_flag(name: &str) -> bool {
+    match env::var(name) {
+        Ok(value) => matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes"
+        ),
+        Err(_) => false,
+    }
+}
+
+/// Check if provisioning is allowed with the `MOBILE_API_ALLOW_PROVISIONING` variable
+fn provisioning_allowed_from_env() -> bool {
+    env_flag(ALLOW_PROVISIONING_ENV)
+}
+
+/// Consistent view of the device information and configuration
+///
+/// Created with [DeviceState::snapshot].
+#[derive(Clone, Debug)]
+pub struct DeviceSnapshot {
+    /// Device information, or None while waiting for provisioning
+    pub info: Option<Arc<DeviceInfo>>,
+
+    /// Device configuration, or None if the device is not configured
+    pub config: Option<DeviceConfig>,
+}
+
+/// Reasons why provisioning failed
+#[derive(Debug)]
+pub enum ProvisionError {
+    /// The device information already exists
+    AlreadyProvisioned,
+
+    /// The provisioning token was missing or did not match
+    TokenInvalid,
+
+    /// Creating or saving the device information failed
+    Failed(mobile_api::error::Error),
+}
+
+/// Reasons why [try_set_config](DeviceState::try_set_config) did not save the configuration
+#[derive(Debug)]
+pub enum SetConfigError {
+    /// Another write kept the configuration locked for the whole wait
+    Locked,
+
+    /// Saving the configuration failed
+    Failed(String),
+}
+
+/// How often a locked configuration is tried again while waiting for it
+const CONFIG_LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Reasons why activating a configuration profile failed
+#[derive(Debug)]
+pub enum ActivateProfileError {
+    /// The profile could not be loaded, for example because it does not exist
+    Load(mobile_api::error::Error),
+
+    /// Saving the profile as the active configuration failed
+    Save(String),
+}
+
+/// Check if the reboot marker *file* was written after the system was booted
+///
+/// A marker from before the boot time is left over from a reboot that was not requested through
+/// the API, and the changes it stands for are already in effect.
+fn is_reboot_marker_current(file: &Path, boot_time: u64) -> bool {
+    let modified = match fs::metadata(file).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        Err(_) => return false,
+    };
+    match modified.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs() >= boot_time,
+        Err(_) => false,
+    }
+}
+
+/// New identity for a device waiting for provisioning
+fn new_provisioning_token(srng: &SRNG) -> mobile_api::error::Result<ProvisioningToken> {
+    Ok(ProvisioningToken {
+        uuid: srng.generate_uuid()?,
+        authorization_key: srng.generate_key()?,
+    })
+}
+
+/// UUID and authorization key of the *token* for comparing them in constant time
+fn provisioning_token_bytes(token: &ProvisioningToken) -> [u8; 48] {
+    let mut bytes = [0u8; 48];
+    bytes[..16].copy_from_slice(token.uuid.as_bytes());
+    bytes[16..].copy_from_slice(token.authorization_key.as_bytes());
+    bytes
+}
+
+/// CPU usage per core, physical core count, and CPU information from the refreshed *sys_info*
+fn cpu_status(sys_info: &System) -> (Vec<f32>, Option<usize>, CpuInfo) {
+    let mut cpu_usage = Vec::new();
+    let mut frequency = Vec::new();
+    for cpu in sys_info.cpus() {
+        cpu_usage.push(cpu.cpu_usage() * 0.01);
+        frequency.push(cpu.frequency());
+    }
+    let brand = match sys_info.cpus().first() {
+        Some(cpu) => cpu.brand().to_string(),
+        None => sys_info.global_cpu_info().brand().to_string(),
+    };
+    (
+        cpu_usage,
+        sys_info.physical_core_count(),
+        CpuInfo { brand, frequency },
+    )
+}
+
+/// RAM and swap usage from the refreshed *sys_info*
+fn mem_status(sys_info: &System) -> (MemStatus, Option<MemStatus>) {
+    // Divide by zero if the computer does not have memory... unlikely
+    let mem_usage = MemStatus::new(
+        sys_info.total_memory(),
+        sys_info.available_memory(),
+        sys_info.used_memory(),
+    );
+
+    // However systems without swap do exists
+    let swap_usage = if sys_info.total_swap() > 0 {
+        Some(MemStatus::new(
+            sys_info.total_swap(),
+            sys_info.free_swap(),
+            sys_info.used_swap(),
+        ))
+    } else {
+        None
+    };
+    (mem_usage, swap_usage)
+}
+
+/// Load average and process count from the refreshed *sys_info*
+fn load_status(sys_info: &System) -> ([f32; 3], usize) {
+    let load_average = [
+        sys_info.load_average().one as f32,
+        sys_info.load_average().five as f32,
+        sys_info.load_average().fifteen as f32,
+    ];
+    (load_average, sys_info.processes().len())
 }
 
 /// Sorting disk information based on device file
@@ -39,75 +275,202 @@ fn sort_disks_by_device_name(a: &Disk, b: &Disk) -> Ordering {
     a.name().cmp(b.name())
 }
 
+/// Available space of the disk that contains the *path*
+///
+/// The *disks* are pairs of a mount point and the available bytes on it. The disk with the longest
+/// mount point containing the path is used, since nested mounts hide the disks under them.
+fn free_space_on_disks<'a>(
+    disks: impl IntoIterator<Item = (&'a Path, u64)>,
+    path: &Path,
+) -> Option<u64> {
+    disks
+        .into_iter()
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+        .map(|(_, available)| available)
+}
+
 impl DeviceState {
     /// Creating server state object
     ///
     /// Function tries to load device info and return DeviceState for the server on success.
     ///
     /// If something goes wrong, then message is returned as error
+    ///
+    /// The device configuration is stored in the `config.json` file. Use
+    /// [with_store](DeviceState::with_store) for a custom store.
     pub fn new(sifis_home: SifisHome) -> Result<DeviceState, String> {
-        // Try to load device info
-        let device_info = match sifis_home.load_info() {
-            Ok(device_info) => device_info,
-            Err(error) => {
-                // Special message for file not found error
-                if let mobile_api::error::ErrorKind::IoError(io_error) = error.kind() {
-                    if io_error.kind() == std::io::ErrorKind::NotFound {
-                        return Err(format!(
-                            "Device information file {:?} not found.\n\
-                             You can use create_device_info application to create it.",
-                            sifis_home.info_file_path()
-                        ));
-                    }
-                };
+        let config_store = Box::new(sifis_home.config_store());
+        Self::with_store(sifis_home, config_store)
+    }
 
-                // Error message for any other error
-                return Err(format!(
-                    "Could not load device information file: {:?}\n{}",
-                    sifis_home.info_file_path(),
-                    error
-                ));
+    /// Creating server state object with a custom configuration store
+    ///
+    /// Works like [new](DeviceState::new), but the device configuration is loaded from and saved
+    /// to the given *config_store*.
+    ///
+    /// When the `MOBILE_API_ALLOW_PROVISIONING` environment variable is set to `1`, `true`, or
+    /// `yes`, a missing device information file is allowed. See
+    /// [with_provisioning](DeviceState::with_provisioning).
+    pub fn with_store(
+        sifis_home: SifisHome,
+        config_store: Box<dyn ConfigStore>,
+    ) -> Result<DeviceState, String> {
+        Self::with_provisioning(sifis_home, config_store, provisioning_allowed_from_env())
+    }
+
+    /// Creating server state object that may wait for provisioning
+    ///
+    /// A stored configuration that cannot be loaded is an error, it is not treated as missing.
+    ///
+    /// With *allow_provisioning*, the state is created even if the device information file is
+    /// missing. Endpoints requiring the API key are then refused until the device information is
+    /// created with [provision](DeviceState::provision). A new identity is generated for the
+    /// device, and provisioning requires its [provisioning_token](DeviceState::provisioning_token).
+    pub fn with_provisioning(
+        sifis_home: SifisHome,
+        config_store: Box<dyn ConfigStore>,
+        allow_provisioning: bool,
+    ) -> Result<DeviceState, String> {
+        // Try to load device info
+        let device_info = if sifis_home.info_exists() {
+            match sifis_home.load_info() {
+                Ok(device_info) => Some(Arc::new(device_info)),
+                Err(error) => {
+                    return Err(format!(
+                        "Could not load device information file: {:?}\n{}",
+                        sifis_home.info_file_path(),
+                        error
+                    ));
+                }
             }
+        } else if allow_provisioning {
+            // Waiting for provisioning
+            None
+        } else {
+            return Err(format!(
+                "Device information file {:?} not found.\n\
+                 You can use create_device_info application to create it.",
+                sifis_home.info_file_path()
+            ));
+        };
+
+        let provisioning_token = match device_info {
+            Some(_) => None,
+            None => Some(
+                new_provisioning_token(sifis_home.srng())
+                    .map_err(|error| format!("Could not create the provisioning token: {error}"))?,
+            ),
         };
 
         let busy_reason = Mutex::new("");
-        let device_config = RwLock::new(sifis_home.load_config().ok());
+        // Starting unconfigured would let the next save replace a configuration that only fails
+        // the current checks, like a device name that older versions accepted
+        let loaded_config = match config_store.load() {
+            Ok(config) => Some(config),
+            Err(error) if matches!(error.kind(), mobile_api::error::ErrorKind::NotConfigured) => {
+                None
+            }
+            Err(error) => {
+                return Err(format!(
+                    "Could not load device configuration: {error}\n\
+                     Fix or remove the configuration to start the server."
+                ))
+            }
+        };
+        // Overrides from the environment take precedence over the stored configuration
+        let loaded_config = DeviceConfig::from_env(loaded_config.as_ref())
+            .map_err(|error| format!("Invalid device configuration in the environment: {error}"))?;
+        let last_config = Mutex::new(loaded_config.clone());
+        let (config_sender, _) = watch::channel(loaded_config.clone().map(Arc::new));
+        let device_config = RwLock::new(loaded_config);
 
         let sys_info_refreshes = RefreshKind::new()
-            .with_cpu(CpuRefreshKind::new().with_cpu_usage())
+            .with_cpu(CpuRefreshKind::new().with_cpu_usage().with_frequency())
             .with_memory()
-            .with_disks_list();
+            .with_disks_list()
+            .with_processes(ProcessRefreshKind::new().with_cpu());
         let mut sys = System::new_with_specifics(sys_info_refreshes);
         sys.refresh_specifics(sys_info_refreshes);
+
+        // Marker from before the current boot is stale
+        let reboot_marker = sifis_home.reboot_required_file_path();
+        let reboot_required = is_reboot_marker_current(&reboot_marker, sys.boot_time());
+        if !reboot_required {
+            let _ = fs::remove_file(&reboot_marker);
+        }
+        let reboot_required = AtomicBool::new(reboot_required);
         let sys_info = Mutex::new(sys);
 
         Ok(DeviceState {
-            sifis_home,
-            busy_reason,
-            device_config,
-            device_info,
-            sys_info,
-            sys_info_refreshes,
+            shared: Arc::new(SharedState {
+                sifis_home,
+                busy_reason,
+                device_config,
+                last_config,
+                config_store,
+                config_sender,
+                device_info: RwLock::new(device_info),
+                provisioning_token,
+                reboot_required,
+                pending_reset: AtomicBool::new(false),
+                #[cfg(feature = "commands")]
+                confirmation_tokens: Mutex::new(Vec::new()),
+                sys_info,
+                sys_info_refreshes,
+                merge_duplicate_disks: env_flag(MERGE_DISKS_ENV),
+            }),
+            api_key_header: api_key_header_from_env(),
+            static_not_found: static_not_found_from_env(),
         })
     }
 
+    /// Name of the header carrying the API key
+    ///
+    /// Read from the `MOBILE_API_KEY_HEADER` environment variable when the state is created.
+    pub fn api_key_header(&self) -> &str {
+        &self.api_key_header
+    }
+
+    /// Change the name of the header carrying the API key
+    ///
+    /// This must be done before the state is given to Rocket, and the same name must be given to
+    /// the [api_v1::routes](crate::api_v1::routes) for the API documentation.
+    pub fn set_api_key_header(&mut self, api_key_header: String) {
+        self.api_key_header = api_key_header;
+    }
+
+    /// How requests for missing static files are answered
+    ///
+    /// Read from the `MOBILE_API_STATIC_NOT_FOUND` environment variable when the state is created.
+    pub fn static_not_found(&self) -> StaticNotFound {
+        self.static_not_found
+    }
+
+    /// Change how requests for missing static files are answered
+    ///
+    /// This must be done before the state is given to Rocket.
+    pub fn set_static_not_found(&mut self, static_not_found: StaticNotFound) {
+        self.static_not_found = static_not_found;
+    }
+
     /// Check if server is busy
     ///
     /// Returns busy reason or empty str if server is free
     pub fn busy(&self) -> &'static str {
-        self.busy_reason.lock().unwrap().deref()
+        self.shared.busy_reason.lock().unwrap().deref()
     }
 
     /// Clearing server busy status
     pub fn clear_busy(&self) {
-        *self.busy_reason.lock().unwrap() = "";
+        *self.shared.busy_reason.lock().unwrap() = "";
     }
 
     /// Set server busy reason message
     ///
     /// See also: [BusyGuard]
     pub fn set_busy(&self, reason: &'static str) -> Result<(), &'static str> {
-        let mut guard = self.busy_reason.lock().unwrap();
+        let mut guard = self.shared.busy_reason.lock().unwrap();
         if guard.is_empty() {
             *guard = reason;
             Ok(())
@@ -117,33 +480,81 @@ impl DeviceState {
     }
     /// Requesting system status
     pub fn device_status(&self) -> DeviceStatus {
-        let mut sys_info = self.sys_info.lock().unwrap();
-        sys_info.refresh_specifics(self.sys_info_refreshes);
-        sys_info.sort_disks_by(sort_disks_by_device_name);
+        let mut sys_info = self.shared.sys_info.lock().unwrap();
+        sys_info.refresh_specifics(self.shared.sys_info_refreshes);
+        let (cpu_usage, physical_cpus, cpu_info) = cpu_status(&sys_info);
+        let (mem_usage, swap_usage) = mem_status(&sys_info);
+        let disks = self.disk_status(&mut sys_info);
+        let (load_average, process_count) = load_status(&sys_info);
 
-        let mut cpu_usage = Vec::new();
-        for cpu in sys_info.cpus() {
-            cpu_usage.push(cpu.cpu_usage() * 0.01);
-        }
+        // Time before the epoch is reported as the epoch
+        let collected_at_unix_ms = get_unix_time_ms().unwrap_or_default();
 
-        // Divide by zero if the computer does not have memory... unlikely
-        let mem_usage = MemStatus::new(
-            sys_info.total_memory(),
-            sys_info.available_memory(),
-            sys_info.used_memory(),
-        );
+        DeviceStatus {
+            logical_cpus: cpu_usage.len(),
+            cpu_usage,
+            physical_cpus,
+            cpu_info,
+            mem_usage,
+            swap_usage,
+            disks,
+            uptime: sys_info.uptime(),
+            boot_time_unix: sys_info.boot_time(),
+            load_average,
+            process_count,
+            collected_at_unix_ms,
+        }
+    }
 
-        // However systems without swap do exists
-        let swap_usage = if sys_info.total_swap() > 0 {
-            Some(MemStatus::new(
-                sys_info.total_swap(),
-                sys_info.free_swap(),
-                sys_info.used_swap(),
-            ))
-        } else {
-            None
-        };
+    /// Requesting only the selected *sections* of the system status
+    ///
+    /// Only the system information needed for the sections is refreshed.
+    pub fn partial_device_status(&self, sections: &[StatusSection]) -> PartialDeviceStatus {
+        let mut sys_info = self.shared.sys_info.lock().unwrap();
+        let refreshes = self.shared.sys_info_refreshes;
+        let mut status = PartialDeviceStatus::default();
+        for section in sections {
+            match section {
+                StatusSection::Cpu => {
+                    if let Some(cpu_refreshes) = refreshes.cpu() {
+                        sys_info.refresh_cpu_specifics(cpu_refreshes);
+                    }
+                    let (cpu_usage, physical_cpus, cpu_info) = cpu_status(&sys_info);
+                    status.logical_cpus = Some(cpu_usage.len());
+                    status.cpu_usage = Some(cpu_usage);
+                    status.physical_cpus = physical_cpus;
+                    status.cpu_info = Some(cpu_info);
+                }
+                StatusSection::Mem => {
+                    sys_info.refresh_memory();
+                    let (mem_usage, swap_usage) = mem_status(&sys_info);
+                    status.mem_usage = Some(mem_usage);
+                    status.swap_usage = swap_usage;
+                }
+                StatusSection::Disks => {
+                    sys_info.refresh_disks_list();
+                    status.disks = Some(self.disk_status(&mut sys_info));
+                }
+                StatusSection::Uptime => {
+                    status.uptime = Some(sys_info.uptime());
+                    status.boot_time_unix = Some(sys_info.boot_time());
+                }
+                StatusSection::Load => {
+                    if let Some(process_refreshes) = refreshes.processes() {
+                        sys_info.refresh_processes_specifics(process_refreshes);
+                    }
+                    let (load_average, process_count) = load_status(&sys_info);
+                    status.load_average = Some(load_average);
+                    status.process_count = Some(process_count);
+                }
+            }
+        }
+        status
+    }
 
+    /// Disk section of the status from the refreshed *sys_info*
+    fn disk_status(&self, sys_info: &mut System) -> Vec<DiskStatus> {
+        sys_info.sort_disks_by(sort_disks_by_device_name);
         let mut disks = Vec::new();
         for disk in sys_info.disks() {
             disks.push(DiskStatus {
@@ -151,6 +562,9 @@ impl DeviceState {
                 file_system: String::from_utf8_lossy(disk.file_system()).into(),
                 total_space: disk.total_space(),
                 mount_point: String::from(disk.mount_point().to_str().unwrap_or_default()),
+                mount_points: vec![String::from(
+                    disk.mount_point().to_str().unwrap_or_default(),
+                )],
                 available_space: disk.available_space(),
                 usage: if disk.total_space() > 0 {
                     1.0 - (disk.available_space() as f32 / disk.total_space() as f32)
@@ -159,54 +573,472 @@ impl DeviceState {
                 },
             });
         }
+        if self.shared.merge_duplicate_disks {
+            disks = merge_duplicate_disks(disks);
+        }
+        disks
+    }
 
-        let uptime = sys_info.uptime();
+    /// Available bytes on the disk containing the *path*
+    ///
+    /// The disk is found from the mount points in the system disk list. The path is made absolute
+    /// when possible, so that symbolic links resolve to the right disk. Returns `None` if no disk
+    /// contains the path, for example when the disk list is not available.
+    pub fn free_space_for(&self, path: &Path) -> Option<u64> {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut sys_info = self.shared.sys_info.lock().unwrap();
+        sys_info.refresh_disks();
+        free_space_on_disks(
+            sys_info
+                .disks()
+                .iter()
+                .map(|disk| (disk.mount_point(), disk.available_space())),
+            &path,
+        )
+    }
 
-        let load_average = [
-            sys_info.load_average().one as f32,
-            sys_info.load_average().five as f32,
-            sys_info.load_average().fifteen as f32,
-        ];
+    /// Path to the SIFIS-Home directory
+    pub fn home_path(&self) -> &Path {
+        self.shared.sifis_home.home_path()
+    }
 
-        DeviceStatus {
-            cpu_usage,
-            mem_usage,
-            swap_usage,
-            disks,
-            uptime,
-            load_average,
+    /// Requesting the *top* processes in the given *order*
+    ///
+    /// Process CPU usage is measured between two refreshes, so it is zero for processes that
+    /// started after the previous status or process query.
+    pub fn top_processes(&self, top: usize, order: ProcessOrder) -> Vec<ProcessStatus> {
+        let mut sys_info = self.shared.sys_info.lock().unwrap();
+        sys_info.refresh_processes_specifics(ProcessRefreshKind::new().with_cpu());
+
+        let mut processes: Vec<ProcessStatus> = sys_info
+            .processes()
+            .iter()
+            .map(|(pid, process)| ProcessStatus {
+                pid: pid.as_u32(),
+                name: process.name().to_string(),
+                cpu: process.cpu_usage() * 0.01,
+                mem: process.memory(),
+            })
+            .collect();
+        match order {
+            ProcessOrder::Cpu => processes.sort_by(|a, b| b.cpu.total_cmp(&a.cpu)),
+            ProcessOrder::Mem => processes.sort_by_key(|process| Reverse(process.mem)),
         }
+        processes.truncate(top);
+        processes
     }
 
     /// Get a copy current config if available
     pub fn get_config(&self) -> Option<DeviceConfig> {
-        if let Ok(config) = self.device_config.read() {
+        if let Ok(config) = self.shared.device_config.read() {
             config.clone()
         } else {
             None
         }
     }
 
+    /// Get a copy of the current config without waiting for writers
+    ///
+    /// Unlike [get_config](DeviceState::get_config), this never blocks when
+    /// [set_config](DeviceState::set_config) is holding the lock while saving. The last known
+    /// configuration is returned instead, so the result can be stale until the save completes.
+    pub fn try_get_config(&self) -> Option<DeviceConfig> {
+        match self.shared.device_config.try_read() {
+            Ok(config) => config.clone(),
+            Err(_) => match self.shared.last_config.lock() {
+                Ok(last_config) => last_config.clone(),
+                Err(_) => None,
+            },
+        }
+    }
+
+    /// Capture the device information and configuration together
+    ///
+    /// Both locks are held while the values are copied, so the snapshot cannot mix values from
+    /// before and after a concurrent update. The information lock is always taken first.
+    pub fn snapshot(&self) -> DeviceSnapshot {
+        let info = self.shared.device_info.read().unwrap();
+        let config = self.shared.device_config.read().unwrap();
+        DeviceSnapshot {
+            info: info.clone(),
+            config: config.clone(),
+        }
+    }
+
     /// Set new config
     ///
-    /// Given config is written to `config.json` file.
-    /// Sending None will delete `config.json` file.
+    /// Given config is written to the configuration store, which is the `config.json` file by
+    /// default. Sending None will remove the stored configuration.
+    ///
+    /// Changes that take effect after a restart mark the device as requiring a reboot, see
+    /// [ConfigDiff::requires_reboot](mobile_api::configs::ConfigDiff::requires_reboot). Adding or
+    /// removing the whole configuration always requires a reboot. The reboot marker is written
+    /// before the configuration, so a failed save can leave the device marked as requiring a
+    /// reboot, but a saved change is never left unmarked.
     pub fn set_config(
         &self,
         config: Option<DeviceConfig>,
     ) -> Result<(), Box<dyn std::error::Error + '_>> {
-        let mut write_lock = self.device_config.write()?;
+        let write_lock = self.shared.device_config.write()?;
+        self.write_config(write_lock, config)
+    }
+
+    /// Set new config unless another write keeps it locked for longer than the *wait*
+    ///
+    /// Works like [set_config](DeviceState::set_config), but a concurrent write does not block
+    /// the async worker. The lock is tried again every few milliseconds, and
+    /// [SetConfigError::Locked] is returned if it is still held after the *wait*.
+    pub async fn try_set_config(
+        &self,
+        config: Option<DeviceConfig>,
+        wait: Duration,
+    ) -> Result<(), SetConfigError> {
+        let deadline = Instant::now() + wait;
+        loop {
+            match self.shared.device_config.try_write() {
+                Ok(write_lock) => {
+                    return self
+                        .write_config(write_lock, config)
+                        .map_err(|error| SetConfigError::Failed(error.to_string()))
+                }
+                Err(TryLockError::Poisoned(error)) => {
+                    return Err(SetConfigError::Failed(error.to_string()))
+                }
+                Err(TryLockError::WouldBlock) => {}
+            }
+            if Instant::now() >= deadline {
+                return Err(SetConfigError::Locked);
+            }
+            sleep(CONFIG_LOCK_RETRY_INTERVAL).await;
+        }
+    }
+
+    /// Save the *config* while holding the *write_lock* of the configuration
+    fn write_config(
+        &self,
+        mut write_lock: RwLockWriteGuard<'_, Option<DeviceConfig>>,
+        config: Option<DeviceConfig>,
+    ) -> Result<(), Box<dyn std::error::Error + '_>> {
+        // The marker is written first, so that a saved configuration is never left without it
+        let requires_reboot = match (write_lock.as_ref(), config.as_ref()) {
+            (Some(old_config), Some(new_config)) => old_config.diff(new_config).requires_reboot(),
+            (None, None) => false,
+            _ => true,
+        };
+        if requires_reboot {
+            self.set_reboot_required(true)?;
+        }
         match &config {
-            None => self.sifis_home.remove_config()?,
-            Some(config) => self.sifis_home.save_config(config)?,
+            None => self.shared.config_store.remove()?,
+            Some(config) => self.shared.config_store.save(config)?,
+        }
+        if let Ok(mut last_config) = self.shared.last_config.lock() {
+            last_config.clone_from(&config);
         }
+        self.shared
+            .config_sender
+            .send_replace(config.clone().map(Arc::new));
         *write_lock = config;
         Ok(())
     }
 
-    /// Access device info reference
-    pub fn device_info(&self) -> &DeviceInfo {
-        &self.device_info
+    /// Names of the stored configuration profiles
+    ///
+    /// See [SifisHome::list_profiles] for details.
+    pub fn profiles(&self) -> mobile_api::error::Result<Vec<String>> {
+        self.shared.sifis_home.list_profiles()
+    }
+
+    /// Save the *config* as the configuration profile with the *name*
+    ///
+    /// See [SifisHome::save_profile] for details.
+    pub fn save_profile(&self, name: &str, config: &DeviceConfig) -> mobile_api::error::Result<()> {
+        self.shared.sifis_home.save_profile(name, config)
+    }
+
+    /// Remove the configuration profile with the *name*
+    ///
+    /// See [SifisHome::remove_profile] for details.
+    pub fn remove_profile(&self, name: &str) -> mobile_api::error::Result<()> {
+        self.shared.sifis_home.remove_profile(name)
+    }
+
+    /// Make the configuration profile with the *name* the active configuration
+    ///
+    /// The profile is copied to the configuration store with [set_config](DeviceState::set_config),
+    /// so the reboot requirement and the subscribers are handled like for any other change. The
+    /// profile itself is kept.
+    pub fn activate_profile(&self, name: &str) -> Result<DeviceConfig, ActivateProfileError> {
+        let config = self
+            .shared
+            .sifis_home
+            .load_profile(name)
+            .map_err(ActivateProfileError::Load)?;
+        self.set_config(Some(config.clone()))
+            .map_err(|error| ActivateProfileError::Save(error.to_string()))?;
+        Ok(config)
+    }
+
+    /// Subscribe to configuration changes
+    ///
+    /// The receiver starts with the current configuration, and it is notified each time
+    /// [set_config](DeviceState::set_config) saves a new configuration or removes it. This allows
+    /// services running in the same process to react to changes without polling.
+    pub fn subscribe_config(&self) -> watch::Receiver<Option<Arc<DeviceConfig>>> {
+        self.shared.config_sender.subscribe()
+    }
+
+    /// Check if the device should be restarted for configuration changes to take effect
+    pub fn reboot_required(&self) -> bool {
+        self.shared.reboot_required.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Set or clear the reboot required flag
+    ///
+    /// The flag is persisted with the `reboot_required` marker file in the SIFIS-Home path, so
+    /// that it survives server restarts that happen before the device is rebooted.
+    pub fn set_reboot_required(&self, required: bool) -> std::io::Result<()> {
+        let marker = self.shared.sifis_home.reboot_required_file_path();
+        if required {
+            fs::write(&marker, b"")?;
+        } else if let Err(err) = fs::remove_file(&marker) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                return Err(err);
+            }
+        }
+        self.shared
+            .reboot_required
+            .store(required, AtomicOrdering::SeqCst);
+        Ok(())
+    }
+
+    /// Check if a factory reset is waiting for the device to be restarted
+    ///
+    /// The configuration is in an inconsistent state until the restart, so the configuration
+    /// endpoints refuse to work while this is set.
+    pub fn pending_reset(&self) -> bool {
+        self.shared.pending_reset.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Set or clear the pending factory reset flag
+    ///
+    /// Unlike the reboot required flag, this is not persisted, because a restarted server is
+    /// already past the reset.
+    pub fn set_pending_reset(&self, pending: bool) {
+        self.shared
+            .pending_reset
+            .store(pending, AtomicOrdering::SeqCst);
+    }
+
+    /// Access device info
+    ///
+    /// Returns None if the server is waiting for provisioning.
+    pub fn device_info(&self) -> Option<Arc<DeviceInfo>> {
+        self.shared.device_info.read().unwrap().clone()
+    }
+
+    /// Check if the device information is available
+    pub fn is_provisioned(&self) -> bool {
+        self.shared.device_info.read().unwrap().is_some()
+    }
+
+    /// Provisioning token while the server is waiting for provisioning
+    ///
+    /// The token holds the UUID and the authorization key that the device gets when it is
+    /// provisioned. It is only shown locally, so knowing it proves access to the device.
+    pub fn provisioning_token(&self) -> Option<String> {
+        match self.is_provisioned() {
+            true => None,
+            false => self
+                .shared
+                .provisioning_token
+                .map(|token| token.to_string()),
+        }
+    }
+
+    /// Create and save new device information
+    ///
+    /// This works only once, while the server is waiting for provisioning, and the *token* must
+    /// match the [provisioning_token](DeviceState::provisioning_token). The device information is
+    /// created for the *product_name* with the identity from the token and saved to the
+    /// `device.json` file.
+    pub fn provision(
+        &self,
+        product_name: String,
+        token: &str,
+    ) -> Result<Arc<DeviceInfo>, ProvisionError> {
+        let mut device_info = self.shared.device_info.write().unwrap();
+        let expected = match (device_info.as_ref(), self.shared.provisioning_token) {
+            (None, Some(expected)) => expected,
+            _ => return Err(ProvisionError::AlreadyProvisioned),
+        };
+        let token = token
+            .parse::<ProvisioningToken>()
+            .map_err(|_| ProvisionError::TokenInvalid)?;
+        verify_slices_are_equal(
+            &provisioning_token_bytes(&token),
+            &provisioning_token_bytes(&expected),
+        )
+        .map_err(|_| ProvisionError::TokenInvalid)?;
+        let mut new_info = self
+            .shared
+            .sifis_home
+            .new_info(product_name)
+            .map_err(ProvisionError::Failed)?;
+        new_info.set_uuid(token.uuid);
+        new_info.set_authorization_key(token.authorization_key);
+        self.shared
+            .sifis_home
+            .save_info(&new_info)
+            .map_err(ProvisionError::Failed)?;
+        self.shared
+            .sifis_home
+            .ensure_private_key()
+            .map_err(ProvisionError::Failed)?;
+        let new_info = Arc::new(new_info);
+        *device_info = Some(new_info.clone());
+        Ok(new_info)
+    }
+
+    /// Replace the device information
+    ///
+    /// The *new_info* is saved to the `device.json` file first, and the value in memory is
+    /// swapped only when saving succeeds. Requests authorized after this call use the new
+    /// authorization key.
+    pub fn update_device_info(&self, new_info: DeviceInfo) -> mobile_api::error::Result<()> {
+        let mut device_info = self.shared.device_info.write().unwrap();
+        self.shared.sifis_home.save_info(&new_info)?;
+        *device_info = Some(Arc::new(new_info));
+        Ok(())
+    }
+
+    /// Issue a one-time token for confirming the *command*
+    ///
+    /// The token is accepted once by [use_confirmation_token](DeviceState::use_confirmation_token)
+    /// for the same command, and only before the *lifetime* has passed. At most
+    /// [MAX_CONFIRMATION_TOKENS] are kept, so issuing more drops the oldest ones.
+    #[cfg(feature = "commands")]
+    pub fn issue_confirmation_token(
+        &self,
+        command: &str,
+        lifetime: Duration,
+    ) -> mobile_api::error::Result<String> {
+        let token = self
+            .srng()
+            .generate_code(CONFIRMATION_TOKEN_LENGTH, &CONFIRMATION_TOKEN_ALPHABET)?;
+        let now = Instant::now();
+        let mut tokens = self.shared.confirmation_tokens.lock().unwrap();
+        tokens.retain(|pending| pending.expires_at > now);
+        if tokens.len() >= MAX_CONFIRMATION_TOKENS {
+            let excess = tokens.len() + 1 - MAX_CONFIRMATION_TOKENS;
+            tokens.drain(..excess);
+        }
+        tokens.push(ConfirmationToken {
+            command: command.to_string(),
+            token: token.clone(),
+            expires_at: now + lifetime,
+        });
+        Ok(token)
+    }
+
+    /// Use a confirmation *token* issued for the *command*
+    ///
+    /// Returns true if the token was issued for the command and has not expired. The token is
+    /// removed, so that it can not be used again. The tokens are compared in constant time.
+    #[cfg(feature = "commands")]
+    pub fn use_confirmation_token(&self, command: &str, token: &str) -> bool {
+        let now = Instant::now();
+        let mut tokens = self.shared.confirmation_tokens.lock().unwrap();
+        tokens.retain(|pending| pending.expires_at > now);
+        match tokens.iter().position(|pending| {
+            pending.command == command
+                && verify_slices_are_equal(pending.token.as_bytes(), token.as_bytes()).is_ok()
+        }) {
+            Some(index) => {
+                tokens.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the DHT private key file
+    ///
+    /// See [SifisHome::remove_private_key] for details.
+    #[cfg(feature = "commands")]
+    pub fn remove_private_key(&self) -> mobile_api::error::Result<()> {
+        self.shared.sifis_home.remove_private_key()
+    }
+
+    /// Secure Random Number Generator shared by the whole server
+    ///
+    /// See [SifisHome::srng] for details.
+    pub fn srng(&self) -> &SRNG {
+        self.shared.sifis_home.srng()
+    }
+
+    /// DHT public key derived from the private key file
+    ///
+    /// See [SifisHome::load_public_key] for details.
+    pub fn public_key(&self) -> mobile_api::error::Result<PublicKey> {
+        self.shared.sifis_home.load_public_key()
+    }
+
+    /// How many times the device has booted
+    ///
+    /// See [SifisHome::increment_boot_count] for details.
+    pub fn boot_count(&self) -> mobile_api::error::Result<u64> {
+        self.shared.sifis_home.load_boot_count()
+    }
+
+    /// Set the boot count back to zero
+    pub fn reset_boot_count(&self) -> mobile_api::error::Result<()> {
+        self.shared.sifis_home.reset_boot_count()
+    }
+
+    /// Path to the command scripts directory
+    ///
+    /// The `MOBILE_API_SCRIPTS_PATH` environment variable is used when set. Otherwise, the
+    /// `scripts` directory is searched with [resource_path](DeviceState::resource_path).
+    pub fn scripts_path(&self) -> Result<PathBuf, std::io::Error> {
+        match env::var("MOBILE_API_SCRIPTS_PATH") {
+            Ok(path) => Ok(PathBuf::from(path)),
+            Err(_) => self.resource_path("scripts"),
+        }
+    }
+
+    /// Check if the *script_name* script is found from the [scripts_path](DeviceState::scripts_path)
+    pub fn has_script(&self, script_name: &str) -> bool {
+        self.scripts_path()
+            .is_ok_and(|path| path.join(script_name).is_file())
+    }
+
+    /// Names of the subsystems that are not ready
+    ///
+    /// The device is ready when it is provisioned and configured, the private key file exists, and
+    /// the command scripts directory is found. The configuration is read with
+    /// [try_get_config](DeviceState::try_get_config), so this does not wait for a save to finish.
+    pub fn missing_subsystems(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        match self.device_info() {
+            Some(device_info) => {
+                let private_key_file = self.shared.sifis_home.private_key_file_for(&device_info);
+                if !private_key_file.is_ok_and(|path| path.is_file()) {
+                    missing.push("private_key");
+                }
+            }
+            None => {
+                missing.push("device_info");
+                missing.push("private_key");
+            }
+        }
+        if self.try_get_config().is_none() {
+            missing.push("configuration");
+        }
+        if !self.scripts_path().is_ok_and(|path| path.is_dir()) {
+            missing.push("scripts");
+        }
+        missing
     }
 
     /// Try to find requested resource path
@@ -220,7 +1052,7 @@ impl DeviceState {
     ///
     pub fn resource_path(&self, path: &str) -> Result<PathBuf, std::io::Error> {
         // Try to find from SIFIS Home path
-        let mut target_path = PathBuf::from(self.sifis_home.home_path());
+        let mut target_path = PathBuf::from(self.shared.sifis_home.home_path());
         target_path.push(path);
         if target_path.exists() {
             return Ok(target_path);
@@ -262,6 +1094,9 @@ impl DeviceState {
 ///
 /// The guardian automatically clears the busy message when the object goes out of scope.
 ///
+/// The guardian holds its own handle to the shared state, so it can be moved into a response
+/// stream that outlives the request.
+///
 /// # Example
 ///
 /// ```rust
@@ -275,30 +1110,29 @@ impl DeviceState {
 ///     Err(reason) => CommandResponse::Busy(reason),
 /// }
 /// ```
-pub struct BusyGuard<'a> {
-    /// Reference to state object
-    state: &'a DeviceState,
+pub struct BusyGuard {
+    /// Handle to the state object
+    state: DeviceState,
 }
 
-impl BusyGuard<'_> {
+impl BusyGuard {
     /// Tries to make system busy
     ///
     /// If the server is free, then it is marked busy with the *reason* and guardian object is
     /// returned in Ok.
     ///
     /// If the serve is busy, then the reason is returned in the Err.
-    pub fn try_busy<'a>(
-        state: &'a DeviceState,
-        reason: &'static str,
-    ) -> Result<BusyGuard<'a>, &'static str> {
+    pub fn try_busy(state: &DeviceState, reason: &'static str) -> Result<BusyGuard, &'static str> {
         match state.set_busy(reason) {
-            Ok(_) => Ok(BusyGuard { state }),
+            Ok(_) => Ok(BusyGuard {
+                state: state.clone(),
+            }),
             Err(reason) => Err(reason),
         }
     }
 }
 
-impl Drop for BusyGuard<'_> {
+impl Drop for BusyGuard {
     /// Clearing busy message when guardian goes out of scope
     fn drop(&mut self) {
         self.state.clear_busy();
@@ -308,7 +1142,15 @@ impl Drop for BusyGuard<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::api_v1::tests_common::create_test_state;
+    use crate::api_common::ErrorResponse;
+    use crate::api_v1::tests_common::{
+        api_key_header_for, create_test_config, create_test_sifis_home, create_test_state,
+    };
+    use crate::build_rocket;
+    use mobile_api::security::{is_clock_likely_unset, SecurityKey};
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use tokio::runtime::Builder;
 
     // Test ignored for Miri because the server has time and io-related
     // functions that are not available in isolation mode
@@ -335,4 +1177,424 @@ mod tests {
         // Busy guard went out of scope, "server" should be free now.
         assert_eq!(state.busy(), "");
     }
+
+    /// Store keeping the configuration in memory
+    ///
+    /// The stored value is shared so that the test can inspect it after the store is moved to
+    /// the state object.
+    #[derive(Clone, Default)]
+    struct MemoryConfigStore(Arc<Mutex<Option<DeviceConfig>>>);
+
+    impl ConfigStore for MemoryConfigStore {
+        fn load(&self) -> mobile_api::error::Result<DeviceConfig> {
+            match self.0.lock().unwrap().as_ref() {
+                Some(config) => Ok(config.clone()),
+                None => Err(mobile_api::error::Error::not_configured()),
+            }
+        }
+
+        fn save(&self, config: &DeviceConfig) -> mobile_api::error::Result<()> {
+            *self.0.lock().unwrap() = Some(config.clone());
+            Ok(())
+        }
+
+        fn remove(&self) -> mobile_api::error::Result<()> {
+            *self.0.lock().unwrap() = None;
+            Ok(())
+        }
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[cfg(feature = "commands")]
+    #[test]
+    fn test_confirmation_tokens() {
+        let (_test_dir, state) = create_test_state();
+        let lifetime = Duration::from_secs(60);
+
+        // Tokens work once and only for their own command
+        let token = state.issue_confirmation_token("restart", lifetime).unwrap();
+        assert!(!state.use_confirmation_token("shutdown", &token));
+        assert!(!state.use_confirmation_token("restart", &token[1..]));
+        assert!(state.use_confirmation_token("restart", &token));
+        assert!(!state.use_confirmation_token("restart", &token));
+
+        // The oldest tokens are dropped when there are too many
+        let tokens: Vec<String> = (0..MAX_CONFIRMATION_TOKENS + 2)
+            .map(|_| state.issue_confirmation_token("restart", lifetime).unwrap())
+            .collect();
+        assert_eq!(
+            state.shared.confirmation_tokens.lock().unwrap().len(),
+            MAX_CONFIRMATION_TOKENS
+        );
+        assert!(!state.use_confirmation_token("restart", &tokens[0]));
+        assert!(!state.use_confirmation_token("restart", &tokens[1]));
+        assert!(tokens[2..]
+            .iter()
+            .all(|token| state.use_confirmation_token("restart", token)));
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_custom_config_store() {
+        let (_test_dir, sifis_home) = create_test_sifis_home();
+        let config_file = sifis_home.config_file_path();
+
+        // Configuration in the store should be loaded at start
+        let store = MemoryConfigStore::default();
+        store.save(&create_test_config()).unwrap();
+        let state = DeviceState::with_store(sifis_home, Box::new(store.clone())).unwrap();
+        assert_eq!(state.get_config(), Some(create_test_config()));
+
+        // Changes should go to the store instead of config.json
+        let new_config =
+            DeviceConfig::new(SecurityKey::new().unwrap(), "New name".parse().unwrap());
+        state.set_config(Some(new_config.clone())).unwrap();
+        assert_eq!(store.load().unwrap(), new_config);
+        assert!(!config_file.exists());
+
+        state.set_config(None).unwrap();
+        assert!(store.load().is_err());
+        assert_eq!(state.get_config(), None);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_invalid_stored_config() {
+        let (_test_dir, sifis_home) = create_test_sifis_home();
+        let config_file = sifis_home.config_file_path();
+
+        // A name that the current rules reject must not leave the device unconfigured
+        let mut json = serde_json::to_value(create_test_config()).unwrap();
+        json["name"] = "Line\nbreak".into();
+        fs::write(&config_file, json.to_string()).unwrap();
+        let error = DeviceState::new(sifis_home).err().unwrap();
+        assert!(error.starts_with("Could not load device configuration"));
+        assert!(error.ends_with("\nFix or remove the configuration to start the server."));
+        assert!(config_file.exists());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_subscribe_config() {
+        let (_test_dir, state) = create_test_state();
+        let mut receiver = state.subscribe_config();
+        assert!(receiver.borrow().is_none());
+
+        // Waiting subscriber sees the new configuration
+        let config = create_test_config();
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        let mut waiting_receiver = state.subscribe_config();
+        let waiter = runtime.spawn(async move {
+            waiting_receiver.changed().await.unwrap();
+            let config = waiting_receiver.borrow().clone();
+            config
+        });
+        state.set_config(Some(config.clone())).unwrap();
+        let observed = runtime.block_on(waiter).unwrap();
+        assert_eq!(observed.as_deref(), Some(&config));
+
+        // Other subscribers see the change too, including the removal
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(receiver.borrow_and_update().as_deref(), Some(&config));
+        assert!(!receiver.has_changed().unwrap());
+        state.set_config(None).unwrap();
+        assert!(receiver.has_changed().unwrap());
+        assert!(receiver.borrow_and_update().is_none());
+
+        // New subscribers start with the current configuration
+        state.set_config(Some(config.clone())).unwrap();
+        assert_eq!(state.subscribe_config().borrow().as_deref(), Some(&config));
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_snapshot() {
+        let (_test_dir, state) = create_test_state();
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.info, state.device_info());
+        assert!(snapshot.config.is_none());
+
+        // Name and key are changed together by the writer
+        let config_a = create_test_config();
+        let config_b = DeviceConfig::new(SecurityKey::new().unwrap(), "Other".parse().unwrap());
+        let writer_state = state.clone();
+        let (writer_a, writer_b) = (config_a.clone(), config_b.clone());
+        let writer = std::thread::spawn(move || {
+            for round in 0..200 {
+                let config = if round % 2 == 0 { &writer_a } else { &writer_b };
+                writer_state.set_config(Some(config.clone())).unwrap();
+            }
+        });
+        while !writer.is_finished() {
+            let snapshot = state.snapshot();
+            assert!(snapshot.info.is_some());
+            if let Some(config) = snapshot.config {
+                assert!(config == config_a || config == config_b);
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(state.snapshot().config, Some(config_b));
+    }
+
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_try_get_config() {
+        let (_test_dir, state) = create_test_state();
+        assert_eq!(state.try_get_config(), None);
+        state.set_config(Some(create_test_config())).unwrap();
+        assert_eq!(state.try_get_config(), Some(create_test_config()));
+
+        // Held write lock should not block, the last known config is returned instead
+        let write_lock = state.shared.device_config.write().unwrap();
+        assert_eq!(state.try_get_config(), Some(create_test_config()));
+        drop(write_lock);
+
+        state.set_config(None).unwrap();
+        let _write_lock = state.shared.device_config.write().unwrap();
+        assert_eq!(state.try_get_config(), None);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_try_set_config() {
+        let (_test_dir, state) = create_test_state();
+        let runtime = Builder::new_current_thread().enable_time().build().unwrap();
+        let wait = Duration::from_millis(50);
+        runtime
+            .block_on(state.try_set_config(Some(create_test_config()), wait))
+            .unwrap();
+        assert_eq!(state.get_config(), Some(create_test_config()));
+
+        // Held write lock is answered after the wait instead of blocking
+        let write_lock = state.shared.device_config.write().unwrap();
+        let started = Instant::now();
+        let result = runtime.block_on(state.try_set_config(None, wait));
+        assert!(matches!(result, Err(SetConfigError::Locked)));
+        assert!(started.elapsed() >= wait);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(write_lock);
+        assert_eq!(state.get_config(), Some(create_test_config()));
+
+        // The configuration endpoint answers with 503 while the lock is held
+        let client = Client::tracked(build_rocket(state.clone())).unwrap();
+        let write_lock = state.shared.device_config.write().unwrap();
+        let started = Instant::now();
+        let response = client
+            .put("/v1/device/configuration")
+            .header(api_key_header_for(&state))
+            .json(&create_test_config())
+            .dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(response.headers().get_one("Retry-After").is_some());
+        let error_response = response.into_json::<ErrorResponse>().unwrap();
+        assert_eq!(
+            error_response.error.description,
+            "The device configuration is being saved by another request."
+        );
+        drop(write_lock);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_partial_device_status() {
+        let (_test_dir, state) = create_test_state();
+
+        // Only the selected sections are collected
+        let status = state.partial_device_status(&[StatusSection::Load]);
+        assert!(status.process_count.unwrap() > 0);
+        assert!(status.load_average.is_some());
+        assert!(status.cpu_usage.is_none());
+        assert!(status.cpu_info.is_none());
+        assert!(status.mem_usage.is_none());
+        assert!(status.disks.is_none());
+        assert!(status.uptime.is_none());
+
+        let status = state.partial_device_status(&[StatusSection::Cpu, StatusSection::Uptime]);
+        let cpu_usage = status.cpu_usage.unwrap();
+        assert_eq!(status.logical_cpus, Some(cpu_usage.len()));
+        assert_eq!(status.cpu_info.unwrap().frequency.len(), cpu_usage.len());
+        assert!(status.boot_time_unix.unwrap() > 0);
+        assert!(status.mem_usage.is_none());
+        assert!(status.process_count.is_none());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_device_status_cpu_info() {
+        let (_test_dir, state) = create_test_state();
+        let cpu_refreshes = state.shared.sys_info_refreshes.cpu().unwrap();
+        assert!(cpu_refreshes.cpu_usage());
+        assert!(cpu_refreshes.frequency());
+
+        // There is a frequency for each core
+        let status = state.device_status();
+        assert_eq!(status.cpu_info.frequency.len(), status.cpu_usage.len());
+        let json = serde_json::to_value(&status).unwrap();
+        assert!(json["cpuInfo"]["brand"].is_string());
+        assert!(json["cpuInfo"]["frequency"].is_array());
+        assert!(json["cpuUsage"].is_array());
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_device_status_cpu_counts() {
+        let (_test_dir, state) = create_test_state();
+        let status = state.device_status();
+        assert_eq!(status.cpu_usage.len(), status.logical_cpus);
+        assert!(status.logical_cpus > 0);
+        if let Some(physical_cpus) = status.physical_cpus {
+            assert!(physical_cpus > 0);
+            assert!(physical_cpus <= status.logical_cpus);
+        }
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_device_status_boot_time() {
+        let (_test_dir, state) = create_test_state();
+        let status = state.device_status();
+        let now = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        // Boot time and uptime are rounded to seconds and read at slightly different times
+        let estimated_now = status.boot_time_unix + status.uptime;
+        assert!(estimated_now.abs_diff(now) <= 5, "{estimated_now} != {now}");
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_device_status_collected_at() {
+        let (_test_dir, state) = create_test_state();
+        let before = get_unix_time_ms().unwrap();
+        let status = state.device_status();
+        let after = get_unix_time_ms().unwrap();
+        assert!(status.collected_at_unix_ms >= before);
+        assert!(status.collected_at_unix_ms <= after);
+        assert!(!is_clock_likely_unset(status.collected_at_unix_ms));
+
+        // Each status has its own timestamp
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(state.device_status().collected_at_unix_ms > status.collected_at_unix_ms);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_shared_srng() {
+        let (_test_dir, state) = create_test_state();
+        assert!(std::ptr::eq(state.srng(), state.clone().srng()));
+
+        // Handlers in other threads use the same generator
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let state = state.clone();
+                std::thread::spawn(move || state.srng().generate_key().unwrap())
+            })
+            .collect();
+        let mut keys: Vec<SecurityKey> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        keys.push(state.srng().generate_key().unwrap());
+        for (index, key) in keys.iter().enumerate() {
+            assert!(!key.is_null());
+            assert!(keys[index + 1..].iter().all(|other| other != key));
+        }
+    }
+
+    #[test]
+    fn test_free_space_on_disks() {
+        let disks = [
+            (Path::new("/"), 1000),
+            (Path::new("/boot"), 200),
+            (Path::new("/home"), 3000),
+            (Path::new("/home/user/data"), 4000),
+        ];
+        let free_space = |path: &str| free_space_on_disks(disks, Path::new(path));
+        assert_eq!(free_space("/etc/sifis-home"), Some(1000));
+        assert_eq!(free_space("/boot/config.txt"), Some(200));
+        assert_eq!(free_space("/home/user"), Some(3000));
+        assert_eq!(free_space("/home/user/data"), Some(4000));
+        assert_eq!(free_space("/home/user/data/sifis-home"), Some(4000));
+
+        // Only whole path components match
+        assert_eq!(free_space("/bootstrap"), Some(1000));
+        assert_eq!(free_space("/home/user/database"), Some(3000));
+
+        // No disk contains a relative path, and no disks contain anything
+        assert_eq!(free_space("relative/path"), None);
+        assert_eq!(free_space_on_disks(Vec::new(), Path::new("/")), None);
+    }
+
+    // Test ignored for Miri because the server has time and io-related
+    // functions that are not available in isolation mode
+    #[cfg_attr(miri, ignore)]
+    #[test]
+    fn test_reboot_required() {
+        let (_test_dir, sifis_home) = create_test_sifis_home();
+        let marker = sifis_home.reboot_required_file_path();
+        let state = DeviceState::new(sifis_home).unwrap();
+        assert!(!state.reboot_required());
+
+        // Changing configuration requires a reboot
+        state.set_config(Some(create_test_config())).unwrap();
+        assert!(state.reboot_required());
+        assert!(marker.exists());
+
+        // Changing only the name does not require a reboot
+        state.set_reboot_required(false).unwrap();
+        let mut config = create_test_config();
+        config.set_name("New name".parse().unwrap());
+        state.set_config(Some(config.clone())).unwrap();
+        assert!(!state.reboot_required());
+        state.set_config(Some(config.clone())).unwrap();
+        assert!(!state.reboot_required());
+
+        // Changing the key does
+        config.set_dht_shared_key(SecurityKey::new().unwrap());
+        state.set_config(Some(config)).unwrap();
+        assert!(state.reboot_required());
+
+        // Server restart before the reboot should keep the flag
+        let sifis_home = SifisHome::new_with_path(marker.parent().unwrap().to_path_buf());
+        let state = DeviceState::new(sifis_home).unwrap();
+        assert!(state.reboot_required());
+
+        // Clearing removes the marker
+        state.set_reboot_required(false).unwrap();
+        assert!(!state.reboot_required());
+        assert!(!marker.exists());
+        state.set_reboot_required(false).unwrap(); // Clearing twice is okay
+
+        // Marker from before the boot time is stale
+        fs::write(&marker, b"").unwrap();
+        assert!(is_reboot_marker_current(&marker, 0));
+        assert!(!is_reboot_marker_current(&marker, u64::MAX));
+        assert!(!is_reboot_marker_current(
+            &marker.with_extension("missing"),
+            0
+        ));
+    }
 }
@@ -8,70 +8,970 @@
 //! This file is missing when the Smart Device is first started, or the user has done a factory
 //! reset.
 
-use crate::error::Result;
-use crate::security::SecurityKey;
+use crate::error::{Error, Result};
+use crate::security::{SecurityKey, SRNG};
+use ring::digest;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{Metadata, Schema, StringValidation};
 use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::env;
+use std::fmt::{Display, Formatter};
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
-use uuid::Uuid;
+use std::str::FromStr;
+use uuid::{Uuid, Variant};
+
+/// How many times an interrupted file operation is tried before giving up
+const INTERRUPTED_MAX_ATTEMPTS: usize = 10;
+
+/// Run the file *operation* again if it was interrupted
+///
+/// Some embedded filesystems return `EINTR` from reads and writes, which is a transient error.
+/// Other errors are returned immediately.
+fn retry_on_interrupt<T, F>(mut operation: F) -> io::Result<T>
+where
+    F: FnMut() -> io::Result<T>,
+{
+    let mut attempts = 1;
+    loop {
+        match operation() {
+            Err(err)
+                if err.kind() == io::ErrorKind::Interrupted
+                    && attempts < INTERRUPTED_MAX_ATTEMPTS =>
+            {
+                attempts += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Maximum length of the device name in bytes
+///
+/// The DHT layer truncates device names longer than this, so peers would see a different name than
+/// the mobile application. The limit is in bytes, not characters, which means that names containing
+/// multibyte UTF-8 characters reach it with fewer characters.
+pub const DEVICE_NAME_MAX_BYTES: usize = 64;
+
+/// Maximum length of the device description in characters
+pub const DEVICE_DESCRIPTION_MAX_CHARS: usize = 256;
+
+/// Length of the DHT node identifier in bytes
+pub const DHT_NODE_ID_BYTES: usize = 16;
+
+/// Prefix hashed before the shared key when computing the DHT node identifier
+///
+/// The prefix keeps the identifier different from other digests of the same key, such as the
+/// [fingerprint](SecurityKey::fingerprint).
+const DHT_NODE_ID_CONTEXT: &[u8] = b"sifis-home dht node id\0";
+
+/// Check that the device name is acceptable
+///
+/// The name must not be empty, its UTF-8 encoding can be at most [DEVICE_NAME_MAX_BYTES] long, and
+/// it must not contain control characters such as newlines or tabs.
+pub fn validate_device_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(Error::device_name_invalid("device name is empty"));
+    }
+    if name.len() > DEVICE_NAME_MAX_BYTES {
+        return Err(Error::device_name_invalid("device name is too long"));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(Error::device_name_invalid(
+            "device name contains control characters",
+        ));
+    }
+    Ok(())
+}
+
+/// Check that the device description is acceptable
+///
+/// The description is free text, like the location or purpose of the device, but it can be at
+/// most [DEVICE_DESCRIPTION_MAX_CHARS] characters long.
+pub fn validate_description(description: &str) -> Result<()> {
+    if description.chars().count() > DEVICE_DESCRIPTION_MAX_CHARS {
+        return Err(Error::config_value_invalid("description is too long"));
+    }
+    Ok(())
+}
+
+/// Time zone names accepted by [validate_timezone], one per line
+///
+/// The list has `UTC` and the zone and link names of the IANA time zone database (2025b) in the
+/// `Africa`, `America`, `Antarctica`, `Arctic`, `Asia`, `Atlantic`, `Australia`, `Etc`, `Europe`,
+/// `Indian`, and `Pacific` areas. The names are sorted.
+pub const TIMEZONES: &str = include_str!("timezones.txt");
+
+/// Check that the time zone is a known IANA time zone name
+///
+/// Accepted are the names in [TIMEZONES], like `UTC`, `Europe/Rome`, or
+/// `America/Argentina/Buenos_Aires`. The names are case-sensitive.
+pub fn validate_timezone(timezone: &str) -> Result<()> {
+    if TIMEZONES.lines().any(|name| name == timezone) {
+        Ok(())
+    } else {
+        Err(Error::config_value_invalid("unknown time zone"))
+    }
+}
+
+/// Check that the locale looks like a POSIX locale name
+///
+/// The locale has a language code and optional territory, codeset, and modifier, for example
+/// `fi`, `en_US`, or `en_US.UTF-8`. Only ASCII letters, digits, `_`, `-`, `.`, and `@` are allowed.
+pub fn validate_locale(locale: &str) -> Result<()> {
+    if !locale.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(Error::config_value_invalid(
+            "locale does not start with a language code",
+        ));
+    }
+    if !locale
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '@'))
+    {
+        return Err(Error::config_value_invalid(
+            "locale contains invalid characters",
+        ));
+    }
+    Ok(())
+}
+
+/// Validated device name
+///
+/// Leading and trailing whitespace is trimmed when the name is created, and the result must pass
+/// the [validate_device_name] check. Serialized as a plain string.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DeviceName(String);
+
+impl DeviceName {
+    /// Borrow the name as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for DeviceName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for DeviceName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<DeviceName> for String {
+    fn from(name: DeviceName) -> Self {
+        name.0
+    }
+}
+
+impl std::str::FromStr for DeviceName {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        DeviceName::try_from(name.to_string())
+    }
+}
+
+impl JsonSchema for DeviceName {
+    fn schema_name() -> String {
+        String::from("DeviceName")
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = String::json_schema(gen).into_object();
+        let metadata = Metadata {
+            description: Some(format!(
+                "Device name without control characters, at most {} bytes as UTF-8",
+                DEVICE_NAME_MAX_BYTES
+            )),
+            examples: vec!["Living Room Lamp".to_string().into()],
+            ..Default::default()
+        };
+        schema.metadata = Some(Box::new(metadata));
+        // The maxLength keyword counts characters, so the byte limit is only in the description
+        let string = StringValidation {
+            max_length: None,
+            min_length: Some(1),
+            pattern: None,
+        };
+        schema.string = Some(Box::new(string));
+        schema.into()
+    }
+}
+
+impl TryFrom<String> for DeviceName {
+    type Error = Error;
+
+    fn try_from(name: String) -> Result<Self> {
+        let name = name.trim();
+        validate_device_name(name)?;
+        Ok(DeviceName(name.to_string()))
+    }
+}
+
+/// Check that the UUID is suitable for identifying the device
+///
+/// Device identifiers are generated with [SRNG::generate_uuid](crate::security::SRNG::generate_uuid),
+/// which makes version 7 UUIDs with the RFC 4122 variant bits. Any other UUID can not belong to a
+/// Smart Device.
+pub fn is_valid_device_uuid(uuid: &Uuid) -> bool {
+    uuid.get_version_num() == 7 && uuid.get_variant() == Variant::RFC4122
+}
+
+/// File formats for the device information and configuration
+///
+/// JSON is the format of the `device.json` and `config.json` files. MessagePack and CBOR are
+/// compact binary alternatives, for example, for transferring the files, and TOML is easier to
+/// edit by hand. TOML has no null value, so vendor settings containing nulls cannot be saved in
+/// it. Use [ConfigFormat::ALL] to list
+/// the formats, and [from_extension](ConfigFormat::from_extension) or [FromStr] to pick one at
+/// runtime.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigFormat {
+    /// JSON text, pretty printed when saved
+    Json,
+    /// MessagePack binary with named fields
+    MessagePack,
+    /// CBOR binary with named fields
+    Cbor,
+    /// TOML text
+    Toml,
+}
+
+impl ConfigFormat {
+    /// All supported formats
+    pub const ALL: [ConfigFormat; 4] = [
+        ConfigFormat::Json,
+        ConfigFormat::MessagePack,
+        ConfigFormat::Cbor,
+        ConfigFormat::Toml,
+    ];
+
+    /// Short description of the format
+    pub fn description(&self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "JSON text",
+            ConfigFormat::MessagePack => "MessagePack binary",
+            ConfigFormat::Cbor => "CBOR binary",
+            ConfigFormat::Toml => "TOML text",
+        }
+    }
+
+    /// File extensions of the format, the first one is preferred
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            ConfigFormat::Json => &["json"],
+            ConfigFormat::MessagePack => &["msgpack", "mpk"],
+            ConfigFormat::Cbor => &["cbor"],
+            ConfigFormat::Toml => &["toml"],
+        }
+    }
+
+    /// Infer the format from the extension of the *path*
+    ///
+    /// The extension is matched case-insensitively against [extensions](ConfigFormat::extensions).
+    pub fn from_extension(path: &Path) -> Result<ConfigFormat> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        ConfigFormat::ALL
+            .into_iter()
+            .find(|format| {
+                format
+                    .extensions()
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(extension))
+            })
+            .ok_or_else(|| Error::format_unsupported(format!("{:?}", path)))
+    }
+
+    /// Name of the format, which [FromStr] accepts
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "json",
+            ConfigFormat::MessagePack => "msgpack",
+            ConfigFormat::Cbor => "cbor",
+            ConfigFormat::Toml => "toml",
+        }
+    }
+
+    /// Read a value in this format from the *file*
+    fn load<T: DeserializeOwned>(&self, file: &Path) -> Result<T> {
+        let bytes = retry_on_interrupt(|| fs::read(file))?;
+        Ok(match self {
+            ConfigFormat::Json => serde_json::from_slice(&bytes)?,
+            ConfigFormat::MessagePack => rmp_serde::from_slice(&bytes)?,
+            ConfigFormat::Cbor => ciborium::de::from_reader(bytes.as_slice())?,
+            ConfigFormat::Toml => toml::from_str(
+                std::str::from_utf8(&bytes)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            )?,
+        })
+    }
+
+    /// Write the *value* in this format to the *file*
+    fn save<T: Serialize>(&self, value: &T, file: &Path) -> Result<()> {
+        let bytes = match self {
+            ConfigFormat::Json => serde_json::to_vec_pretty(value)?,
+            ConfigFormat::MessagePack => rmp_serde::to_vec_named(value)?,
+            ConfigFormat::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(value, &mut bytes)?;
+                bytes
+            }
+            ConfigFormat::Toml => toml::to_string_pretty(value)?.into_bytes(),
+        };
+        retry_on_interrupt(|| fs::write(file, &bytes))?;
+        Ok(())
+    }
+}
+
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = Error;
+
+    /// Parse the format from its [name](ConfigFormat::name) or any of its extensions
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        ConfigFormat::ALL
+            .into_iter()
+            .find(|format| {
+                format.name().eq_ignore_ascii_case(s)
+                    || format
+                        .extensions()
+                        .iter()
+                        .any(|extension| extension.eq_ignore_ascii_case(s))
+            })
+            .ok_or_else(|| Error::format_unsupported(s.to_string()))
+    }
+}
 
 /// Smart Device Configuration
+///
+/// Fields are written in camelCase to match the mobile application. Files written with the older
+/// snake_case names are still read.
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct DeviceConfig {
     /// User-defined name for the Smart Device
-    name: String,
+    name: DeviceName,
     /// Shared key for DHT communication, 32 bytes in hex format
+    #[serde(alias = "dht_shared_key")]
     dht_shared_key: SecurityKey,
+    /// IANA time zone name for displaying local time, like `Europe/Rome`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    /// Locale for the user interface, like `en_US.UTF-8`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locale: Option<String>,
+    /// Free-text description, like the location or purpose of the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    /// Vendor-specific settings, unknown keys are kept as they are
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+/// Fields that differ between two [DeviceConfig] values
+///
+/// Created with [DeviceConfig::diff]. Each flag is true when the field changed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ConfigDiff {
+    /// Device name changed
+    pub name: bool,
+    /// Shared DHT key changed
+    pub dht_shared_key: bool,
+    /// Time zone changed
+    pub timezone: bool,
+    /// Locale changed
+    pub locale: bool,
+    /// Description changed
+    pub description: bool,
+    /// Any of the vendor-specific settings changed
+    pub extra: bool,
+}
+
+impl ConfigDiff {
+    /// Check if nothing changed
+    pub fn is_empty(&self) -> bool {
+        *self == ConfigDiff::default()
+    }
+
+    /// Check if the changes take effect only after a reboot
+    ///
+    /// The DHT connection is opened with the shared key and the user interface with the locale
+    /// when the device starts, and vendor settings are unknown, so changing them requires a
+    /// reboot. The name and description are read when needed, and the time zone is applied right
+    /// away.
+    pub fn requires_reboot(&self) -> bool {
+        self.dht_shared_key || self.locale || self.extra
+    }
 }
 
+/// Environment variable overriding the device name, see [DeviceConfig::from_env]
+pub const DEVICE_NAME_ENV: &str = "MOBILE_API_DEVICE_NAME";
+
+/// Environment variable overriding the DHT shared key, see [DeviceConfig::from_env]
+pub const DHT_KEY_ENV: &str = "MOBILE_API_DHT_KEY";
+
+/// Field names of [DeviceConfig], which cannot be used for the vendor settings
+const DEVICE_CONFIG_FIELDS: [&str; 6] = [
+    "name",
+    "dhtSharedKey",
+    "dht_shared_key",
+    "timezone",
+    "locale",
+    "description",
+];
+
 impl DeviceConfig {
     /// Create a new configuration
-    pub fn new(dht_shared_key: SecurityKey, name: String) -> DeviceConfig {
+    pub fn new(dht_shared_key: SecurityKey, name: DeviceName) -> DeviceConfig {
         DeviceConfig {
             dht_shared_key,
             name,
+            timezone: None,
+            locale: None,
+            description: None,
+            extra: Map::new(),
         }
     }
 
+    /// Create a new configuration with a freshly generated DHT shared key
+    ///
+    /// Every call generates a new random key, so two configurations made with this never share
+    /// a key. Returns an error if the *name* is not a valid [DeviceName] or the random number
+    /// generator fails.
+    pub fn generate(name: String) -> Result<DeviceConfig> {
+        let name = DeviceName::try_from(name)?;
+        Ok(DeviceConfig::new(SRNG::new().generate_key()?, name))
+    }
+
+    /// Apply the overrides from the environment over the *loaded* configuration
+    ///
+    /// The `MOBILE_API_DEVICE_NAME` variable replaces the device name, and `MOBILE_API_DHT_KEY`
+    /// replaces the DHT shared key given as a hex or base64 string. The environment takes
+    /// precedence over the loaded file, and the other settings are kept. Unset and empty variables
+    /// are ignored.
+    ///
+    /// Without a loaded configuration, a new one is created only if both variables are set, and
+    /// None is returned otherwise. Returns an error if a variable has an invalid name or key.
+    pub fn from_env(loaded: Option<&DeviceConfig>) -> Result<Option<DeviceConfig>> {
+        DeviceConfig::from_env_with(loaded, |name| env::var(name).ok())
+    }
+
+    /// Apply the overrides using the *var* function for the variable values
+    fn from_env_with<F>(loaded: Option<&DeviceConfig>, var: F) -> Result<Option<DeviceConfig>>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let value = |name| var(name).filter(|value| !value.trim().is_empty());
+        let name = value(DEVICE_NAME_ENV)
+            .map(DeviceName::try_from)
+            .transpose()?;
+        let dht_shared_key = value(DHT_KEY_ENV)
+            .map(|key| SecurityKey::from_string(key.trim()))
+            .transpose()?;
+        let mut config = match loaded {
+            Some(loaded) => loaded.clone(),
+            None => {
+                return Ok(match (name, dht_shared_key) {
+                    (Some(name), Some(key)) => Some(DeviceConfig::new(key, name)),
+                    _ => None,
+                })
+            }
+        };
+        if let Some(name) = name {
+            config.set_name(name);
+        }
+        if let Some(key) = dht_shared_key {
+            config.set_dht_shared_key(key);
+        }
+        Ok(Some(config))
+    }
+
+    /// Borrow description if set
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
     /// Borrow shared DHT key
     pub fn dht_shared_key(&self) -> &SecurityKey {
         &self.dht_shared_key
     }
 
+    /// Compare with the *other* configuration
+    ///
+    /// The returned [ConfigDiff] tells which fields are different in the *other*.
+    pub fn diff(&self, other: &DeviceConfig) -> ConfigDiff {
+        ConfigDiff {
+            name: self.name != other.name,
+            dht_shared_key: self.dht_shared_key != other.dht_shared_key,
+            timezone: self.timezone != other.timezone,
+            locale: self.locale != other.locale,
+            description: self.description != other.description,
+            extra: self.extra != other.extra,
+        }
+    }
+
+    /// Compare with the *other* configuration, ignoring the shared DHT key
+    ///
+    /// Returns true if all the other fields are equal, for example when only the key was rotated.
+    pub fn eq_ignoring_keys(&self, other: &DeviceConfig) -> bool {
+        let ConfigDiff {
+            name,
+            dht_shared_key: _,
+            timezone,
+            locale,
+            description,
+            extra,
+        } = self.diff(other);
+        !(name || timezone || locale || description || extra)
+    }
+
+    /// Deterministic DHT node identifier derived from the shared key
+    ///
+    /// The identifier is the first [DHT_NODE_ID_BYTES] bytes of the SHA-256 digest of the ASCII
+    /// string `sifis-home dht node id`, a zero byte, and the 32 bytes of the shared DHT key. All
+    /// components must use this function so that they compute the same address.
+    pub fn dht_node_id(&self) -> [u8; DHT_NODE_ID_BYTES] {
+        let mut context = digest::Context::new(&digest::SHA256);
+        context.update(DHT_NODE_ID_CONTEXT);
+        context.update(self.dht_shared_key.as_bytes());
+        let digest = context.finish();
+        let mut node_id = [0u8; DHT_NODE_ID_BYTES];
+        node_id.copy_from_slice(&digest.as_ref()[..DHT_NODE_ID_BYTES]);
+        node_id
+    }
+
+    /// Borrow vendor-specific settings
+    ///
+    /// These are the keys in the configuration file that this crate does not know about.
+    pub fn extra(&self) -> &Map<String, Value> {
+        &self.extra
+    }
+
+    /// Get a vendor-specific setting as type *T*
+    ///
+    /// Returns `None` if the *key* is not set, and an error if the value does not parse as *T*.
+    pub fn extra_value<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.extra.get(key) {
+            Some(value) => Ok(Some(T::deserialize(value)?)),
+            None => Ok(None),
+        }
+    }
+
     /// Load from file
     ///
     /// Tries to load and parse configuration from the given *file* path.
     pub fn load_from(file: &Path) -> Result<DeviceConfig> {
-        let config_json = fs::read_to_string(file)?;
+        let config_json = retry_on_interrupt(|| fs::read_to_string(file))?;
         Ok(serde_json::from_str::<DeviceConfig>(&config_json)?)
     }
 
+    /// Load from file in the given *format*
+    pub fn load_as(file: &Path, format: ConfigFormat) -> Result<DeviceConfig> {
+        format.load(file)
+    }
+
+    /// Borrow locale if set
+    pub fn locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
     /// Borrow device name
     pub fn name(&self) -> &str {
-        &self.name
+        self.name.as_str()
+    }
+
+    /// Remove a vendor-specific setting
+    ///
+    /// Returns the removed value if the *key* was set.
+    pub fn remove_extra_value(&mut self, key: &str) -> Option<Value> {
+        self.extra.remove(key)
     }
 
     /// Save to file
     ///
     /// Tries to write configuration to the given *file* as pretty JSON.
     pub fn save_to(&self, file: &Path) -> Result<()> {
-        let config_json = serde_json::to_string_pretty(&self)?;
-        fs::write(file, config_json.as_bytes())?;
+        self.save_to_with(file, true)
+    }
+
+    /// Save to file in the given *format*
+    ///
+    /// JSON is written pretty printed. Use [load_as](DeviceConfig::load_as) with the same format
+    /// to read the file.
+    pub fn save_as(&self, file: &Path, format: ConfigFormat) -> Result<()> {
+        format.save(self, file)
+    }
+
+    /// Save to file as pretty or compact JSON
+    ///
+    /// Compact JSON saves space on constrained flash storage. [load_from](DeviceConfig::load_from)
+    /// reads both.
+    pub fn save_to_with(&self, file: &Path, pretty: bool) -> Result<()> {
+        let config_json = self.to_json_bytes(pretty)?;
+        retry_on_interrupt(|| fs::write(file, &config_json))?;
         Ok(())
     }
 
+    /// Check that the configuration values are acceptable
+    ///
+    /// The name rules are checked already when the [DeviceName] is created, so this checks the
+    /// rest of the values. The shared DHT key must not be null, and the optional time zone,
+    /// locale, and description must pass [validate_timezone], [validate_locale], and
+    /// [validate_description].
+    pub fn validate(&self) -> Result<()> {
+        if self.dht_shared_key.is_null() {
+            return Err(Error::security_key_wrong("DHT shared key is null"));
+        }
+        if let Some(timezone) = &self.timezone {
+            validate_timezone(timezone)?;
+        }
+        if let Some(locale) = &self.locale {
+            validate_locale(locale)?;
+        }
+        if let Some(description) = &self.description {
+            validate_description(description)?;
+        }
+        Ok(())
+    }
+
+    /// Change description
+    pub fn set_description(&mut self, description: Option<String>) {
+        self.description = description;
+    }
+
     /// Change shared DHT key
     pub fn set_dht_shared_key(&mut self, dht_shared_key: SecurityKey) {
         self.dht_shared_key = dht_shared_key;
     }
 
+    /// Change a vendor-specific setting
+    ///
+    /// The *key* cannot be the name of a known configuration field.
+    pub fn set_extra_value<T: Serialize>(&mut self, key: &str, value: &T) -> Result<()> {
+        if DEVICE_CONFIG_FIELDS.contains(&key) {
+            return Err(Error::config_value_invalid(
+                "vendor setting cannot use a configuration field name",
+            ));
+        }
+        self.extra
+            .insert(key.to_string(), serde_json::to_value(value)?);
+        Ok(())
+    }
+
+    /// Change locale
+    pub fn set_locale(&mut self, locale: Option<String>) {
+        self.locale = locale;
+    }
+
     /// Change device name
-    pub fn set_name(&mut self, name: String) {
+    pub fn set_name(&mut self, name: DeviceName) {
         self.name = name;
     }
+
+    /// Change time zone
+    pub fn set_timezone(&mut self, timezone: Option<String>) {
+        self.timezone = timezone;
+    }
+
+    /// Borrow time zone if set
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
+
+    /// Canonical JSON bytes for hashing
+    ///
+    /// The keys of all objects, including the vendor settings, are sorted and there is no
+    /// whitespace, so equal configurations always give the same bytes regardless of the field
+    /// order in the struct or in the loaded file. Use these bytes for checksums and fingerprints.
+    pub fn canonical_bytes(&self) -> Result<Vec<u8>> {
+        /// Rebuild objects with sorted keys
+        fn sorted(value: Value) -> Value {
+            match value {
+                Value::Object(map) => {
+                    let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+                    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    Value::Object(
+                        entries
+                            .into_iter()
+                            .map(|(key, value)| (key, sorted(value)))
+                            .collect(),
+                    )
+                }
+                Value::Array(values) => Value::Array(values.into_iter().map(sorted).collect()),
+                other => other,
+            }
+        }
+        Ok(serde_json::to_vec(&sorted(serde_json::to_value(self)?))?)
+    }
+
+    /// Convenience function to turn configuration to JSON bytes
+    ///
+    /// The bytes are the same that [save_to](DeviceConfig::save_to) writes when *pretty* is true.
+    pub fn to_json_bytes(&self, pretty: bool) -> Result<Vec<u8>> {
+        Ok(match pretty {
+            true => serde_json::to_vec_pretty(&self)?,
+            false => serde_json::to_vec(&self)?,
+        })
+    }
+}
+
+/// Expand `${VAR}` references in the *text*
+///
+/// The *var* function returns the value of the named environment variable. Undefined variables
+/// and unterminated references are errors.
+fn expand_env_vars<F>(text: &str, var: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let reference = &rest[start + 2..];
+        let end = reference.find('}').ok_or_else(|| {
+            Error::path_expansion_failed(format!("unterminated variable reference in {text:?}"))
+        })?;
+        let name = &reference[..end];
+        match var(name) {
+            Some(value) => expanded.push_str(&value),
+            None => {
+                return Err(Error::path_expansion_failed(format!(
+                    "environment variable `{name}` used in {text:?} is not set"
+                )))
+            }
+        }
+        rest = &reference[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Alphabet of the provisioning token, the RFC 4648 base32 alphabet
+const TOKEN_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Number of characters between the dashes of the provisioning token
+const TOKEN_GROUP_LEN: usize = 4;
+
+/// Bytes encoded into the provisioning token: UUID, authorization key, and CRC-32
+const TOKEN_BYTES: usize = 16 + 32 + 4;
+
+/// CRC-32 (IEEE 802.3) checksum of the *data*
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
 }
 
+/// Identity of the device as a compact token
+///
+/// The token contains the UUID and the authorization key of the device, and it is meant to be
+/// read aloud, for example, when support helps with provisioning over the phone. It is written in
+/// base32 in groups of four characters separated with dashes. A CRC-32 checksum is included to
+/// catch typos.
+///
+/// When parsing, letter case, dashes, and whitespace are ignored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ProvisioningToken {
+    /// Device UUID
+    pub uuid: Uuid,
+    /// Authorization key for the API
+    pub authorization_key: SecurityKey,
+}
+
+impl Display for ProvisioningToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = Vec::with_capacity(TOKEN_BYTES);
+        bytes.extend_from_slice(self.uuid.as_bytes());
+        bytes.extend_from_slice(self.authorization_key.as_bytes());
+        bytes.extend_from_slice(&crc32(&bytes).to_be_bytes());
+
+        // Base32 encoding, the last character is padded with zero bits
+        let mut characters = Vec::with_capacity((TOKEN_BYTES * 8).div_ceil(5));
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for byte in bytes {
+            buffer = (buffer << 8) | byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                characters.push(TOKEN_ALPHABET[(buffer >> bits) as usize & 0x1f]);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        if bits > 0 {
+            characters.push(TOKEN_ALPHABET[(buffer << (5 - bits)) as usize & 0x1f]);
+        }
+
+        for (index, group) in characters.chunks(TOKEN_GROUP_LEN).enumerate() {
+            if index > 0 {
+                f.write_str("-")?;
+            }
+            // The alphabet is ASCII
+            f.write_str(std::str::from_utf8(group).unwrap_or_default())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ProvisioningToken {
+    type Err = Error;
+
+    fn from_str(token: &str) -> Result<Self> {
+        let mut bytes = Vec::with_capacity(TOKEN_BYTES);
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for c in token.chars() {
+            if c == '-' || c.is_whitespace() {
+                continue;
+            }
+            let value = TOKEN_ALPHABET
+                .iter()
+                .position(|&a| a as char == c.to_ascii_uppercase())
+                .ok_or_else(|| {
+                    Error::provisioning_token_invalid("token contains invalid characters")
+                })?;
+            buffer = (buffer << 5) | value as u32;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((buffer >> bits) as u8);
+                buffer &= (1 << bits) - 1;
+            }
+        }
+        // Padding bits must be zero, otherwise a typo in the last character could go unnoticed
+        if bytes.len() != TOKEN_BYTES || bits >= 5 || buffer != 0 {
+            return Err(Error::provisioning_token_invalid("token has wrong length"));
+        }
+
+        let (data, checksum) = bytes.split_at(TOKEN_BYTES - 4);
+        if crc32(data).to_be_bytes() != checksum {
+            return Err(Error::provisioning_token_invalid(
+                "token checksum does not match",
+            ));
+        }
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&data[..16]);
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&data[16..]);
+        Ok(ProvisioningToken {
+            uuid: Uuid::from_bytes(uuid),
+            authorization_key: SecurityKey::from_bytes(key),
+        })
+    }
+}
+
+/// Start of the [QrPayloadFormat::Uri] payload, followed by the device UUID
+pub const QR_URI_PREFIX: &str = "sifis://device/";
+
+/// Payload formats for the QR code delivered with the device
+///
+/// Use [DeviceInfo::qr_payload_format] to create the payload, and
+/// [parse_key](QrPayloadFormat::parse_key) to read the authorization key back from it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum QrPayloadFormat {
+    /// Authorization key as an uppercase hex string
+    #[default]
+    Hex,
+    /// `sifis://device/<uuid>?key=<hex>` URI with the device UUID and the authorization key
+    Uri,
+    /// Authorization key as a standard base64 string with padding
+    Base64,
+}
+
+impl QrPayloadFormat {
+    /// All supported formats
+    pub const ALL: [QrPayloadFormat; 3] = [
+        QrPayloadFormat::Hex,
+        QrPayloadFormat::Uri,
+        QrPayloadFormat::Base64,
+    ];
+
+    /// Name of the format, which [FromStr] accepts
+    pub fn name(&self) -> &'static str {
+        match self {
+            QrPayloadFormat::Hex => "hex",
+            QrPayloadFormat::Uri => "uri",
+            QrPayloadFormat::Base64 => "base64",
+        }
+    }
+
+    /// Read the authorization key from a *payload* in this format
+    ///
+    /// Hex payloads can use any letter case. The UUID of a URI payload must be valid, but it is not
+    /// returned.
+    pub fn parse_key(&self, payload: &str) -> Result<SecurityKey> {
+        match self {
+            QrPayloadFormat::Hex => SecurityKey::from_hex(payload),
+            QrPayloadFormat::Base64 => SecurityKey::from_base64(payload),
+            QrPayloadFormat::Uri => {
+                let rest = payload.strip_prefix(QR_URI_PREFIX).ok_or_else(|| {
+                    Error::security_key_wrong("the payload is not a sifis:// URI")
+                })?;
+                let (uuid, query) = rest.split_once('?').unwrap_or((rest, ""));
+                Uuid::parse_str(uuid).map_err(|_| {
+                    Error::device_uuid_invalid("the sifis:// URI has an invalid UUID")
+                })?;
+                let key = query
+                    .split('&')
+                    .find_map(|parameter| parameter.strip_prefix("key="))
+                    .ok_or_else(|| Error::security_key_wrong("the sifis:// URI has no key"))?;
+                SecurityKey::from_hex(key)
+            }
+        }
+    }
+}
+
+impl Display for QrPayloadFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for QrPayloadFormat {
+    type Err = Error;
+
+    /// Parse the format from its [name](QrPayloadFormat::name)
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        QrPayloadFormat::ALL
+            .into_iter()
+            .find(|format| format.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| Error::format_unsupported(s.to_string()))
+    }
+}
+
+/// Field names of [DeviceInfo] in the `device.json` file with their older snake_case names
+const DEVICE_INFO_FIELDS: [(&str, &str); 4] = [
+    ("productName", "product_name"),
+    ("authorizationKey", "authorization_key"),
+    ("privateKeyFile", "private_key_file"),
+    ("uuid", "uuid"),
+];
+
 /// Smart Device Information
 ///
 /// This information is pre-written at the factory or can be generated when the Smart Device Mobile
@@ -79,14 +979,21 @@ impl DeviceConfig {
 ///
 /// Some or all of these are delivered with the device in a QR code for the mobile application to
 /// scan.
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+///
+/// Fields are written in camelCase to match the mobile application. Files written with the older
+/// snake_case names are still read.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct DeviceInfo {
     /// Product name
+    #[serde(alias = "product_name")]
     product_name: String,
     /// 256-bit authorization key in hex format. SIFIS-Home mobile application needs this key to
     /// access configuration endpoints of the Smart Device Mobile API service.
+    #[serde(alias = "authorization_key")]
     authorization_key: SecurityKey,
     /// Path to DHT private key file. The sifis-dht generates key file on the first run
+    #[serde(alias = "private_key_file")]
     private_key_file: PathBuf,
     /// 128-bit UUID in standard hex format
     uuid: Uuid,
@@ -113,27 +1020,130 @@ impl DeviceInfo {
         &self.authorization_key
     }
 
+    /// Creation time of the device information
+    ///
+    /// The time is read from the UUIDv7 timestamp, in milliseconds since the UNIX_EPOCH. Returns
+    /// None for other UUID versions, which do not contain a suitable timestamp.
+    pub fn created_at_unix_ms(&self) -> Option<u64> {
+        match self.uuid.get_version_num() {
+            7 => Some((self.uuid.as_u128() >> 80) as u64),
+            _ => None,
+        }
+    }
+
+    /// Compare with the *other* device information, ignoring the authorization key
+    ///
+    /// Returns true if all the other fields are equal, for example when only the key was rotated.
+    pub fn eq_ignoring_keys(&self, other: &DeviceInfo) -> bool {
+        let DeviceInfo {
+            product_name,
+            authorization_key: _,
+            private_key_file,
+            uuid,
+        } = self;
+        *product_name == other.product_name
+            && *private_key_file == other.private_key_file
+            && *uuid == other.uuid
+    }
+
     /// Load from file
     ///
     /// Tries to load and parse device information from the given *file* path.
     pub fn load_from(file: &Path) -> Result<DeviceInfo> {
-        let info_json = fs::read_to_string(file)?;
+        let info_json = retry_on_interrupt(|| fs::read_to_string(file))?;
         Ok(serde_json::from_str::<DeviceInfo>(&info_json)?)
     }
 
+    /// Load from file in the given *format*
+    pub fn load_as(file: &Path, format: ConfigFormat) -> Result<DeviceInfo> {
+        format.load(file)
+    }
+
+    /// Save to file in the given *format*
+    ///
+    /// JSON is written pretty printed. Use [load_as](DeviceInfo::load_as) with the same format
+    /// to read the file.
+    pub fn save_as(&self, file: &Path, format: ConfigFormat) -> Result<()> {
+        format.save(self, file)
+    }
+
     /// Save to file
     ///
     /// Tries to write device information to the given *file* as pretty JSON.
     pub fn save_to(&self, file: &Path) -> Result<()> {
-        fs::write(file, self.to_json(true)?.as_bytes())?;
+        self.save_to_with(file, true)
+    }
+
+    /// Save to file as pretty or compact JSON
+    ///
+    /// Compact JSON saves space on constrained flash storage. [load_from](DeviceInfo::load_from)
+    /// reads both.
+    pub fn save_to_with(&self, file: &Path, pretty: bool) -> Result<()> {
+        let info_json = self.to_json(pretty)?;
+        retry_on_interrupt(|| fs::write(file, info_json.as_bytes()))?;
         Ok(())
     }
 
+    /// Provisioning token with the UUID and the authorization key
+    ///
+    /// See [ProvisioningToken] for the format.
+    pub fn provisioning_token(&self) -> String {
+        ProvisioningToken {
+            uuid: self.uuid,
+            authorization_key: self.authorization_key,
+        }
+        .to_string()
+    }
+
+    /// Payload for the QR code in the given *format*
+    ///
+    /// See [QrPayloadFormat] for the formats.
+    pub fn qr_payload_format(&self, format: QrPayloadFormat) -> String {
+        match format {
+            QrPayloadFormat::Hex => self.authorization_key.hex(true),
+            QrPayloadFormat::Uri => format!(
+                "{QR_URI_PREFIX}{}?key={}",
+                self.uuid,
+                self.authorization_key.hex(false)
+            ),
+            QrPayloadFormat::Base64 => self.authorization_key.to_base64(),
+        }
+    }
+
+    /// Parse a token made with [provisioning_token](DeviceInfo::provisioning_token)
+    ///
+    /// Returns an error if the token is malformed or its checksum does not match.
+    pub fn from_provisioning_token(token: &str) -> Result<ProvisioningToken> {
+        token.parse()
+    }
+
     /// Borrow private key file path
     pub fn private_key_file(&self) -> &PathBuf {
         &self.private_key_file
     }
 
+    /// Private key file path with environment variables expanded
+    ///
+    /// `${VAR}` references in the stored path are replaced with the values of the environment
+    /// variables, so that paths like `${SIFIS_HOME_PATH}/private.pem` stay portable when the
+    /// SIFIS-Home directory is relocated. The stored path is not changed.
+    ///
+    /// Returns an error if a referenced variable is not set.
+    pub fn resolved_private_key_file(&self) -> Result<PathBuf> {
+        self.resolve_private_key_file_with(|name| env::var(name).ok())
+    }
+
+    /// Expand the private key file path using the *var* function for the variable values
+    fn resolve_private_key_file_with<F>(&self, var: F) -> Result<PathBuf>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        match self.private_key_file.to_str() {
+            Some(path) => Ok(PathBuf::from(expand_env_vars(path, var)?)),
+            None => Ok(self.private_key_file.clone()),
+        }
+    }
+
     /// Borrow product name
     pub fn product_name(&self) -> &str {
         &self.product_name
@@ -162,11 +1172,67 @@ impl DeviceInfo {
         self.product_name = product_name;
     }
 
+    /// Replace the UUID with a new UUIDv7 from the *srng*
+    ///
+    /// Use this when two devices were accidentally given the same UUID. The product name,
+    /// authorization key, and private key path are kept. Afterwards, the
+    /// [created_at_unix_ms()](DeviceInfo::created_at_unix_ms) time comes from the new UUID.
+    pub fn regenerate_uuid(&mut self, srng: &SRNG) -> Result<()> {
+        self.uuid = srng.generate_uuid()?;
+        Ok(())
+    }
+
     /// Change UUID
     pub fn set_uuid(&mut self, uuid: Uuid) {
         self.uuid = uuid;
     }
 
+    /// Check a `device.json` document against what this version expects
+    ///
+    /// The *json* must parse as device information, otherwise the parse error is returned. All
+    /// current fields are required, so a missing field is an error. Issues that do not stop the
+    /// document from loading are returned as warnings:
+    ///
+    /// * Fields that this version does not know, which are dropped when the file is saved again
+    /// * Fields with their older snake_case names, which are renamed when the file is saved again
+    /// * A UUID that is not version 7, so that the creation time is unknown and the
+    ///   [is_valid_device_uuid] check fails
+    /// * An empty product name, or whitespace around it
+    ///
+    /// Returns an empty list for an up-to-date document.
+    pub fn validate_schema(json: &str) -> Result<Vec<String>> {
+        let device_info = serde_json::from_str::<DeviceInfo>(json)?;
+        let document = serde_json::from_str::<Map<String, Value>>(json)?;
+        let mut warnings = Vec::new();
+        for key in document.keys() {
+            match DEVICE_INFO_FIELDS
+                .iter()
+                .find(|(name, old_name)| key == name || key == old_name)
+            {
+                Some((name, _)) if key != name => warnings.push(format!(
+                    "field `{key}` uses the old name, it is saved as `{name}`"
+                )),
+                Some(_) => {}
+                None => warnings.push(format!(
+                    "unknown field `{key}` is dropped when the file is saved"
+                )),
+            }
+        }
+        if !is_valid_device_uuid(&device_info.uuid) {
+            warnings.push(format!(
+                "uuid {} is not a version 7 UUID, the creation time is unknown",
+                device_info.uuid
+            ));
+        }
+        let product_name = device_info.product_name.trim();
+        if product_name.is_empty() {
+            warnings.push("productName is empty".to_string());
+        } else if product_name != device_info.product_name {
+            warnings.push("productName has whitespace around it".to_string());
+        }
+        Ok(warnings)
+    }
+
     /// Convenience function to turn device information to JSON
     pub fn to_json(&self, pretty: bool) -> Result<String> {
         Ok(match pretty {
@@ -179,6 +1245,7 @@ impl DeviceInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::ErrorKind;
     use crate::SifisHome;
     use uuid::uuid;
 
@@ -202,13 +1269,13 @@ mod tests {
     #[test]
     fn test_device_config() {
         // Testing constructor and getters
-        let mut config = DeviceConfig::new(TEST_KEY_A, "Test config".to_string());
+        let mut config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
         assert_eq!(config.dht_shared_key(), &TEST_KEY_A);
         assert_eq!(config.name(), "Test config");
 
         // Testing setters and getters
         config.set_dht_shared_key(TEST_KEY_B);
-        config.set_name(String::from("New name"));
+        config.set_name("New name".parse().unwrap());
         assert_eq!(config.dht_shared_key(), &TEST_KEY_B);
         assert_eq!(config.name(), "New name");
     }
@@ -216,7 +1283,8 @@ mod tests {
     #[test]
     fn test_device_config_serde() {
         // Testing human readable with JSON
-        let config_a = DeviceConfig::new(SecurityKey::new().unwrap(), String::from("Test device"));
+        let config_a =
+            DeviceConfig::new(SecurityKey::new().unwrap(), "Test device".parse().unwrap());
         let json = serde_json::to_string(&config_a).unwrap();
         let config_b = serde_json::from_str::<DeviceConfig>(&json).unwrap();
         assert_eq!(config_a, config_b);
@@ -227,6 +1295,641 @@ mod tests {
         assert_eq!(config_a, config_b);
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)] // File operations are not available with miri
+    fn test_device_config_field_names() {
+        let config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["dhtSharedKey"], TEST_KEY_A.hex(false));
+        assert!(json.get("dht_shared_key").is_none());
+
+        // Old snake_case and new camelCase documents give the same configuration
+        let key = TEST_KEY_A.hex(false);
+        let old_json = format!(r#"{{"name":"Test config","dht_shared_key":"{key}"}}"#);
+        let new_json = format!(r#"{{"name":"Test config","dhtSharedKey":"{key}"}}"#);
+        assert_eq!(
+            serde_json::from_str::<DeviceConfig>(&old_json).unwrap(),
+            config
+        );
+        assert_eq!(
+            serde_json::from_str::<DeviceConfig>(&new_json).unwrap(),
+            config
+        );
+
+        // Old files load and are written back with the new names
+        let test_dir = tempfile::tempdir().unwrap();
+        let file = test_dir.path().join("config.json");
+        fs::write(&file, &old_json).unwrap();
+        let loaded = DeviceConfig::load_from(&file).unwrap();
+        assert_eq!(loaded, config);
+        assert!(loaded.extra().is_empty());
+        loaded.save_to(&file).unwrap();
+        let saved = fs::read_to_string(&file).unwrap();
+        assert!(saved.contains("dhtSharedKey"));
+        assert!(!saved.contains("dht_shared_key"));
+
+        // Neither name can be used for a vendor setting
+        let mut config = config;
+        assert!(config.set_extra_value("dhtSharedKey", &1).is_err());
+        assert!(config.set_extra_value("dht_shared_key", &1).is_err());
+
+        // The schema uses the new names
+        let schema = serde_json::to_value(schemars::schema_for!(DeviceConfig)).unwrap();
+        assert!(schema["properties"].get("dhtSharedKey").is_some());
+        assert!(schema["properties"].get("dht_shared_key").is_none());
+    }
+
+    #[test]
+    fn test_device_config_generate() {
+        let config_a = DeviceConfig::generate("Kitchen".to_string()).unwrap();
+        let config_b = DeviceConfig::generate("Kitchen".to_string()).unwrap();
+        assert_eq!(config_a.name(), "Kitchen");
+        assert_eq!(config_b.name(), "Kitchen");
+        assert!(!config_a.dht_shared_key().is_null());
+        assert_ne!(config_a.dht_shared_key(), config_b.dht_shared_key());
+        assert!(config_a.eq_ignoring_keys(&config_b));
+
+        // The name is validated like elsewhere
+        let error = DeviceConfig::generate(" ".to_string()).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::DeviceNameInvalid(_)));
+    }
+
+    #[test]
+    fn test_device_config_eq_ignoring_keys() {
+        let config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        assert!(config.eq_ignoring_keys(&config.clone()));
+
+        // Only the key differs
+        let mut other = config.clone();
+        other.set_dht_shared_key(TEST_KEY_B);
+        assert_ne!(config, other);
+        assert!(config.eq_ignoring_keys(&other));
+        assert!(other.eq_ignoring_keys(&config));
+
+        // Any other field makes them unequal
+        let mut other = config.clone();
+        other.set_name("Other".parse().unwrap());
+        assert!(!config.eq_ignoring_keys(&other));
+        let mut other = config.clone();
+        other.set_timezone(Some("Europe/Rome".to_string()));
+        assert!(!config.eq_ignoring_keys(&other));
+        let mut other = config.clone();
+        other.set_locale(Some("it_IT.UTF-8".to_string()));
+        assert!(!config.eq_ignoring_keys(&other));
+        let mut other = config.clone();
+        other.set_description(Some("Kitchen".to_string()));
+        assert!(!config.eq_ignoring_keys(&other));
+        let mut other = config.clone();
+        other.set_extra_value("volume", &3).unwrap();
+        other.set_dht_shared_key(TEST_KEY_B);
+        assert!(!config.eq_ignoring_keys(&other));
+    }
+
+    #[test]
+    fn test_device_config_diff() {
+        let config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+
+        // Identical
+        let diff = config.diff(&config.clone());
+        assert!(diff.is_empty());
+        assert!(!diff.requires_reboot());
+
+        // Name changed
+        let mut renamed = config.clone();
+        renamed.set_name("New name".parse().unwrap());
+        let diff = config.diff(&renamed);
+        assert_eq!(
+            diff,
+            ConfigDiff {
+                name: true,
+                ..Default::default()
+            }
+        );
+        assert!(!diff.requires_reboot());
+
+        // Key changed
+        let mut rekeyed = config.clone();
+        rekeyed.set_dht_shared_key(TEST_KEY_B);
+        let diff = config.diff(&rekeyed);
+        assert_eq!(
+            diff,
+            ConfigDiff {
+                dht_shared_key: true,
+                ..Default::default()
+            }
+        );
+        assert!(diff.requires_reboot());
+
+        // Other fields
+        let mut other = renamed.clone();
+        other.set_timezone(Some("Europe/Rome".to_string()));
+        let diff = config.diff(&other);
+        assert!(diff.name && diff.timezone);
+        assert!(!diff.requires_reboot());
+        other.set_locale(Some("it_IT.UTF-8".to_string()));
+        assert!(config.diff(&other).requires_reboot());
+        let mut other = config.clone();
+        other.set_extra_value("volume", &3).unwrap();
+        assert!(config.diff(&other).extra);
+        assert!(config.diff(&other).requires_reboot());
+    }
+
+    #[test]
+    fn test_dht_node_id() {
+        let config_a = DeviceConfig::new(TEST_KEY_A, "Device A".parse().unwrap());
+        let config_b = DeviceConfig::new(TEST_KEY_B, "Device B".parse().unwrap());
+
+        // Same key gives the same id regardless of the name
+        let same_key = DeviceConfig::new(TEST_KEY_A, "Other name".parse().unwrap());
+        assert_eq!(config_a.dht_node_id(), same_key.dht_node_id());
+        assert_eq!(config_a.dht_node_id(), config_a.dht_node_id());
+
+        // Different keys give different ids
+        assert_ne!(config_a.dht_node_id(), config_b.dht_node_id());
+
+        // Known answers that other implementations can check against
+        assert_eq!(
+            config_a.dht_node_id(),
+            [
+                0xa8, 0xd8, 0xa8, 0xa4, 0xda, 0x43, 0x03, 0xdf, 0x64, 0xd5, 0xfb, 0xbf, 0xd0, 0x4d,
+                0xa6, 0x43,
+            ]
+        );
+        assert_eq!(
+            config_b.dht_node_id(),
+            [
+                0x74, 0xf9, 0xaa, 0x8d, 0xdf, 0x86, 0xc0, 0x4d, 0xe2, 0xa8, 0x1d, 0x15, 0x12, 0x55,
+                0xa0, 0x10,
+            ]
+        );
+
+        // The id should not be a plain digest of the key
+        let plain = digest::digest(&digest::SHA256, TEST_KEY_A.as_bytes());
+        assert_ne!(
+            &config_a.dht_node_id()[..],
+            &plain.as_ref()[..DHT_NODE_ID_BYTES]
+        );
+    }
+
+    #[test]
+    fn test_device_config_validate() {
+        let config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        assert!(config.validate().is_ok());
+        let null_key = SecurityKey::from_bytes([0; 32]);
+        let config = DeviceConfig::new(null_key, "Test config".parse().unwrap());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_device_config_timezone_and_locale() {
+        let mut config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        assert_eq!(config.timezone(), None);
+        assert_eq!(config.locale(), None);
+        config.set_timezone(Some("Europe/Rome".to_string()));
+        config.set_locale(Some("it_IT.UTF-8".to_string()));
+        assert_eq!(config.timezone(), Some("Europe/Rome"));
+        assert_eq!(config.locale(), Some("it_IT.UTF-8"));
+        assert!(config.validate().is_ok());
+
+        config.set_timezone(Some("Mars/Olympus_Mons".to_string()));
+        assert!(config.validate().is_err());
+        config.set_timezone(None);
+        config.set_locale(Some("en US".to_string()));
+        assert!(config.validate().is_err());
+
+        // Older configuration files do not have the fields
+        let json = format!(
+            r#"{{"name":"Test config","dht_shared_key":"{}"}}"#,
+            TEST_KEY_A
+        );
+        let config = serde_json::from_str::<DeviceConfig>(&json).unwrap();
+        assert_eq!(config.timezone(), None);
+        assert_eq!(config.locale(), None);
+    }
+
+    #[test]
+    fn test_device_config_description() {
+        let mut config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        assert_eq!(config.description(), None);
+        let description = "Kitchen, controls the dishwasher";
+        config.set_description(Some(description.to_string()));
+        assert_eq!(config.description(), Some(description));
+        assert!(config.validate().is_ok());
+        assert!(config.diff(&config.clone()).is_empty());
+        let diff = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap()).diff(&config);
+        assert!(diff.description);
+        assert!(!diff.requires_reboot());
+
+        // Length is counted in characters
+        let longest = "ä".repeat(DEVICE_DESCRIPTION_MAX_CHARS);
+        assert!(validate_description(&longest).is_ok());
+        assert!(validate_description("").is_ok());
+        let error = validate_description(&format!("{longest}a")).unwrap_err();
+        assert_eq!(error.to_string(), "description is too long");
+        config.set_description(Some(format!("{longest}a")));
+        assert!(config.validate().is_err());
+
+        // Serialized only when set, and older configuration files do not have the field
+        config.set_description(Some(description.to_string()));
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains(r#""description":"Kitchen, controls the dishwasher""#));
+        assert_eq!(serde_json::from_str::<DeviceConfig>(&json).unwrap(), config);
+        config.set_description(None);
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("description"));
+        let json = format!(
+            r#"{{"name":"Test config","dht_shared_key":"{}"}}"#,
+            TEST_KEY_A
+        );
+        let config = serde_json::from_str::<DeviceConfig>(&json).unwrap();
+        assert_eq!(config.description(), None);
+        assert!(config.extra().is_empty());
+    }
+
+    #[test]
+    fn test_device_config_extra() {
+        let mut config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        assert!(config.extra().is_empty());
+        assert_eq!(config.extra_value::<u32>("brightness").unwrap(), None);
+
+        // Typed helpers
+        config.set_extra_value("brightness", &80u32).unwrap();
+        config
+            .set_extra_value("vendor", &vec!["a".to_string(), "b".to_string()])
+            .unwrap();
+        assert_eq!(config.extra_value::<u32>("brightness").unwrap(), Some(80));
+        assert_eq!(
+            config.extra_value::<Vec<String>>("vendor").unwrap(),
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert!(config.extra_value::<String>("brightness").is_err());
+        assert!(config.set_extra_value("name", &"Other").is_err());
+        assert_eq!(config.name(), "Test config");
+        assert_eq!(
+            config.remove_extra_value("vendor"),
+            Some(Value::from(vec!["a", "b"]))
+        );
+        assert_eq!(config.remove_extra_value("vendor"), None);
+
+        // Unknown fields survive a load and save cycle
+        let json = format!(
+            r#"{{"name":"Test config","dht_shared_key":"{}","led":{{"color":"red"}},"volume":3}}"#,
+            TEST_KEY_A
+        );
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let file = test_dir.path().join("config.json");
+        fs::write(&file, json).unwrap();
+        let loaded = DeviceConfig::load_from(&file).unwrap();
+        assert_eq!(loaded.name(), "Test config");
+        assert_eq!(loaded.extra().len(), 2);
+        assert_eq!(loaded.extra_value::<i64>("volume").unwrap(), Some(3));
+        loaded.save_to(&file).unwrap();
+        let reloaded = DeviceConfig::load_from(&file).unwrap();
+        assert_eq!(reloaded, loaded);
+        assert_eq!(reloaded.extra()["led"]["color"], "red");
+
+        // Known fields are still parsed strictly
+        for json in [
+            r#"{"name":5,"dht_shared_key":"f0e1d2c3b4a5968778695a4b3c2d1e0f0f1e2d3c4b5a69788796a5b4c3d2e1f0"}"#,
+            r#"{"name":"","dht_shared_key":"f0e1d2c3b4a5968778695a4b3c2d1e0f0f1e2d3c4b5a69788796a5b4c3d2e1f0"}"#,
+            r#"{"name":"Test config","dht_shared_key":"not a key"}"#,
+            r#"{"name":"Test config","volume":3}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<DeviceConfig>(json).is_err(),
+                "{json}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_format() {
+        let format_of = |path: &str| ConfigFormat::from_extension(Path::new(path));
+        assert_eq!(format_of("config.json").unwrap(), ConfigFormat::Json);
+        assert_eq!(format_of("/opt/DEVICE.JSON").unwrap(), ConfigFormat::Json);
+        assert_eq!(
+            format_of("config.msgpack").unwrap(),
+            ConfigFormat::MessagePack
+        );
+        assert_eq!(format_of("config.mpk").unwrap(), ConfigFormat::MessagePack);
+        assert_eq!(format_of("config.cbor").unwrap(), ConfigFormat::Cbor);
+        assert_eq!(format_of("config.TOML").unwrap(), ConfigFormat::Toml);
+        for path in ["config.yaml", "config", "json"] {
+            let error = format_of(path).unwrap_err();
+            assert!(matches!(error.kind(), ErrorKind::FormatUnsupported(_)));
+        }
+
+        // Names
+        for format in ConfigFormat::ALL {
+            assert_eq!(format.name().parse::<ConfigFormat>().unwrap(), format);
+            assert_eq!(format.to_string(), format.name());
+            assert!(!format.description().is_empty());
+        }
+        assert_eq!(
+            " JSON ".parse::<ConfigFormat>().unwrap(),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            "mpk".parse::<ConfigFormat>().unwrap(),
+            ConfigFormat::MessagePack
+        );
+        assert!("yaml".parse::<ConfigFormat>().is_err());
+    }
+
+    #[cfg_attr(miri, ignore)] // File operations are not available with miri
+    #[test]
+    fn test_config_format_round_trip() {
+        let test_dir = tempfile::TempDir::new().unwrap();
+        let mut config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        config.set_timezone(Some("Europe/Rome".to_string()));
+        config.set_extra_value("volume", &3).unwrap();
+        let info = DeviceInfo::new(
+            "Test product".to_string(),
+            TEST_KEY_B,
+            PathBuf::from("/tmp/private.pem"),
+            TEST_UUID,
+        );
+        for extension in ["json", "msgpack", "mpk", "cbor", "toml"] {
+            let config_file = test_dir.path().join(format!("config.{extension}"));
+            let info_file = test_dir.path().join(format!("device.{extension}"));
+            let format = ConfigFormat::from_extension(&config_file).unwrap();
+            config.save_as(&config_file, format).unwrap();
+            info.save_as(&info_file, format).unwrap();
+            assert_eq!(DeviceConfig::load_as(&config_file, format).unwrap(), config);
+            assert_eq!(DeviceInfo::load_as(&info_file, format).unwrap(), info);
+        }
+
+        // JSON is the same as the regular files
+        let json_file = test_dir.path().join("config.json");
+        assert_eq!(DeviceConfig::load_from(&json_file).unwrap(), config);
+
+        // Reading in the wrong format fails
+        let msgpack_file = test_dir.path().join("config.msgpack");
+        assert!(DeviceConfig::load_as(&msgpack_file, ConfigFormat::Json).is_err());
+        assert!(DeviceConfig::load_as(&json_file, ConfigFormat::MessagePack).is_err());
+        let cbor_file = test_dir.path().join("config.cbor");
+        assert!(DeviceConfig::load_as(&cbor_file, ConfigFormat::Toml).is_err());
+        assert!(DeviceConfig::load_as(&json_file, ConfigFormat::Cbor).is_err());
+
+        // TOML is readable text with the same field names
+        let toml = fs::read_to_string(test_dir.path().join("config.toml")).unwrap();
+        assert!(toml.contains("name = \"Test config\""));
+        assert!(toml.contains("timezone = \"Europe/Rome\""));
+    }
+
+    #[test]
+    fn test_timezone_validation() {
+        for timezone in [
+            "UTC",
+            "Etc/UTC",
+            "Etc/GMT+2",
+            "Europe/Helsinki",
+            "America/Argentina/Buenos_Aires",
+            "America/Port-au-Prince",
+            "Europe/Kyiv",
+            "Europe/Kiev",
+        ] {
+            assert!(validate_timezone(timezone).is_ok(), "{timezone}");
+        }
+        for timezone in [
+            "",
+            "utc",
+            "Helsinki",
+            "Europe",
+            "Europe/",
+            "Mars/Olympus_Mons",
+            "europe/helsinki",
+            "Europe/Hel sinki",
+            "Europe/../etc/passwd",
+            "America/Argentina/Buenos_Aires/Extra",
+            "Europe/Nowhere",
+            "America/Atlantis",
+            "US/Eastern",
+        ] {
+            assert!(validate_timezone(timezone).is_err(), "{timezone}");
+        }
+    }
+
+    #[test]
+    fn test_locale_validation() {
+        for locale in ["fi", "en_US", "en_US.UTF-8", "sr_RS@latin"] {
+            assert!(validate_locale(locale).is_ok(), "{locale}");
+        }
+        for locale in ["", "_US", "en US", "en_US;rm"] {
+            assert!(validate_locale(locale).is_err(), "{locale}");
+        }
+    }
+
+    #[test]
+    fn test_device_config_json_bytes() {
+        let config = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        let compact = config.to_json_bytes(false).unwrap();
+        let pretty = config.to_json_bytes(true).unwrap();
+        assert_ne!(compact, pretty);
+        assert!(!compact.contains(&b'\n'));
+        assert!(pretty.contains(&b'\n'));
+
+        // Both should parse back to the same config
+        assert_eq!(
+            serde_json::from_slice::<DeviceConfig>(&compact).unwrap(),
+            config
+        );
+        assert_eq!(
+            serde_json::from_slice::<DeviceConfig>(&pretty).unwrap(),
+            config
+        );
+        assert_eq!(
+            compact,
+            br#"{"name":"Test config","dhtSharedKey":"f0e1d2c3b4a5968778695a4b3c2d1e0f0f1e2d3c4b5a69788796a5b4c3d2e1f0"}"#
+        );
+    }
+
+    #[test]
+    fn test_device_config_canonical_bytes() {
+        let mut config_a = DeviceConfig::new(TEST_KEY_A, "Test config".parse().unwrap());
+        config_a.set_extra_value("zeta", &1).unwrap();
+        config_a
+            .set_extra_value(
+                "alpha",
+                &serde_json::json!({"b": [{"y": 1, "x": 2}], "a": null}),
+            )
+            .unwrap();
+
+        // Same values set in another order
+        let mut config_b = DeviceConfig::new(TEST_KEY_B, "Other".parse().unwrap());
+        config_b
+            .set_extra_value(
+                "alpha",
+                &serde_json::json!({"a": null, "b": [{"x": 2, "y": 1}]}),
+            )
+            .unwrap();
+        config_b.set_extra_value("zeta", &1).unwrap();
+        config_b.set_name("Test config".parse().unwrap());
+        config_b.set_dht_shared_key(TEST_KEY_A);
+        assert_eq!(config_a, config_b);
+
+        let bytes = config_a.canonical_bytes().unwrap();
+        assert_eq!(bytes, config_b.canonical_bytes().unwrap());
+        assert_eq!(bytes, config_a.clone().canonical_bytes().unwrap());
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            concat!(
+                r#"{"alpha":{"a":null,"b":[{"x":2,"y":1}]},"#,
+                r#""dhtSharedKey":"f0e1d2c3b4a5968778695a4b3c2d1e0f0f1e2d3c4b5a69788796a5b4c3d2e1f0","#,
+                r#""name":"Test config","zeta":1}"#
+            )
+        );
+
+        // Different configurations give different bytes
+        config_b.set_locale(Some("fi_FI".to_string()));
+        assert_ne!(
+            config_a.canonical_bytes().unwrap(),
+            config_b.canonical_bytes().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_device_name_validation() {
+        // Empty name is not allowed
+        assert!(validate_device_name("").is_err());
+
+        // ASCII names at and over the limit
+        assert!(validate_device_name(&"a".repeat(DEVICE_NAME_MAX_BYTES)).is_ok());
+        assert!(validate_device_name(&"a".repeat(DEVICE_NAME_MAX_BYTES + 1)).is_err());
+
+        // Two-byte characters, limit is reached with half the characters
+        let name = "ä".repeat(DEVICE_NAME_MAX_BYTES / 2);
+        assert_eq!(name.len(), DEVICE_NAME_MAX_BYTES);
+        assert!(validate_device_name(&name).is_ok());
+        let name = "ä".repeat(DEVICE_NAME_MAX_BYTES / 2 + 1);
+        assert!(validate_device_name(&name).is_err());
+
+        // Three-byte characters padded to exactly the limit and one byte over it
+        let mut name = "€".repeat(DEVICE_NAME_MAX_BYTES / 3);
+        while name.len() < DEVICE_NAME_MAX_BYTES {
+            name.push('a');
+        }
+        assert!(validate_device_name(&name).is_ok());
+        name.push('a');
+        assert!(name.chars().count() < DEVICE_NAME_MAX_BYTES);
+        assert!(validate_device_name(&name).is_err());
+
+        // Control characters are not allowed
+        assert!(validate_device_name("Line\nbreak").is_err());
+        assert!(validate_device_name("Tab\there").is_err());
+    }
+
+    #[test]
+    fn test_device_name() {
+        // Valid names, surrounding whitespace is trimmed
+        let name = DeviceName::try_from(String::from("Kitchen")).unwrap();
+        assert_eq!(name.as_str(), "Kitchen");
+        let name: DeviceName = "  Living Room \t".parse().unwrap();
+        assert_eq!(name.as_str(), "Living Room");
+        assert_eq!(name.to_string(), "Living Room");
+        assert_eq!(String::from(name), "Living Room");
+
+        // Invalid names
+        assert!("".parse::<DeviceName>().is_err());
+        assert!("   ".parse::<DeviceName>().is_err());
+        assert!("Line\nbreak".parse::<DeviceName>().is_err());
+        assert!("a"
+            .repeat(DEVICE_NAME_MAX_BYTES + 1)
+            .parse::<DeviceName>()
+            .is_err());
+        assert!("ä"
+            .repeat(DEVICE_NAME_MAX_BYTES)
+            .parse::<DeviceName>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_device_name_serde() {
+        // Serialized as a plain string
+        let name: DeviceName = "Test device".parse().unwrap();
+        let json = serde_json::to_string(&name).unwrap();
+        assert_eq!(json, "\"Test device\"");
+        assert_eq!(serde_json::from_str::<DeviceName>(&json).unwrap(), name);
+
+        // Deserializing trims and validates
+        let name = serde_json::from_str::<DeviceName>("\" Padded \"").unwrap();
+        assert_eq!(name.as_str(), "Padded");
+        assert!(serde_json::from_str::<DeviceName>("\"\"").is_err());
+        let json = format!(
+            "{{\"name\":\"{}\",\"dht_shared_key\":\"{}\"}}",
+            "a".repeat(65),
+            TEST_KEY_A
+        );
+        let error = serde_json::from_str::<DeviceConfig>(&json).unwrap_err();
+        assert!(error.to_string().contains("device name is too long"));
+    }
+
+    #[test]
+    fn test_device_name_schema() {
+        // The byte limit cannot be expressed with maxLength, which counts characters
+        let mut gen = SchemaGenerator::default();
+        let schema = DeviceName::json_schema(&mut gen).into_object();
+        let string = schema.string.unwrap();
+        assert_eq!(string.min_length, Some(1));
+        assert_eq!(string.max_length, None);
+        let description = schema.metadata.unwrap().description.unwrap();
+        assert!(description.contains(&format!("{DEVICE_NAME_MAX_BYTES} bytes")));
+    }
+
+    #[test]
+    fn test_retry_on_interrupt() {
+        // Interrupted once, then succeeds
+        let mut calls = 0;
+        let result = retry_on_interrupt(|| {
+            calls += 1;
+            match calls {
+                1 => Err(io::Error::from(io::ErrorKind::Interrupted)),
+                _ => Ok("content"),
+            }
+        });
+        assert_eq!(result.unwrap(), "content");
+        assert_eq!(calls, 2);
+
+        // Other errors are not retried
+        let mut calls = 0;
+        let result: io::Result<()> = retry_on_interrupt(|| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+
+        // Giving up if the interruptions continue
+        let mut calls = 0;
+        let result: io::Result<()> = retry_on_interrupt(|| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::Interrupted))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(calls, INTERRUPTED_MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn test_device_uuid_validation() {
+        // Generated identifiers should be valid
+        let uuid = crate::security::SRNG::new().generate_uuid().unwrap();
+        assert!(is_valid_device_uuid(&uuid));
+
+        // Version 7 UUID
+        let uuid_v7 = "01890a5d-ac96-774b-bcce-b302099a8057";
+        assert!(is_valid_device_uuid(&Uuid::parse_str(uuid_v7).unwrap()));
+
+        // Version 4 UUID should be rejected
+        let uuid_v4 = "5f8b3c30-ec2f-4228-af3b-dde564985e60";
+        assert!(!is_valid_device_uuid(&Uuid::parse_str(uuid_v4).unwrap()));
+
+        // Version 7 with the wrong variant bits should be rejected
+        let uuid_v7_ncs = "01890a5d-ac96-774b-3cce-b302099a8057";
+        assert!(!is_valid_device_uuid(
+            &Uuid::parse_str(uuid_v7_ncs).unwrap()
+        ));
+    }
+
     #[test]
     fn test_device_info() {
         // Testing construction with SifisHome
@@ -238,6 +1941,8 @@ mod tests {
         assert_eq!(device.private_key_file(), &expected_private_key_file);
         assert_eq!(device.product_name(), "Test Device");
         assert_eq!(device.uuid().get_version_num(), 7);
+        let created_at = device.created_at_unix_ms().unwrap() as u128;
+        assert!(created_at <= crate::security::get_unix_time_ms().unwrap());
 
         // Testing constructor and getters
         let test_private_key = "/tmp/test/private.key";
@@ -251,6 +1956,7 @@ mod tests {
         assert_eq!(device.private_key_file(), Path::new(test_private_key));
         assert_eq!(device.product_name(), "Test Device");
         assert_eq!(device.uuid(), &TEST_UUID);
+        assert_eq!(device.created_at_unix_ms(), None); // TEST_UUID is not UUIDv7
 
         // Testing setters and getters
         let new_uuid = uuid!("5f8b3c30-ec2f-4228-af3b-dde564985e60");
@@ -263,6 +1969,351 @@ mod tests {
         assert_eq!(device.private_key_file(), Path::new(new_private_key));
         assert_eq!(device.product_name(), "New name");
         assert_eq!(device.uuid(), &new_uuid);
+
+        // Timestamp from a known UUIDv7
+        device.set_uuid(uuid!("01890a5d-ac96-774b-bcce-b302099a8057"));
+        assert_eq!(device.created_at_unix_ms(), Some(0x0189_0a5d_ac96));
+    }
+
+    #[test]
+    fn test_device_info_validate_schema() {
+        // Up-to-date document has no warnings
+        let device = DeviceInfo::new(
+            "Test Device".to_string(),
+            TEST_KEY_A,
+            PathBuf::from("/tmp/test/private.key"),
+            SRNG::new().generate_uuid().unwrap(),
+        );
+        for pretty in [true, false] {
+            let json = device.to_json(pretty).unwrap();
+            assert!(DeviceInfo::validate_schema(&json).unwrap().is_empty());
+        }
+
+        // Outdated document still loads, but with warnings
+        let outdated_json = r#"{
+            "product_name": " Old Device ",
+            "authorization_key": "f0e1d2c3b4a5968778695a4b3c2d1e0f0f1e2d3c4b5a69788796a5b4c3d2e1f0",
+            "private_key_file": "/opt/sifis-home/private.pem",
+            "uuid": "a1a2a3a4-b1b2-41c2-91d2-d3d4d5d6d7d8",
+            "qr_code_version": 1
+        }"#;
+        assert!(serde_json::from_str::<DeviceInfo>(outdated_json).is_ok());
+        let warnings = DeviceInfo::validate_schema(outdated_json).unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                "field `authorization_key` uses the old name, it is saved as `authorizationKey`",
+                "field `private_key_file` uses the old name, it is saved as `privateKeyFile`",
+                "field `product_name` uses the old name, it is saved as `productName`",
+                "unknown field `qr_code_version` is dropped when the file is saved",
+                "uuid a1a2a3a4-b1b2-41c2-91d2-d3d4d5d6d7d8 is not a version 7 UUID, \
+                 the creation time is unknown",
+                "productName has whitespace around it",
+            ]
+        );
+        let empty_name_json = device.to_json(false).unwrap().replace("Test Device", "  ");
+        assert_eq!(
+            DeviceInfo::validate_schema(&empty_name_json).unwrap(),
+            vec!["productName is empty"]
+        );
+
+        // Documents that do not load are errors
+        let missing_uuid_json = r#"{
+            "product_name": "Old Device",
+            "authorization_key": "f0e1d2c3b4a5968778695a4b3c2d1e0f0f1e2d3c4b5a69788796a5b4c3d2e1f0",
+            "private_key_file": "/opt/sifis-home/private.pem"
+        }"#;
+        for json in [missing_uuid_json, "[]", "{", ""] {
+            let error = DeviceInfo::validate_schema(json).unwrap_err();
+            assert!(matches!(error.kind(), ErrorKind::SerdeJson(_)));
+        }
+    }
+
+    #[test]
+    fn test_device_info_eq_ignoring_keys() {
+        let info = DeviceInfo::new(
+            "Test Device".to_string(),
+            TEST_KEY_A,
+            PathBuf::from("/tmp/test/private.key"),
+            TEST_UUID,
+        );
+        assert!(info.eq_ignoring_keys(&info.clone()));
+
+        // Only the key differs
+        let mut other = info.clone();
+        other.set_authorization_key(TEST_KEY_B);
+        assert_ne!(info, other);
+        assert!(info.eq_ignoring_keys(&other));
+        assert!(other.eq_ignoring_keys(&info));
+
+        // Any other field makes them unequal
+        let mut other = info.clone();
+        other.set_product_name("Other Device".to_string());
+        assert!(!info.eq_ignoring_keys(&other));
+        let mut other = info.clone();
+        other.set_private_key_file(PathBuf::from("/tmp/test/other.key"));
+        assert!(!info.eq_ignoring_keys(&other));
+        let mut other = info.clone();
+        other.regenerate_uuid(&SRNG::new()).unwrap();
+        other.set_authorization_key(TEST_KEY_B);
+        assert!(!info.eq_ignoring_keys(&other));
+    }
+
+    #[test]
+    fn test_device_info_regenerate_uuid() {
+        let srng = SRNG::new();
+        let mut device = DeviceInfo::new(
+            "Test Device".to_string(),
+            TEST_KEY_A,
+            PathBuf::from("/tmp/test/private.key"),
+            TEST_UUID,
+        );
+        let original = device.clone();
+
+        device.regenerate_uuid(&srng).unwrap();
+        assert_ne!(device.uuid(), original.uuid());
+        assert_eq!(device.uuid().get_version_num(), 7);
+        assert!(is_valid_device_uuid(device.uuid()));
+        assert!(device.created_at_unix_ms().is_some());
+
+        // Only the UUID changed
+        let mut restored = device.clone();
+        restored.set_uuid(*original.uuid());
+        assert_eq!(restored, original);
+
+        // Each call gives a new UUID
+        let previous = *device.uuid();
+        device.regenerate_uuid(&srng).unwrap();
+        assert_ne!(device.uuid(), &previous);
+    }
+
+    #[test]
+    fn test_device_info_clone() {
+        let sifis_home = SifisHome::new();
+        let info = sifis_home.new_info(String::from("Test device")).unwrap();
+        let mut copy = info.clone();
+        assert_eq!(copy, info);
+
+        // The copy is independent from the original
+        copy.set_product_name(String::from("Other device"));
+        assert_ne!(copy, info);
+        assert_eq!(info.product_name(), "Test device");
+    }
+
+    #[test]
+    fn test_resolved_private_key_file() {
+        let var = |name: &str| match name {
+            "SIFIS_HOME_PATH" => Some("/srv/sifis-home".to_string()),
+            "KEY_NAME" => Some("dht".to_string()),
+            _ => None,
+        };
+        let mut info = DeviceInfo::new(
+            "Test product".to_string(),
+            TEST_KEY_A,
+            PathBuf::from("${SIFIS_HOME_PATH}/keys/${KEY_NAME}.pem"),
+            TEST_UUID,
+        );
+        assert_eq!(
+            info.resolve_private_key_file_with(var).unwrap(),
+            PathBuf::from("/srv/sifis-home/keys/dht.pem")
+        );
+        // The stored value stays literal
+        assert_eq!(
+            info.private_key_file(),
+            Path::new("${SIFIS_HOME_PATH}/keys/${KEY_NAME}.pem")
+        );
+
+        // Paths without references are returned as is
+        info.set_private_key_file(PathBuf::from("/opt/sifis-home/private.pem"));
+        assert_eq!(
+            info.resolve_private_key_file_with(var).unwrap(),
+            PathBuf::from("/opt/sifis-home/private.pem")
+        );
+
+        // Undefined variables and unterminated references are errors
+        info.set_private_key_file(PathBuf::from("${UNDEFINED_DIR}/private.pem"));
+        let error = info.resolve_private_key_file_with(var).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::PathExpansionFailed(_)));
+        assert!(error.to_string().contains("`UNDEFINED_DIR`"));
+        info.set_private_key_file(PathBuf::from("${SIFIS_HOME_PATH/private.pem"));
+        assert!(info.resolve_private_key_file_with(var).is_err());
+    }
+
+    #[test]
+    fn test_config_from_env_with() {
+        let mut loaded = DeviceConfig::new(TEST_KEY_A, "File name".parse().unwrap());
+        loaded.set_timezone(Some("Europe/Rome".to_string()));
+        let key_b_base64 = TEST_KEY_B.to_base64();
+        let key_b_hex = TEST_KEY_B.hex(false);
+
+        // Nothing set keeps the loaded configuration, or the lack of it
+        let unset = |_: &str| None;
+        assert_eq!(
+            DeviceConfig::from_env_with(Some(&loaded), unset).unwrap(),
+            Some(loaded.clone())
+        );
+        assert_eq!(DeviceConfig::from_env_with(None, unset).unwrap(), None);
+
+        // Environment overrides the file, other settings are kept
+        let both = |name: &str| match name {
+            DEVICE_NAME_ENV => Some(" Env name ".to_string()),
+            DHT_KEY_ENV => Some(key_b_base64.clone()),
+            _ => None,
+        };
+        let config = DeviceConfig::from_env_with(Some(&loaded), both)
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.name(), "Env name");
+        assert_eq!(config.dht_shared_key(), &TEST_KEY_B);
+        assert_eq!(config.timezone(), Some("Europe/Rome"));
+        let config = DeviceConfig::from_env_with(None, both).unwrap().unwrap();
+        assert_eq!(
+            config,
+            DeviceConfig::new(TEST_KEY_B, "Env name".parse().unwrap())
+        );
+
+        // Only one override, and empty values are ignored
+        let key_only = |name: &str| match name {
+            DEVICE_NAME_ENV => Some("  ".to_string()),
+            DHT_KEY_ENV => Some(key_b_hex.clone()),
+            _ => None,
+        };
+        let config = DeviceConfig::from_env_with(Some(&loaded), key_only)
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.name(), "File name");
+        assert_eq!(config.dht_shared_key(), &TEST_KEY_B);
+        assert_eq!(DeviceConfig::from_env_with(None, key_only).unwrap(), None);
+
+        // Invalid values are errors
+        let bad_key = |name: &str| match name {
+            DHT_KEY_ENV => Some("not a key".to_string()),
+            _ => None,
+        };
+        assert!(DeviceConfig::from_env_with(Some(&loaded), bad_key).is_err());
+        let bad_name = |name: &str| match name {
+            DEVICE_NAME_ENV => Some("x".repeat(65)),
+            _ => None,
+        };
+        assert!(DeviceConfig::from_env_with(Some(&loaded), bad_name).is_err());
+    }
+
+    #[test]
+    fn test_config_from_env() {
+        let loaded = DeviceConfig::new(TEST_KEY_A, "File name".parse().unwrap());
+        env::set_var(DEVICE_NAME_ENV, "Container device");
+        env::set_var(DHT_KEY_ENV, TEST_KEY_B.hex(true));
+        let config = DeviceConfig::from_env(Some(&loaded));
+        env::remove_var(DEVICE_NAME_ENV);
+        env::remove_var(DHT_KEY_ENV);
+
+        let config = config.unwrap().unwrap();
+        assert_eq!(config.name(), "Container device");
+        assert_eq!(config.dht_shared_key(), &TEST_KEY_B);
+        assert_eq!(DeviceConfig::from_env(Some(&loaded)).unwrap(), Some(loaded));
+    }
+
+    #[test]
+    fn test_qr_payload_format() {
+        let info = DeviceInfo::new(
+            "Test product".to_string(),
+            TEST_KEY_A,
+            PathBuf::from("private.pem"),
+            TEST_UUID,
+        );
+        assert_eq!(
+            info.qr_payload_format(QrPayloadFormat::default()),
+            TEST_KEY_A.hex(true)
+        );
+        assert_eq!(
+            info.qr_payload_format(QrPayloadFormat::Uri),
+            format!("sifis://device/{TEST_UUID}?key={}", TEST_KEY_A.hex(false))
+        );
+        assert_eq!(
+            info.qr_payload_format(QrPayloadFormat::Base64),
+            "8OHSw7Sllod4aVpLPC0eDw8eLTxLWml4h5altMPS4fA="
+        );
+
+        // Every format is parsed back, but not as another format
+        for format in QrPayloadFormat::ALL {
+            let payload = info.qr_payload_format(format);
+            assert_eq!(format.parse_key(&payload).unwrap(), TEST_KEY_A, "{format}");
+            assert_eq!(format.name().parse::<QrPayloadFormat>().unwrap(), format);
+            for other in QrPayloadFormat::ALL {
+                if other != format {
+                    assert!(other.parse_key(&payload).is_err(), "{format} as {other}");
+                }
+            }
+        }
+        assert_eq!(
+            " URI ".parse::<QrPayloadFormat>().unwrap(),
+            QrPayloadFormat::Uri
+        );
+        assert!("svg".parse::<QrPayloadFormat>().is_err());
+
+        // Other query parameters are allowed, but the URI needs a valid UUID and a key
+        let uri = format!(
+            "sifis://device/{TEST_UUID}?v=1&key={}",
+            TEST_KEY_A.hex(true)
+        );
+        assert_eq!(QrPayloadFormat::Uri.parse_key(&uri).unwrap(), TEST_KEY_A);
+        for uri in [
+            format!("sifis://device/{TEST_UUID}"),
+            format!("sifis://device/{TEST_UUID}?key=00"),
+            format!("sifis://device/not-a-uuid?key={}", TEST_KEY_A.hex(false)),
+            format!("https://device/{TEST_UUID}?key={}", TEST_KEY_A.hex(false)),
+        ] {
+            assert!(QrPayloadFormat::Uri.parse_key(&uri).is_err(), "{uri}");
+        }
+    }
+
+    #[test]
+    fn test_provisioning_token() {
+        let info = DeviceInfo::new(
+            "Test product".to_string(),
+            TEST_KEY_A,
+            PathBuf::from("private.pem"),
+            TEST_UUID,
+        );
+        let token = info.provisioning_token();
+        assert_eq!(token.len(), 84 + 20);
+        assert!(token.split('-').all(|group| group.len() == 4));
+
+        // Round trip, also without dashes and in lower case
+        let expected = ProvisioningToken {
+            uuid: TEST_UUID,
+            authorization_key: TEST_KEY_A,
+        };
+        assert_eq!(
+            DeviceInfo::from_provisioning_token(&token).unwrap(),
+            expected
+        );
+        let relaxed = token.replace('-', " ").to_lowercase();
+        assert_eq!(relaxed.parse::<ProvisioningToken>().unwrap(), expected);
+
+        // Any single character typo is detected
+        let characters: Vec<char> = token.chars().filter(|&c| c != '-').collect();
+        for (index, &original) in characters.iter().enumerate() {
+            for &replacement in TOKEN_ALPHABET {
+                let replacement = replacement as char;
+                if replacement == original {
+                    continue;
+                }
+                let mut corrupted = characters.clone();
+                corrupted[index] = replacement;
+                let corrupted: String = corrupted.into_iter().collect();
+                assert!(
+                    DeviceInfo::from_provisioning_token(&corrupted).is_err(),
+                    "{corrupted}"
+                );
+            }
+        }
+
+        // Wrong length and characters
+        assert!(DeviceInfo::from_provisioning_token("").is_err());
+        assert!(DeviceInfo::from_provisioning_token(&token[..token.len() - 1]).is_err());
+        assert!(DeviceInfo::from_provisioning_token(&format!("{token}A")).is_err());
+        assert!(DeviceInfo::from_provisioning_token(&format!("0{}", &token[1..])).is_err());
     }
 
     #[test]
@@ -290,4 +2341,62 @@ mod tests {
         assert_eq!(info_b, info_c);
         assert_eq!(info_b, info_c);
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // File operations are not available with miri
+    fn test_device_info_field_names() {
+        let info = DeviceInfo::new(
+            "Test Device".to_string(),
+            TEST_KEY_A,
+            PathBuf::from("/opt/sifis-home/private.pem"),
+            TEST_UUID,
+        );
+        let json = serde_json::to_value(&info).unwrap();
+        let mut keys = json.as_object().unwrap().keys().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec!["authorizationKey", "privateKeyFile", "productName", "uuid"]
+        );
+
+        // Old snake_case and new camelCase documents give the same information
+        let key = TEST_KEY_A.hex(false);
+        let old_json = format!(
+            r#"{{"product_name":"Test Device","authorization_key":"{key}",
+                "private_key_file":"/opt/sifis-home/private.pem","uuid":"{TEST_UUID}"}}"#
+        );
+        let new_json = format!(
+            r#"{{"productName":"Test Device","authorizationKey":"{key}",
+                "privateKeyFile":"/opt/sifis-home/private.pem","uuid":"{TEST_UUID}"}}"#
+        );
+        assert_eq!(serde_json::from_str::<DeviceInfo>(&old_json).unwrap(), info);
+        assert_eq!(serde_json::from_str::<DeviceInfo>(&new_json).unwrap(), info);
+
+        // Old files load from disk in both formats
+        let test_dir = tempfile::tempdir().unwrap();
+        let file = test_dir.path().join("device.json");
+        fs::write(&file, &old_json).unwrap();
+        assert_eq!(DeviceInfo::load_from(&file).unwrap(), info);
+
+        // Old binaries wrote the keys and the UUID as bytes in MessagePack
+        #[derive(Serialize)]
+        struct OldDeviceInfo {
+            product_name: String,
+            authorization_key: SecurityKey,
+            private_key_file: PathBuf,
+            uuid: Uuid,
+        }
+        let old_info = OldDeviceInfo {
+            product_name: "Test Device".to_string(),
+            authorization_key: TEST_KEY_A,
+            private_key_file: PathBuf::from("/opt/sifis-home/private.pem"),
+            uuid: TEST_UUID,
+        };
+        let file = test_dir.path().join("device.msgpack");
+        fs::write(&file, rmp_serde::to_vec_named(&old_info).unwrap()).unwrap();
+        assert_eq!(
+            DeviceInfo::load_as(&file, ConfigFormat::MessagePack).unwrap(),
+            info
+        );
+    }
 }
@@ -1,5 +1,7 @@
 //! Error reporting
 
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 /// A type alias for `Result<T, mobile_api::error::Error>`
@@ -20,6 +22,84 @@ impl Error {
         Error(Box::new(kind))
     }
 
+    /// Convenience function for reporting an alphabet that codes can not be generated from
+    pub(crate) fn code_alphabet_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::CodeAlphabetInvalid(reason)))
+    }
+
+    /// Convenience function for reporting a bundle that could not be imported
+    pub(crate) fn bundle_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::BundleInvalid(reason)))
+    }
+
+    /// Convenience function for reporting unacceptable configuration values
+    pub(crate) fn config_value_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::ConfigValueInvalid(reason)))
+    }
+
+    /// Convenience function for reporting an unacceptable device name
+    pub(crate) fn device_name_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::DeviceNameInvalid(reason)))
+    }
+
+    /// Convenience function for reporting UUIDs not suitable for identifying the device
+    pub(crate) fn device_uuid_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::DeviceUuidInvalid(reason)))
+    }
+
+    /// Error for a device without configuration
+    ///
+    /// [ConfigStore](crate::store::ConfigStore) implementations return this from `load` when
+    /// no configuration is stored.
+    pub fn not_configured() -> Error {
+        Error(Box::new(ErrorKind::NotConfigured))
+    }
+
+    /// Convenience function for reporting a file format that is not supported
+    pub(crate) fn format_unsupported(format: String) -> Error {
+        Error(Box::new(ErrorKind::FormatUnsupported(format)))
+    }
+
+    /// Convenience function for reporting a SIFIS-Home path that can not be used
+    pub(crate) fn home_path_invalid(reason: String) -> Error {
+        Error(Box::new(ErrorKind::HomePathInvalid(reason)))
+    }
+
+    /// Convenience function for reporting a path that could not be expanded
+    pub(crate) fn path_expansion_failed(reason: String) -> Error {
+        Error(Box::new(ErrorKind::PathExpansionFailed(reason)))
+    }
+
+    /// Convenience function for reporting a private key that could not be read
+    pub(crate) fn private_key_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::PrivateKeyInvalid(reason)))
+    }
+
+    /// Convenience function for reporting a private key path that is not allowed
+    pub(crate) fn private_key_path_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::PrivateKeyPathInvalid(reason)))
+    }
+
+    /// Convenience function for reporting an unacceptable configuration profile name
+    pub(crate) fn profile_name_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::ProfileNameInvalid(reason)))
+    }
+
+    /// Convenience function for reporting a configuration profile that is not stored
+    pub(crate) fn profile_not_found(name: &str) -> Error {
+        Error(Box::new(ErrorKind::ProfileNotFound(name.to_string())))
+    }
+
+    /// Convenience function for reporting an unacceptable provisioning token
+    pub(crate) fn provisioning_token_invalid(reason: &'static str) -> Error {
+        Error(Box::new(ErrorKind::ProvisioningTokenInvalid(reason)))
+    }
+
+    /// Error for writing files when [SifisHome](crate::SifisHome) is read-only
+    pub fn read_only() -> Error {
+        Error(Box::new(ErrorKind::ReadOnly))
+    }
+
     /// Convenience function for reporting errors with SecurityKey
     pub(crate) fn security_key_wrong(reason: &'static str) -> Error {
         Error(Box::new(ErrorKind::SecurityKeyWrong(reason)))
@@ -40,12 +120,38 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self.0 {
             ErrorKind::Base64DecodeError(ref err) => err.fmt(f),
+            ErrorKind::BundleInvalid(reason) => reason.fmt(f),
+            ErrorKind::CborDecode(ref err) => err.fmt(f),
+            ErrorKind::CborEncode(ref err) => err.fmt(f),
+            ErrorKind::CodeAlphabetInvalid(reason) => reason.fmt(f),
+            ErrorKind::ConfigValueInvalid(reason) => reason.fmt(f),
+            ErrorKind::DeviceNameInvalid(reason) => reason.fmt(f),
+            ErrorKind::DeviceUuidInvalid(reason) => reason.fmt(f),
+            ErrorKind::FormatUnsupported(ref format) => {
+                write!(f, "unsupported file format: {format}")
+            }
+            ErrorKind::HomePathInvalid(ref reason) => reason.fmt(f),
             ErrorKind::IoError(ref err) => err.fmt(f),
+            ErrorKind::MessagePackDecode(ref err) => err.fmt(f),
+            ErrorKind::MessagePackEncode(ref err) => err.fmt(f),
+            ErrorKind::NotConfigured => "device is not configured yet".fmt(f),
             ErrorKind::NumParseIntError(ref err) => err.fmt(f),
+            ErrorKind::PathExpansionFailed(ref reason) => reason.fmt(f),
+            ErrorKind::PrivateKeyInvalid(reason) => reason.fmt(f),
+            ErrorKind::PrivateKeyPathInvalid(reason) => reason.fmt(f),
+            ErrorKind::ProfileNameInvalid(reason) => reason.fmt(f),
+            ErrorKind::ProfileNotFound(ref name) => {
+                write!(f, "configuration profile does not exist: {name}")
+            }
+            ErrorKind::ProvisioningTokenInvalid(reason) => reason.fmt(f),
+            ErrorKind::ReadOnly => "files are read-only".fmt(f),
             ErrorKind::RngError(ref err) => err.fmt(f),
             ErrorKind::SecurityKeyWrong(reason) => reason.fmt(f),
             ErrorKind::SerdeJson(ref err) => err.fmt(f),
             ErrorKind::TimeError(ref err) => err.fmt(f),
+            ErrorKind::TomlDecode(ref err) => err.fmt(f),
+            ErrorKind::TomlEncode(ref err) => err.fmt(f),
+            ErrorKind::UuidError(ref err) => err.fmt(f),
         }
     }
 }
@@ -55,10 +161,48 @@ impl fmt::Display for Error {
 pub enum ErrorKind {
     /// Base64 decode error
     Base64DecodeError(base64::DecodeError),
+    /// Exported device state bundle is damaged or was encrypted with another key
+    BundleInvalid(&'static str),
+    /// For CBOR deserialization errors
+    CborDecode(ciborium::de::Error<std::io::Error>),
+    /// For CBOR serialization errors
+    CborEncode(ciborium::ser::Error<std::io::Error>),
+    /// Alphabet for [SRNG::generate_code](crate::security::SRNG::generate_code) is not usable
+    CodeAlphabetInvalid(&'static str),
+    /// Configuration value, like the time zone, does not meet the requirements
+    ConfigValueInvalid(&'static str),
+    /// Device name does not meet the requirements
+    DeviceNameInvalid(&'static str),
+    /// UUID is not suitable for identifying the device
+    DeviceUuidInvalid(&'static str),
+    /// File format is not one of the [ConfigFormat](crate::configs::ConfigFormat) values
+    FormatUnsupported(String),
+    /// SIFIS-Home path is a symbolic link to a missing target
+    HomePathInvalid(String),
     /// Standard I/O errors
     IoError(std::io::Error),
+    /// For MessagePack deserialization errors
+    MessagePackDecode(rmp_serde::decode::Error),
+    /// For MessagePack serialization errors
+    MessagePackEncode(rmp_serde::encode::Error),
+    /// Device configuration is not stored
+    NotConfigured,
     /// Error while parsing integer value from str
     NumParseIntError(std::num::ParseIntError),
+    /// Environment variables in a path could not be expanded
+    PathExpansionFailed(String),
+    /// Private key file is not an Ed25519 key in PKCS#8 PEM format
+    PrivateKeyInvalid(&'static str),
+    /// Private key file is outside the allowed directories
+    PrivateKeyPathInvalid(&'static str),
+    /// Configuration profile name does not meet the requirements
+    ProfileNameInvalid(&'static str),
+    /// Configuration profile with the name is not stored
+    ProfileNotFound(String),
+    /// Provisioning token could not be decoded
+    ProvisioningTokenInvalid(&'static str),
+    /// Files were not written because they are configured read-only
+    ReadOnly,
     /// Unspecified error from the ring crate
     RngError(ring::error::Unspecified),
     /// Error when converting string to SecurityKey
@@ -67,6 +211,12 @@ pub enum ErrorKind {
     SerdeJson(serde_json::Error),
     /// Error with the time
     TimeError(std::time::SystemTimeError),
+    /// For TOML deserialization errors
+    TomlDecode(toml::de::Error),
+    /// For TOML serialization errors
+    TomlEncode(toml::ser::Error),
+    /// Error while parsing UUID from str
+    UuidError(uuid::Error),
 }
 
 impl From<base64::DecodeError> for Error {
@@ -75,12 +225,36 @@ impl From<base64::DecodeError> for Error {
     }
 }
 
+impl From<ciborium::de::Error<std::io::Error>> for Error {
+    fn from(err: ciborium::de::Error<std::io::Error>) -> Self {
+        Error::new(ErrorKind::CborDecode(err))
+    }
+}
+
+impl From<ciborium::ser::Error<std::io::Error>> for Error {
+    fn from(err: ciborium::ser::Error<std::io::Error>) -> Self {
+        Error::new(ErrorKind::CborEncode(err))
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Error::new(ErrorKind::IoError(err))
     }
 }
 
+impl From<rmp_serde::decode::Error> for Error {
+    fn from(err: rmp_serde::decode::Error) -> Self {
+        Error::new(ErrorKind::MessagePackDecode(err))
+    }
+}
+
+impl From<rmp_serde::encode::Error> for Error {
+    fn from(err: rmp_serde::encode::Error) -> Self {
+        Error::new(ErrorKind::MessagePackEncode(err))
+    }
+}
+
 impl From<std::num::ParseIntError> for Error {
     fn from(err: std::num::ParseIntError) -> Self {
         Error::new(ErrorKind::NumParseIntError(err))
@@ -105,16 +279,166 @@ impl From<std::time::SystemTimeError> for Error {
     }
 }
 
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::new(ErrorKind::TomlDecode(err))
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(err: toml::ser::Error) -> Self {
+        Error::new(ErrorKind::TomlEncode(err))
+    }
+}
+
+impl From<uuid::Error> for Error {
+    fn from(err: uuid::Error) -> Self {
+        Error::new(ErrorKind::UuidError(err))
+    }
+}
+
 impl std::error::Error for Error {}
 
+/// Serializable representation of an [Error]
+///
+/// Used as a shared error payload between the server endpoints and their clients.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct ErrorBody {
+    /// Name of the [ErrorKind] variant, for example `DeviceNameInvalid`
+    pub kind: String,
+    /// Human readable error message
+    pub message: String,
+}
+
+impl From<&Error> for ErrorBody {
+    fn from(err: &Error) -> Self {
+        let kind = match err.kind() {
+            ErrorKind::Base64DecodeError(_) => "Base64DecodeError",
+            ErrorKind::BundleInvalid(_) => "BundleInvalid",
+            ErrorKind::CborDecode(_) => "CborDecode",
+            ErrorKind::CborEncode(_) => "CborEncode",
+            ErrorKind::CodeAlphabetInvalid(_) => "CodeAlphabetInvalid",
+            ErrorKind::ConfigValueInvalid(_) => "ConfigValueInvalid",
+            ErrorKind::DeviceNameInvalid(_) => "DeviceNameInvalid",
+            ErrorKind::DeviceUuidInvalid(_) => "DeviceUuidInvalid",
+            ErrorKind::FormatUnsupported(_) => "FormatUnsupported",
+            ErrorKind::HomePathInvalid(_) => "HomePathInvalid",
+            ErrorKind::IoError(_) => "IoError",
+            ErrorKind::MessagePackDecode(_) => "MessagePackDecode",
+            ErrorKind::MessagePackEncode(_) => "MessagePackEncode",
+            ErrorKind::NotConfigured => "NotConfigured",
+            ErrorKind::NumParseIntError(_) => "NumParseIntError",
+            ErrorKind::PathExpansionFailed(_) => "PathExpansionFailed",
+            ErrorKind::PrivateKeyInvalid(_) => "PrivateKeyInvalid",
+            ErrorKind::PrivateKeyPathInvalid(_) => "PrivateKeyPathInvalid",
+            ErrorKind::ProfileNameInvalid(_) => "ProfileNameInvalid",
+            ErrorKind::ProfileNotFound(_) => "ProfileNotFound",
+            ErrorKind::ProvisioningTokenInvalid(_) => "ProvisioningTokenInvalid",
+            ErrorKind::ReadOnly => "ReadOnly",
+            ErrorKind::RngError(_) => "RngError",
+            ErrorKind::SecurityKeyWrong(_) => "SecurityKeyWrong",
+            ErrorKind::SerdeJson(_) => "SerdeJson",
+            ErrorKind::TimeError(_) => "TimeError",
+            ErrorKind::TomlDecode(_) => "TomlDecode",
+            ErrorKind::TomlEncode(_) => "TomlEncode",
+            ErrorKind::UuidError(_) => "UuidError",
+        };
+        ErrorBody {
+            kind: kind.to_string(),
+            message: err.to_string(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::security::SecurityKey;
 
+    #[test]
+    fn test_device_name_invalid_error() {
+        let name_error = crate::configs::validate_device_name("").err().unwrap();
+        let name_error_debug = format!("{:?}", name_error);
+        let name_error_display = format!("{}", name_error);
+        assert_eq!(
+            name_error_debug,
+            "Error(DeviceNameInvalid(\"device name is empty\"))"
+        );
+        assert_eq!(name_error_display, "device name is empty");
+        assert!(matches!(name_error.kind(), ErrorKind::DeviceNameInvalid(_)));
+        assert!(matches!(
+            name_error.into_kind(),
+            ErrorKind::DeviceNameInvalid(_)
+        ));
+    }
+
+    #[test]
+    fn test_error_body() {
+        let name_error = crate::configs::validate_device_name("").err().unwrap();
+        let body = ErrorBody::from(&name_error);
+        assert_eq!(body.kind, "DeviceNameInvalid");
+        assert_eq!(body.message, "device name is empty");
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            r#"{"kind":"DeviceNameInvalid","message":"device name is empty"}"#
+        );
+
+        let key_error = SecurityKey::from_hex("_").err().unwrap();
+        let body = ErrorBody::from(&key_error);
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            r#"{"kind":"SecurityKeyWrong","message":"key data length is incorrect"}"#
+        );
+
+        let io_error = Error::from(std::io::Error::other("example error"));
+        let body = ErrorBody::from(&io_error);
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            r#"{"kind":"IoError","message":"example error"}"#
+        );
+
+        let parse_error = Error::from("x".parse::<u8>().err().unwrap());
+        let json = serde_json::to_string(&ErrorBody::from(&parse_error)).unwrap();
+        let body: ErrorBody = serde_json::from_str(&json).unwrap();
+        assert_eq!(body.kind, "NumParseIntError");
+        assert_eq!(body.message, "invalid digit found in string");
+    }
+
+    #[test]
+    fn test_device_uuid_invalid_error() {
+        let uuid_error = crate::configs::QrPayloadFormat::Uri
+            .parse_key("sifis://device/not-a-uuid?key=00")
+            .err()
+            .unwrap();
+        let uuid_error_debug = format!("{:?}", uuid_error);
+        let uuid_error_display = format!("{}", uuid_error);
+        assert_eq!(
+            uuid_error_debug,
+            "Error(DeviceUuidInvalid(\"the sifis:// URI has an invalid UUID\"))"
+        );
+        assert_eq!(uuid_error_display, "the sifis:// URI has an invalid UUID");
+        assert!(matches!(uuid_error.kind(), ErrorKind::DeviceUuidInvalid(_)));
+        assert!(matches!(
+            uuid_error.into_kind(),
+            ErrorKind::DeviceUuidInvalid(_)
+        ));
+    }
+
+    #[test]
+    fn test_not_configured_error() {
+        let error = Error::not_configured();
+        assert_eq!(format!("{:?}", error), "Error(NotConfigured)");
+        assert_eq!(format!("{}", error), "device is not configured yet");
+        assert!(matches!(error.kind(), ErrorKind::NotConfigured));
+        assert_eq!(
+            serde_json::to_string(&ErrorBody::from(&error)).unwrap(),
+            r#"{"kind":"NotConfigured","message":"device is not configured yet"}"#
+        );
+    }
+
     #[test]
     fn test_io_error() {
-        let io_error_source = std::io::Error::new(std::io::ErrorKind::Other, "example error");
+        let io_error_source = std::io::Error::other("example error");
         let io_error = Error::from(io_error_source);
         let io_error_debug = format!("{:?}", io_error);
         let io_error_display = format!("{}", io_error);
@@ -211,4 +535,18 @@ mod tests {
         assert!(matches!(time_error.kind(), ErrorKind::TimeError(_)));
         assert!(matches!(time_error.into_kind(), ErrorKind::TimeError(_)));
     }
+
+    #[test]
+    fn test_uuid_error() {
+        let uuid_error_source = uuid::Uuid::parse_str("x").err().unwrap();
+        let expected_debug = format!("Error(UuidError({:?}))", uuid_error_source);
+        let expected_display = format!("{}", uuid_error_source);
+        let uuid_error = Error::from(uuid_error_source);
+        let uuid_error_debug = format!("{:?}", uuid_error);
+        let uuid_error_display = format!("{}", uuid_error);
+        assert_eq!(uuid_error_debug, expected_debug);
+        assert_eq!(uuid_error_display, expected_display);
+        assert!(matches!(uuid_error.kind(), ErrorKind::UuidError(_)));
+        assert!(matches!(uuid_error.into_kind(), ErrorKind::UuidError(_)));
+    }
 }
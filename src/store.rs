@@ -0,0 +1,189 @@
+//! Device Configuration Storage
+//!
+//! The [ConfigStore] trait hides where the device configuration is kept. The default
+//! [FileConfigStore] stores it in the `config.json` file, but deployments can provide their own
+//! implementation to keep the configuration, for example, in a key-value store or on tmpfs.
+
+use crate::configs::DeviceConfig;
+use crate::error::{Error, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Persistence backend for the device configuration
+///
+/// Implementations must be thread-safe because the server shares the store between requests.
+pub trait ConfigStore: Send + Sync {
+    /// Load the stored configuration
+    ///
+    /// Returns [ErrorKind::NotConfigured](crate::error::ErrorKind::NotConfigured) if the
+    /// configuration is not stored, and other errors if it could not be read.
+    fn load(&self) -> Result<DeviceConfig>;
+
+    /// Store the configuration, replacing any previous one
+    fn save(&self, config: &DeviceConfig) -> Result<()>;
+
+    /// Remove the stored configuration
+    ///
+    /// Returns Ok if the configuration is removed or was not stored in the first place.
+    fn remove(&self) -> Result<()>;
+}
+
+/// Configuration store using a JSON file
+///
+/// This is the default store. [SifisHome](crate::SifisHome) creates one for the `config.json`
+/// file with the [config_store()](crate::SifisHome::config_store) method.
+#[derive(Clone, Debug)]
+pub struct FileConfigStore {
+    /// Path to the configuration file
+    file: PathBuf,
+
+    /// Is the file written as pretty JSON
+    pretty_json: bool,
+
+    /// Are saving and removing refused
+    read_only: bool,
+}
+
+impl FileConfigStore {
+    /// Create a store for the given configuration *file*
+    ///
+    /// The file is written as pretty JSON by default.
+    pub fn new(file: PathBuf) -> FileConfigStore {
+        FileConfigStore {
+            file,
+            pretty_json: true,
+            read_only: false,
+        }
+    }
+
+    /// Path to the configuration file
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// Is the file written as pretty JSON
+    pub fn pretty_json(&self) -> bool {
+        self.pretty_json
+    }
+
+    /// Choose between pretty and compact JSON for writing the file
+    pub fn set_pretty_json(&mut self, pretty_json: bool) {
+        self.pretty_json = pretty_json;
+    }
+
+    /// Are saving and removing refused
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Refuse saving and removing the file with [ErrorKind::ReadOnly](crate::error::ErrorKind)
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+}
+
+impl ConfigStore for FileConfigStore {
+    fn load(&self) -> Result<DeviceConfig> {
+        // Missing file just means that the device is not configured
+        if !self.file.try_exists()? {
+            return Err(Error::not_configured());
+        }
+        DeviceConfig::load_from(&self.file)
+    }
+
+    fn save(&self, config: &DeviceConfig) -> Result<()> {
+        if self.read_only {
+            return Err(Error::read_only());
+        }
+        config.save_to_with(&self.file, self.pretty_json)
+    }
+
+    fn remove(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::read_only());
+        }
+        match fs::remove_file(&self.file) {
+            Ok(_) => Ok(()),
+            Err(err) => match err.kind() {
+                ErrorKind::NotFound => Ok(()), // This is acceptable
+                _ => Err(err.into()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind::NotConfigured;
+    use crate::security::SecurityKey;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    const TEST_KEY: SecurityKey = SecurityKey::from_bytes([
+        0xf0, 0xe1, 0xd2, 0xc3, 0xb4, 0xa5, 0x96, 0x87, 0x78, 0x69, 0x5a, 0x4b, 0x3c, 0x2d, 0x1e,
+        0x0f, 0x0f, 0x1e, 0x2d, 0x3c, 0x4b, 0x5a, 0x69, 0x78, 0x87, 0x96, 0xa5, 0xb4, 0xc3, 0xd2,
+        0xe1, 0xf0,
+    ]);
+
+    /// Store keeping the configuration in memory
+    #[derive(Default)]
+    struct MemoryConfigStore(Mutex<Option<DeviceConfig>>);
+
+    impl ConfigStore for MemoryConfigStore {
+        fn load(&self) -> Result<DeviceConfig> {
+            match self.0.lock().unwrap().as_ref() {
+                Some(config) => Ok(config.clone()),
+                None => Err(Error::not_configured()),
+            }
+        }
+
+        fn save(&self, config: &DeviceConfig) -> Result<()> {
+            *self.0.lock().unwrap() = Some(config.clone());
+            Ok(())
+        }
+
+        fn remove(&self) -> Result<()> {
+            *self.0.lock().unwrap() = None;
+            Ok(())
+        }
+    }
+
+    /// Runs the same checks for any store implementation
+    fn check_store(store: &dyn ConfigStore) {
+        let config = DeviceConfig::new(TEST_KEY, "Test".parse().unwrap());
+        let is_not_configured =
+            |result: Result<DeviceConfig>| matches!(result.unwrap_err().kind(), NotConfigured);
+        assert!(is_not_configured(store.load()));
+        store.save(&config).unwrap();
+        assert_eq!(store.load().unwrap(), config);
+        store.remove().unwrap();
+        assert!(is_not_configured(store.load()));
+        assert!(store.remove().is_ok()); // Removing twice should be okay
+    }
+
+    #[test]
+    fn test_memory_config_store() {
+        check_store(&MemoryConfigStore::default());
+    }
+
+    #[cfg_attr(miri, ignore)] // File operations are not available with miri
+    #[test]
+    fn test_file_config_store() {
+        let test_dir = TempDir::new().unwrap();
+        let mut file = PathBuf::from(test_dir.path());
+        file.push("config.json");
+        let store = FileConfigStore::new(file.clone());
+        assert_eq!(store.file(), file);
+        check_store(&store);
+        assert!(!file.exists());
+
+        // Other errors are still reported
+        fs::write(&file, "{").unwrap();
+        assert!(matches!(
+            store.load().unwrap_err().kind(),
+            crate::error::ErrorKind::SerdeJson(_)
+        ));
+    }
+}
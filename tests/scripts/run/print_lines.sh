@@ -0,0 +1,6 @@
+#!/bin/bash
+
+# Unit test is checking that the lines are received in order
+echo "first line"
+echo "second line"
+echo "third line"
@@ -1,6 +1,6 @@
 use assert_cmd::prelude::*;
 use image::DynamicImage;
-use mobile_api::configs::DeviceInfo;
+use mobile_api::configs::{DeviceInfo, QrPayloadFormat};
 use mobile_api::security::SecurityKey;
 use predicates::prelude::*;
 use resvg::usvg::TreeParsing;
@@ -106,6 +106,26 @@ fn test_forcing_overwrite() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+#[cfg_attr(miri, ignore)] // File operations not available for miri when isolation is enabled
+fn test_product_name_of_subcommand() -> Result<(), Box<dyn Error>> {
+    // After `--`, the name of a subcommand is the product name
+    let tmp_dir = TempDir::new()?;
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command
+        .arg("--output-path")
+        .arg(tmp_dir.path())
+        .arg("--")
+        .arg("info");
+    command.assert().success().stdout(predicate::str::contains(
+        "A new device information file was written to:",
+    ));
+    let device_info = DeviceInfo::load_from(&tmp_dir.path().join("device.json"))?;
+    assert_eq!(device_info.product_name(), "info");
+
+    Ok(())
+}
+
 #[test]
 #[cfg_attr(miri, ignore)] // File operations not available for miri when isolation is enabled
 fn test_private_key() -> Result<(), Box<dyn Error>> {
@@ -178,6 +198,243 @@ fn test_authorization_key_in_qrcode() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[test]
+#[cfg_attr(miri, ignore)] // File operations not available for miri when isolation is enabled
+fn test_qr_code_formats() -> Result<(), Box<dyn Error>> {
+    for format in QrPayloadFormat::ALL {
+        let tmp_dir = TempDir::new()?;
+        let mut svg_file = PathBuf::from(tmp_dir.path());
+        svg_file.push("code.svg");
+        let mut command = Command::cargo_bin(APP_NAME)?;
+        command
+            .arg("--save-qr-code-svg")
+            .arg(&svg_file)
+            .arg("--qr-format")
+            .arg(format.name())
+            .arg("--output-path")
+            .arg(tmp_dir.path())
+            .arg("Test device");
+        command.assert().success();
+
+        // The payload is in the chosen format and contains the key of the device
+        let luma_image = svg_to_dynamic_image(&svg_file)?.into_luma8();
+        let mut prepared_image = rqrr::PreparedImage::prepare(luma_image);
+        let grids = prepared_image.detect_grids();
+        assert_eq!(grids.len(), 1);
+        let (_, payload) = grids[0].decode()?;
+        let mut device_info_file = PathBuf::from(tmp_dir.path());
+        device_info_file.push("device.json");
+        let device_info = DeviceInfo::load_from(&device_info_file).unwrap();
+        assert_eq!(payload, device_info.qr_payload_format(format));
+        assert_eq!(
+            &format.parse_key(&payload).unwrap(),
+            device_info.authorization_key()
+        );
+    }
+
+    // Unknown formats are rejected
+    let tmp_dir = TempDir::new()?;
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command
+        .arg("--qr-format")
+        .arg("svg")
+        .arg("--output-path")
+        .arg(tmp_dir.path())
+        .arg("Test device");
+    command.assert().failure();
+
+    // The format is only used with the QR code image
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command
+        .arg("--qr-format")
+        .arg("uri")
+        .arg("--output-path")
+        .arg(tmp_dir.path())
+        .arg("Test device");
+    command.assert().failure();
+    assert!(!tmp_dir.path().join("device.json").exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)] // File operations not available for miri when isolation is enabled
+fn test_info_subcommand() -> Result<(), Box<dyn Error>> {
+    // Creating device.json to inspect
+    let tmp_dir = TempDir::new()?;
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command
+        .arg("--output-path")
+        .arg(tmp_dir.path())
+        .arg("Info device");
+    command.assert().success();
+    let mut device_info_file = PathBuf::from(tmp_dir.path());
+    device_info_file.push("device.json");
+    let device_info = DeviceInfo::load_from(&device_info_file).unwrap();
+    let key_hex = device_info.authorization_key().hex(false);
+    let fingerprint = device_info.authorization_key().fingerprint();
+
+    // Key should be redacted by default
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("info").arg(&device_info_file);
+    command
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Product name:      Info device"))
+        .stdout(predicate::str::contains(device_info.uuid().to_string()))
+        .stdout(predicate::str::contains(fingerprint.as_str()))
+        .stdout(predicate::str::contains("redacted"))
+        .stdout(predicate::str::contains("UTC"))
+        .stdout(predicate::str::contains(key_hex.as_str()).not());
+
+    // The --show-key option reveals the key
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("info").arg("--show-key").arg(&device_info_file);
+    command
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(key_hex.as_str()))
+        .stdout(predicate::str::contains("redacted").not());
+
+    // JSON output without and with the key
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    let output = command
+        .arg("info")
+        .arg("--json")
+        .arg(&device_info_file)
+        .output()?;
+    assert!(output.status.success());
+    let summary = serde_json::from_slice::<serde_json::Value>(&output.stdout)?;
+    assert_eq!(summary["product_name"], "Info device");
+    assert_eq!(summary["uuid"], device_info.uuid().to_string());
+    assert_eq!(summary["key_fingerprint"], fingerprint);
+    assert_eq!(
+        summary["created_at"],
+        device_info.created_at_unix_ms().unwrap()
+    );
+    assert!(summary.get("authorization_key").is_none());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains(&key_hex));
+
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    let output = command
+        .arg("info")
+        .arg("--json")
+        .arg("--show-key")
+        .arg(&device_info_file)
+        .output()?;
+    let summary = serde_json::from_slice::<serde_json::Value>(&output.stdout)?;
+    assert_eq!(summary["authorization_key"], key_hex);
+
+    // Missing file should give an error
+    let mut missing_file = PathBuf::from(tmp_dir.path());
+    missing_file.push("missing.json");
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("info").arg(&missing_file);
+    command.assert().failure().stderr(predicate::str::contains(
+        "Could not load device information",
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)] // File operations not available for miri when isolation is enabled
+fn test_rotate_uuid_subcommand() -> Result<(), Box<dyn Error>> {
+    // Creating device.json to change
+    let tmp_dir = TempDir::new()?;
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command
+        .arg("--output-path")
+        .arg(tmp_dir.path())
+        .arg("Rotated device");
+    command.assert().success();
+    let mut device_info_file = PathBuf::from(tmp_dir.path());
+    device_info_file.push("device.json");
+    let original = DeviceInfo::load_from(&device_info_file).unwrap();
+
+    // Only the UUID should change
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("rotate-uuid").arg(&device_info_file);
+    command
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(original.uuid().to_string()));
+    let mut rotated = DeviceInfo::load_from(&device_info_file).unwrap();
+    assert_ne!(rotated.uuid(), original.uuid());
+    assert_eq!(rotated.uuid().get_version_num(), 7);
+    rotated.set_uuid(*original.uuid());
+    assert_eq!(rotated, original);
+
+    // Missing file should give an error
+    let mut missing_file = PathBuf::from(tmp_dir.path());
+    missing_file.push("missing.json");
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("rotate-uuid").arg(&missing_file);
+    command.assert().failure().stderr(predicate::str::contains(
+        "Could not load device information",
+    ));
+
+    Ok(())
+}
+
+#[test]
+#[cfg_attr(miri, ignore)] // File operations not available for miri when isolation is enabled
+fn test_verify_subcommand() -> Result<(), Box<dyn Error>> {
+    // Freshly created file is up to date
+    let tmp_dir = TempDir::new()?;
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command
+        .arg("--output-path")
+        .arg(tmp_dir.path())
+        .arg("Verified device");
+    command.assert().success();
+    let mut device_info_file = PathBuf::from(tmp_dir.path());
+    device_info_file.push("device.json");
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("verify").arg(&device_info_file);
+    command
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("up to date"))
+        .stdout(predicate::str::contains("Warning").not());
+
+    // Outdated file gives warnings but still passes
+    let mut outdated_file = PathBuf::from(tmp_dir.path());
+    outdated_file.push("outdated.json");
+    let json = fs::read_to_string(&device_info_file)?;
+    let outdated_json = json.replacen('{', "{\n  \"qr_code_version\": 1,", 1);
+    fs::write(&outdated_file, outdated_json)?;
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("verify").arg(&outdated_file);
+    command
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Warning: unknown field `qr_code_version`",
+        ))
+        .stdout(predicate::str::contains("1 warning(s)"));
+
+    // Broken and missing files fail
+    let mut broken_file = PathBuf::from(tmp_dir.path());
+    broken_file.push("broken.json");
+    fs::write(&broken_file, r#"{"product_name":"Broken device"}"#)?;
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("verify").arg(&broken_file);
+    command
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Device information is not valid"));
+    let mut missing_file = PathBuf::from(tmp_dir.path());
+    missing_file.push("missing.json");
+    let mut command = Command::cargo_bin(APP_NAME)?;
+    command.arg("verify").arg(&missing_file);
+    command.assert().failure().stderr(predicate::str::contains(
+        "Could not read device information",
+    ));
+
+    Ok(())
+}
+
 fn svg_to_dynamic_image(file: &Path) -> Result<DynamicImage, Box<dyn Error>> {
     // Rendering SVG to pixmap
     let svg_options = usvg::Options {
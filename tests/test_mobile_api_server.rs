@@ -41,7 +41,7 @@ async fn test_server_binary() -> Result<(), Box<dyn Error>> {
         .failure()
         // Should tell that device.json not found
         .stderr(
-            predicate::str::is_match("^Device information file.*device\\.json.*not found\\.")
+            predicate::str::is_match("ERROR.*Device information file.*device\\.json.*not found\\.")
                 .unwrap(),
         )
         // Should tell about create_device_info app
@@ -61,6 +61,79 @@ async fn test_server_binary() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Test ignored for miri, because file operations are not available when isolation is enabled.
+#[cfg_attr(miri, ignore)]
+#[test]
+fn test_dump_schemas() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new()?;
+    let mut schema_dir = PathBuf::from(tmp_dir.path());
+    schema_dir.push("schemas");
+
+    let mut command = Command::cargo_bin(SERVER_NAME)?;
+    command
+        .env("SIFIS_HOME_PATH", tmp_dir.path())
+        .arg("--dump-schemas")
+        .arg(&schema_dir);
+    command
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("JSON Schemas were written to:"));
+
+    for (title, property) in [
+        ("DeviceConfig", "dhtSharedKey"),
+        ("DeviceInfo", "authorizationKey"),
+        ("DeviceStatus", "cpuUsage"),
+    ] {
+        let file = schema_dir.join(format!("{title}.schema.json"));
+        let schema: serde_json::Value = serde_json::from_slice(&std::fs::read(&file)?)?;
+        assert!(schema["$schema"].is_string(), "{title}");
+        assert_eq!(schema["title"], title);
+        assert_eq!(schema["type"], "object", "{title}");
+        assert!(schema["properties"][property].is_object(), "{title}");
+    }
+    Ok(())
+}
+
+// Test ignored for miri, because file operations are not available when isolation is enabled.
+#[cfg_attr(miri, ignore)]
+#[test]
+fn test_dump_openapi() -> Result<(), Box<dyn Error>> {
+    let tmp_dir = TempDir::new()?;
+    let mut spec_file = PathBuf::from(tmp_dir.path());
+    spec_file.push("openapi.json");
+
+    // Device information is not needed for writing the specification
+    let mut command = Command::cargo_bin(SERVER_NAME)?;
+    command
+        .env("SIFIS_HOME_PATH", tmp_dir.path())
+        .arg("--dump-openapi")
+        .arg(&spec_file);
+    command.assert().success().stderr(predicate::str::contains(
+        "OpenAPI specification was written to:",
+    ));
+
+    let spec: serde_json::Value = serde_json::from_slice(&std::fs::read(&spec_file)?)?;
+    assert!(spec["openapi"].is_string());
+    let paths = spec["paths"].as_object().unwrap();
+    for path in [
+        "/device/info",
+        "/device/status",
+        "/device/configuration",
+        "/provision",
+    ] {
+        assert!(paths.contains_key(path), "missing path {}", path);
+    }
+    assert_eq!(
+        paths.contains_key("/command/restart"),
+        cfg!(feature = "commands")
+    );
+    assert_eq!(
+        spec["components"]["securitySchemes"]["ApiKeyAuth"]["name"],
+        "x-api-key"
+    );
+    Ok(())
+}
+
 async fn test_graceful_shutdown() -> Result<(), Box<dyn Error>> {
     // Running with valgrind?
     if let Ok(value) = std::env::var("LD_PRELOAD") {
@@ -105,10 +178,19 @@ async fn test_graceful_shutdown() -> Result<(), Box<dyn Error>> {
     loop {
         tokio::select! {
             result = stdout_reader.next_line() => {
+                match result {
+                    Ok(Some(line)) => println!("stdout: {}", line),
+                    Err(_) => panic!("Stdout reader error"),
+                    _ => (),
+                }
+            }
+            result = stderr_reader.next_line() => {
                 match result {
                     Ok(Some(line)) => {
-                        println!("stdout: {}", line);
-                        if line.contains("Rocket has launched from") {
+                        println!("stderr: {}", line);
+                        if line.contains("ERROR") {
+                            panic!("Unexpected error from the server: {}", line);
+                        } else if line.contains("Rocket has launched from") {
                             // Server started successfully, start sending SIGTERM for it
                             let rx = sigterm_tx.subscribe();
                             sigterm_thread_handle = Some(thread::spawn(move || {
@@ -119,13 +201,6 @@ async fn test_graceful_shutdown() -> Result<(), Box<dyn Error>> {
                             break;
                         }
                     },
-                    Err(_) => panic!("Stdout reader error"),
-                    _ => (),
-                }
-            }
-            result = stderr_reader.next_line() => {
-                match result {
-                    Ok(Some(line)) => panic!("Unexpected stderr output: {}", line),
                     Err(_) => panic!("Stderr reader error"),
                     _ => (),
                 }